@@ -0,0 +1,166 @@
+//! Runs an application protocol on top of the overlay: two nodes connected
+//! over an in-memory network ask each other to echo a greeting.
+//!
+//! ```sh
+//! cargo run --example echo
+//! ```
+use {
+  c2n::{
+    behaviour::{Behaviour, BehaviourAction},
+    clock::wall::WallClock,
+    network::{
+      memory::{MemoryHub, MemoryNetwork},
+      ProtocolId,
+      RequestId,
+    },
+    node::Node,
+    node_config::NodeConfigBuilder,
+    node_events::NodeEvent,
+    peer_list_manager::simple::SimplePeerListManager,
+    storage::sim::SimStorage,
+    types::{Keypair, NodeAddress, PeerId},
+  },
+  futures::{
+    executor::block_on,
+    future::{select, Either},
+  },
+  rand::{rngs::StdRng, SeedableRng},
+  std::{
+    collections::VecDeque,
+    task::{Context, Poll, Waker},
+  },
+};
+
+const ECHO: ProtocolId = 1;
+
+/// A greeting a peer sent back.
+#[derive(Debug)]
+struct Echoed {
+  peer_id: PeerId,
+  payload: Vec<u8>,
+}
+
+/// Answers every request with its own payload, and asks every peer it
+/// connects to to echo a greeting.
+#[derive(Default)]
+struct Echo {
+  actions: VecDeque<BehaviourAction<Echoed>>,
+  waker: Option<Waker>,
+}
+
+impl Echo {
+  fn push(&mut self, action: BehaviourAction<Echoed>) {
+    self.actions.push_back(action);
+    if let Some(waker) = self.waker.take() {
+      waker.wake();
+    }
+  }
+}
+
+impl Behaviour for Echo {
+  type Event = Echoed;
+
+  fn supports(&self, protocol: ProtocolId) -> bool {
+    protocol == ECHO
+  }
+
+  fn on_peer_connected(&mut self, peer_id: PeerId) {
+    self.push(BehaviourAction::Request {
+      peer_id,
+      protocol: ECHO,
+      payload: b"hello".to_vec(),
+    });
+  }
+
+  fn on_peer_disconnected(&mut self, _: PeerId) {}
+
+  fn on_message(&mut self, _: PeerId, _: ProtocolId, _: Vec<u8>) {}
+
+  fn on_request(
+    &mut self,
+    peer_id: PeerId,
+    protocol: ProtocolId,
+    id: RequestId,
+    payload: Vec<u8>,
+  ) {
+    self.push(BehaviourAction::Respond {
+      peer_id,
+      protocol,
+      id,
+      payload,
+    });
+  }
+
+  fn on_response(
+    &mut self,
+    peer_id: PeerId,
+    _: ProtocolId,
+    _: Vec<u8>,
+    payload: Vec<u8>,
+  ) {
+    self.push(BehaviourAction::Event(Echoed { peer_id, payload }));
+  }
+
+  fn poll(&mut self, cx: &mut Context<'_>) -> Poll<BehaviourAction<Echoed>> {
+    match self.actions.pop_front() {
+      Some(action) => Poll::Ready(action),
+      None => {
+        self.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+}
+
+fn main() {
+  tracing_subscriber::fmt::init();
+
+  let hub = MemoryHub::new();
+  let clock = WallClock::new();
+  let mut rng = StdRng::from_entropy();
+
+  let mut node = |port: u64, bootnode: Option<NodeAddress>| {
+    let keypair = Keypair::generate(&mut rng);
+    let address: NodeAddress = (
+      *keypair.public(),
+      format!("/memory/{port}").parse().unwrap(),
+    );
+    let mut config = NodeConfigBuilder::new()
+      .with_identity(keypair)
+      .with_address(address.1.clone())
+      .with_min_peers(1);
+    if let Some(bootnode) = bootnode {
+      config = config.with_bootnode(bootnode);
+    }
+
+    let node = Node::builder()
+      .network(MemoryNetwork::new(&hub, address.clone()))
+      .storage(SimStorage::build(StdRng::from_entropy()))
+      .peer_list_manager(SimplePeerListManager::build(
+        StdRng::from_entropy(),
+        clock,
+      ))
+      .behaviour(Echo::default())
+      .clock(clock)
+      .rng(StdRng::from_entropy())
+      .with_node_config(config.build())
+      .build()
+      .expect("failed to build the node");
+    (node, address)
+  };
+  let (mut bootnode, address) = node(1, None);
+  let (mut joining, _) = node(2, Some(address));
+
+  block_on(async {
+    let mut echoes = 0;
+    while echoes < 2 {
+      let event = match select(&mut bootnode, &mut joining).await {
+        Either::Left((event, _)) | Either::Right((event, _)) => event,
+      };
+      if let NodeEvent::Behaviour(Echoed { peer_id, payload }) = event {
+        println!("{} echoed {:?}", peer_id, String::from_utf8_lossy(&payload));
+        echoes += 1;
+      }
+    }
+  });
+}
@@ -0,0 +1,84 @@
+//! Runs a single node over UDP.
+//!
+//! Start a bootnode and a node connecting to it:
+//!
+//! ```sh
+//! cargo run --example udp_node -- /ip4/127.0.0.1/udp/4000
+//! cargo run --example udp_node -- /ip4/127.0.0.1/udp/4001 <peer id>@/ip4/127.0.0.1/udp/4000
+//! ```
+//!
+//! Peers prove that they own their peer id, but messages are sent in the clear
+//! and are not tied to the handshake, anyone on the path can read and inject
+//! them. Only run it on a network you trust.
+use {
+  c2n::{
+    clock::wall::WallClock,
+    network::{handshake::Authenticated, udp::UdpNetwork, Network},
+    node::Node,
+    node_config::NodeConfigBuilder,
+    node_events::NodeEvent,
+    peer_list_manager::simple::SimplePeerListManager,
+    storage::sim::SimStorage,
+    types::{format_node_address, parse_node_address},
+  },
+  futures::executor::block_on,
+  rand::{rngs::StdRng, SeedableRng},
+  std::env,
+};
+
+fn main() {
+  tracing_subscriber::fmt::init();
+
+  let mut args = env::args().skip(1);
+  let address = args
+    .next()
+    .unwrap_or_else(|| "/ip4/127.0.0.1/udp/0".to_string())
+    .parse()
+    .expect("invalid listen address");
+
+  let mut rng = StdRng::from_entropy();
+  let mut config = NodeConfigBuilder::new()
+    .with_unique_identity(&mut rng)
+    .with_address(address)
+    // a bootnode and a single node already form a network
+    .with_min_peers(1);
+  if let Some(bootnode) = args.next() {
+    config = config
+      .with_bootnode(parse_node_address(&bootnode).expect("invalid bootnode"));
+  }
+  let config = config.build();
+
+  let network =
+    UdpNetwork::bind(config.node_address()).expect("failed to listen");
+  println!("listening as {}", format_node_address(network.address()));
+
+  let clock = WallClock::new();
+  let network = Authenticated::new(
+    network,
+    config.keypair().clone(),
+    clock,
+    StdRng::from_entropy(),
+  );
+  let mut node = Node::builder()
+    .network(network)
+    .storage(SimStorage::build(StdRng::from_entropy()))
+    .peer_list_manager(SimplePeerListManager::build(
+      StdRng::from_entropy(),
+      clock,
+    ))
+    .behaviour(())
+    .clock(clock)
+    .rng(StdRng::from_entropy())
+    .with_node_config(config)
+    .build()
+    .expect("failed to build the node");
+
+  block_on(async {
+    loop {
+      let event = (&mut node).await;
+      if event != NodeEvent::Noop {
+        println!("{:?}", event);
+      }
+    }
+  });
+}
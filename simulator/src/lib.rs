@@ -1,3 +1,3 @@
 pub mod sim_builder;
 
-pub use sim_builder::SimBuilder;
+pub use sim_builder::{SimBuilder, Simulation};
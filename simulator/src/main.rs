@@ -1,9 +1,12 @@
 use {
+  c2n::{node_events::NodeState, simulation_executor::RunBudget},
   c2n_simulator::SimBuilder,
   rand::{rngs::StdRng, SeedableRng},
+  std::time::Duration,
 };
 
 const NODE_COUNT: usize = 10;
+const MIN_CONNECTIONS: usize = 3;
 
 fn main() -> anyhow::Result<()> {
   // setup tracing with default subscriber
@@ -15,7 +18,32 @@ fn main() -> anyhow::Result<()> {
     .with_node_count(NODE_COUNT)
     .build();
 
-  loop {
-    simulation.run_tick();
-  }
+  // Run until every node has joined the network and is connected to a couple
+  // of peers, giving up after a minute of simulated time.
+  let report = simulation.run_until(
+    RunBudget::duration(Duration::from_secs(60)),
+    |view| {
+      view.nodes().count() == NODE_COUNT + 1
+        && view
+          .nodes()
+          .all(|node| node.connections().len() >= MIN_CONNECTIONS)
+        && view.nodes().all(|node| node.state() == NodeState::Running)
+    },
+  );
+
+  println!(
+    "{:?} after {} ticks at {:?} simulated time, {} node events",
+    report.outcome,
+    report.ticks,
+    report.now,
+    simulation.events().len()
+  );
+
+  let traffic = simulation.network().0.borrow().total_traffic();
+  println!(
+    "{} messages carrying {} bytes sent, {} bytes received",
+    traffic.messages_sent, traffic.bytes_sent, traffic.bytes_received
+  );
+
+  Ok(())
 }
@@ -1,19 +1,61 @@
 use {
   c2n::{
-    network::sim::{SimNetwork, SimNetworkClient, SimNetworkFuture},
+    clock::sim::SimClock,
+    network::{
+      handshake::Authenticated,
+      sim::{
+        fault::{
+          LinkFaultConfig,
+          LinkFaults,
+          NetworkFault,
+          Partition,
+          PartitionMode,
+        },
+        latency::LinkLatency,
+        SimNetwork,
+        SimNetworkClient,
+        SimNetworkConfig,
+        SimNetworkFuture,
+      },
+    },
     node_config::NodeConfigBuilder,
     peer_list_manager::simple::SimplePeerListManager,
     rng::GeneratesRngSeed,
-    simulation_executor::SimulationExecutor,
-    storage::sim::SimStorage,
+    simulation_executor::{
+      PollOrder,
+      SimulatableNodeFuture,
+      SimulationExecutor,
+    },
+    storage::sim::{SimDisk, SimStorage},
+    types::{NodeAddress, NodeIdentity},
   },
+  multiaddr::Multiaddr,
   rand::{Rng, SeedableRng},
-  std::{rc::Rc, time::Duration},
+  std::{cell::RefCell, ops::Range, rc::Rc, time::Duration},
 };
 
+pub type Simulation<R> = SimulationExecutor<SimNetworkFuture<R>, R>;
+
+/// A partition of the built nodes into groups, applied and healed at fixed
+/// simulated times.
+#[derive(Clone)]
+struct ScheduledPartition {
+  at: Duration,
+  heal_at: Duration,
+  groups: usize,
+  mode: PartitionMode,
+}
+
+#[derive(Clone)]
 pub struct SimBuilder<R> {
   rng: R,
   node_count: Option<usize>,
+  poll_order: PollOrder,
+  ordering_seed: Option<u64>,
+  latency: LinkLatency,
+  network_config: SimNetworkConfig,
+  partition: Option<ScheduledPartition>,
+  link_faults: LinkFaults,
 }
 
 impl<R: Rng + SeedableRng + Unpin + 'static> SimBuilder<R> {
@@ -21,6 +63,12 @@ impl<R: Rng + SeedableRng + Unpin + 'static> SimBuilder<R> {
     Self {
       rng,
       node_count: None,
+      poll_order: Default::default(),
+      ordering_seed: None,
+      latency: Default::default(),
+      network_config: Default::default(),
+      partition: None,
+      link_faults: Default::default(),
     }
   }
 
@@ -29,29 +77,115 @@ impl<R: Rng + SeedableRng + Unpin + 'static> SimBuilder<R> {
     self
   }
 
-  pub fn build(mut self) -> SimulationExecutor<SimNetworkFuture<R>> {
-    let network = SimNetwork::build(self.rng.next_rng_seed());
-
-    let mut simulation =
-      SimulationExecutor::new(Box::pin(SimNetworkFuture::wrap(&network)));
-
-    let bootnode_config = NodeConfigBuilder::new()
-      .with_unique_identity(&mut self.rng)
-      .with_address("/memory/0".parse().unwrap())
-      .build();
-    let bootnode = c2n::node::Node::builder()
-      .network(SimNetworkClient::build(
-        self.rng.next_rng_seed(),
-        Rc::clone(&network),
-        bootnode_config.node_address(),
-      ))
-      .storage(SimStorage::build(self.rng.next_rng_seed()))
-      .peer_list_manager(SimplePeerListManager::build(self.rng.next_rng_seed()))
-      .with_node_config(bootnode_config)
-      .build();
+  pub fn with_poll_order(mut self, poll_order: PollOrder) -> Self {
+    self.poll_order = poll_order;
+    self
+  }
+
+  /// Sets the latency models used by the network to deliver messages.
+  pub fn with_latency(mut self, latency: LinkLatency) -> Self {
+    self.latency = latency;
+    self
+  }
+
+  /// Sets the network configuration, such as the bandwidth, of every node.
+  pub fn with_network_config(
+    mut self,
+    network_config: SimNetworkConfig,
+  ) -> Self {
+    self.network_config = network_config;
+    self
+  }
+
+  /// Lets every link drop, duplicate and reorder messages.
+  pub fn with_link_faults(mut self, link_faults: LinkFaults) -> Self {
+    self.link_faults = link_faults;
+    self
+  }
+
+  /// Splits the nodes into `groups` that can not reach each other at `at`,
+  /// and heals the network at `heal_at`. The bootnode ends up in the first
+  /// group, the other nodes are spread over the groups in turn.
+  pub fn with_partition(
+    mut self,
+    at: Duration,
+    heal_at: Duration,
+    groups: usize,
+    mode: PartitionMode,
+  ) -> Self {
+    self.partition = Some(ScheduledPartition {
+      at,
+      heal_at,
+      groups,
+      mode,
+    });
+    self
+  }
+
+  /// Seeds the order in which nodes are polled independently of the
+  /// scenario, the same seed with a different ordering seed builds the same
+  /// network but interleaves the nodes differently.
+  pub fn with_ordering_seed(mut self, ordering_seed: u64) -> Self {
+    self.ordering_seed = Some(ordering_seed);
+    self
+  }
+
+  /// Builds the same scenario once for every ordering seed in the range.
+  pub fn explore_orderings(
+    self,
+    ordering_seeds: Range<u64>,
+  ) -> impl Iterator<Item = Simulation<R>>
+  where
+    R: Clone,
+  {
+    ordering_seeds.map(move |ordering_seed| {
+      self.clone().with_ordering_seed(ordering_seed).build()
+    })
+  }
+
+  pub fn build(mut self) -> Simulation<R> {
+    // All components share the same simulated clock, which is advanced by the
+    // executor.
+    let clock = SimClock::new();
+    let network = SimNetwork::build(self.rng.next_rng_seed(), clock.clone());
+    network.borrow_mut().set_latency(self.latency.clone());
+    network
+      .borrow_mut()
+      .set_link_faults(LinkFaultConfig::new(self.link_faults));
+
+    // Always draw the ordering rng, so that overriding the ordering seed does
+    // not shift the rng used for the rest of the scenario.
+    let ordering_rng: R = self.rng.next_rng_seed();
+    let ordering_rng = self
+      .ordering_seed
+      .map(R::seed_from_u64)
+      .unwrap_or(ordering_rng);
+
+    let mut simulation = SimulationExecutor::new(
+      Box::pin(SimNetworkFuture::wrap(&network)),
+      clock.clone(),
+      ordering_rng,
+    )
+    .with_poll_order(self.poll_order);
+
+    let bootnode = NodeBlueprint {
+      identity: NodeIdentity::generate(&mut self.rng),
+      address: "/memory/0".parse().unwrap(),
+      bootnode: None,
+      disk: SimDisk::new(),
+      network: Rc::clone(&network),
+      network_config: self.network_config.clone(),
+      clock: clock.clone(),
+    };
 
     // Get the address of the bootnode so that other nodes can connect to it.
-    let bootnode_addr = bootnode.config().node_address();
+    let bootnode_addr = bootnode
+      .identity
+      .public()
+      .into_node_address(bootnode.address.clone());
+    let bootnode_node = bootnode.build(&mut self.rng);
+
+    let mut identities = vec![*bootnode.identity.public()];
 
     // We start at 1 second to give the bootnode a head start.
     let mut time_offset = Duration::from_secs(1);
@@ -60,30 +194,104 @@ impl<R: Rng + SeedableRng + Unpin + 'static> SimBuilder<R> {
       // the random number generator will be seeded at a new position,
       // giving each node a unique starting sequence.
       let mut rng = self.rng.next_rng_seed();
-      let config = NodeConfigBuilder::new()
-        .with_bootnode(bootnode_addr.clone())
-        .with_unique_identity(&mut rng)
-        .with_address(format!("/memory/{}", idx + 1).parse().unwrap())
-        .build();
-      let node = c2n::node::Node::builder()
-        .network(SimNetworkClient::build(
-          rng.next_rng_seed(),
-          Rc::clone(&network),
-          config.node_address(),
-        ))
-        .storage(SimStorage::build(rng.next_rng_seed()))
-        .peer_list_manager(SimplePeerListManager::build(rng.next_rng_seed()))
-        .with_node_config(config)
-        .build();
-
-      time_offset =
-        time_offset + Duration::from_millis(rng.gen_range(100..2_000));
-      simulation.add_node(time_offset, Box::pin(node));
+      let blueprint = NodeBlueprint {
+        identity: NodeIdentity::generate(&mut rng),
+        address: format!("/memory/{}", idx + 1).parse().unwrap(),
+        bootnode: Some(bootnode_addr.clone()),
+        disk: SimDisk::new(),
+        network: Rc::clone(&network),
+        network_config: self.network_config.clone(),
+        clock: clock.clone(),
+      };
+      let node = blueprint.build(&mut rng);
+      identities.push(*blueprint.identity.public());
+
+      time_offset += Duration::from_millis(rng.gen_range(100..2_000));
+      simulation.add_node(time_offset, node);
+      blueprint.register_restart(&mut simulation, rng.next_rng_seed());
     }
 
     // add the bootnode to the simulation
-    simulation.add_node(Duration::ZERO, Box::pin(bootnode));
+    simulation.add_node(Duration::ZERO, bootnode_node);
+    bootnode.register_restart(&mut simulation, self.rng.next_rng_seed());
+
+    if let Some(partition) = self.partition {
+      let group_count = partition.groups.max(1);
+      let mut groups = vec![Vec::new(); group_count];
+      for (idx, identity) in identities.into_iter().enumerate() {
+        groups[idx % group_count].push(identity);
+      }
+
+      let mut network = network.borrow_mut();
+      network.schedule_fault(
+        partition.at,
+        NetworkFault::Partition(Partition::new(groups, partition.mode)),
+      );
+      network.schedule_fault(partition.heal_at, NetworkFault::Heal);
+    }
 
     simulation
   }
 }
+
+/// Everything needed to build a node. It is kept around after the simulation
+/// has been built, so a crashed node can be restarted with the same identity
+/// and the same disk.
+struct NodeBlueprint<R> {
+  identity: NodeIdentity,
+  address: Multiaddr,
+  bootnode: Option<NodeAddress>,
+  disk: SimDisk,
+  network: Rc<RefCell<SimNetwork<R>>>,
+  network_config: SimNetworkConfig,
+  clock: SimClock,
+}
+
+impl<R: Rng + SeedableRng + Unpin + 'static> NodeBlueprint<R> {
+  fn build(&self, rng: &mut R) -> SimulatableNodeFuture {
+    let mut config = NodeConfigBuilder::new()
+      .with_identity(self.identity.clone())
+      .with_address(self.address.clone());
+    if let Some(bootnode) = &self.bootnode {
+      config = config.with_bootnode(bootnode.clone());
+    }
+    let config = config.build();
+
+    let node = c2n::node::Node::builder()
+      .network(Authenticated::new(
+        SimNetworkClient::with_config(
+          rng.next_rng_seed(),
+          Rc::clone(&self.network),
+          config.node_address(),
+          self.network_config.clone(),
+        ),
+        self.identity.clone(),
+        self.clock.clone(),
+        rng.next_rng_seed(),
+      ))
+      .storage(SimStorage::with_disk(
+        rng.next_rng_seed(),
+        self.disk.clone(),
+      ))
+      .peer_list_manager(SimplePeerListManager::build(
+        rng.next_rng_seed(),
+        self.clock.clone(),
+      ))
+      .behaviour(())
+      .clock(self.clock.clone())
+      .rng(rng.next_rng_seed())
+      .with_node_config(config)
+      .build()
+      .expect("the blueprint provides every component");
+
+    Box::pin(node)
+  }
+
+  /// Lets the simulation rebuild the node from this blueprint when it is
+  /// restarted.
+  fn register_restart(self, simulation: &mut Simulation<R>, mut rng: R) {
+    simulation.set_restart_factory(*self.identity.public(), move || {
+      self.build(&mut rng)
+    });
+  }
+}
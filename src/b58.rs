@@ -1,4 +1,4 @@
-use crate::primitives::Pubkey;
+use {crate::primitives::Pubkey, thiserror::Error};
 
 pub trait Base58Encode {
   fn bs58_encode(&self) -> String;
@@ -15,3 +15,88 @@ impl Base58Encode for Pubkey {
     self.to_bytes().bs58_encode()
   }
 }
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Base58DecodeError {
+  #[error("invalid base58: {0}")]
+  InvalidEncoding(#[from] bs58::decode::Error),
+  #[error("expected {expected} bytes but decoded {actual}")]
+  InvalidLength { expected: usize, actual: usize },
+}
+
+pub trait Base58Decode: Sized {
+  fn bs58_decode(encoded: &str) -> Result<Self, Base58DecodeError>;
+}
+
+impl<const N: usize> Base58Decode for [u8; N] {
+  fn bs58_decode(encoded: &str) -> Result<Self, Base58DecodeError> {
+    let bytes = bs58::decode(encoded).into_vec()?;
+    let actual = bytes.len();
+    bytes
+      .try_into()
+      .map_err(|_| Base58DecodeError::InvalidLength {
+        expected: N,
+        actual,
+      })
+  }
+}
+
+impl Base58Decode for Pubkey {
+  fn bs58_decode(encoded: &str) -> Result<Self, Base58DecodeError> {
+    Ok(Pubkey::from_bytes(Base58Decode::bs58_decode(encoded)?))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::types::Keypair,
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  #[test]
+  fn bytes_round_trip() {
+    let bytes = [0, 0, 1, 2, 255];
+    let encoded = bytes.bs58_encode();
+    assert_eq!(encoded, "11LiA");
+    assert_eq!(<[u8; 5]>::bs58_decode(&encoded), Ok(bytes));
+  }
+
+  #[test]
+  fn pubkey_round_trips() {
+    let keypair = Keypair::generate(&mut StdRng::seed_from_u64(1));
+    let encoded = keypair.public().bs58_encode();
+    assert_eq!(Pubkey::bs58_decode(&encoded), Ok(*keypair.public()));
+  }
+
+  #[test]
+  fn characters_outside_the_alphabet_are_rejected() {
+    // 0, O, I and l are left out of the alphabet as easily confused
+    for encoded in ["0", "O", "I", "l", "abc-"] {
+      assert!(matches!(
+        <[u8; 1]>::bs58_decode(encoded),
+        Err(Base58DecodeError::InvalidEncoding(_))
+      ));
+    }
+  }
+
+  #[test]
+  fn wrong_length_is_rejected() {
+    let encoded = [7u8; 31].bs58_encode();
+    assert_eq!(
+      Pubkey::bs58_decode(&encoded),
+      Err(Base58DecodeError::InvalidLength {
+        expected: 32,
+        actual: 31,
+      })
+    );
+    assert_eq!(
+      <[u8; 4]>::bs58_decode(""),
+      Err(Base58DecodeError::InvalidLength {
+        expected: 4,
+        actual: 0,
+      })
+    );
+  }
+}
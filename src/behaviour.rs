@@ -0,0 +1,282 @@
+use {
+  crate::{
+    network::{NetworkError, ProtocolId, RequestId},
+    types::PeerId,
+  },
+  futures::future::Either,
+  std::{
+    convert::Infallible,
+    task::{Context, Poll},
+  },
+};
+
+/// Payloads of behaviours larger than this are not sent, so a message of any
+/// behaviour fits in a UDP datagram and works on every transport.
+pub const MAX_PAYLOAD_SIZE: usize = 60 * 1024;
+
+/// What a behaviour asks the node to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BehaviourAction<E> {
+  /// Send `payload` to a connected peer, it is handed to the behaviour
+  /// supporting `protocol` on the other end.
+  Send {
+    peer_id: PeerId,
+    protocol: ProtocolId,
+    payload: Vec<u8>,
+  },
+  /// Send a request to a connected peer. The answer comes back through
+  /// `Behaviour::on_response`, or `Behaviour::on_request_timeout` when the
+  /// peer does not answer within the configured request timeout.
+  Request {
+    peer_id: PeerId,
+    protocol: ProtocolId,
+    payload: Vec<u8>,
+  },
+  /// Answer the request `id` a peer made through `Behaviour::on_request`.
+  Respond {
+    peer_id: PeerId,
+    protocol: ProtocolId,
+    id: RequestId,
+    payload: Vec<u8>,
+  },
+  /// Report an event to the runtime polling the node, it comes out as
+  /// `NodeEvent::Behaviour`.
+  Event(E),
+}
+
+impl<E> BehaviourAction<E> {
+  fn map_event<F>(self, f: impl FnOnce(E) -> F) -> BehaviourAction<F> {
+    match self {
+      BehaviourAction::Send {
+        peer_id,
+        protocol,
+        payload,
+      } => BehaviourAction::Send {
+        peer_id,
+        protocol,
+        payload,
+      },
+      BehaviourAction::Request {
+        peer_id,
+        protocol,
+        payload,
+      } => BehaviourAction::Request {
+        peer_id,
+        protocol,
+        payload,
+      },
+      BehaviourAction::Respond {
+        peer_id,
+        protocol,
+        id,
+        payload,
+      } => BehaviourAction::Respond {
+        peer_id,
+        protocol,
+        id,
+        payload,
+      },
+      BehaviourAction::Event(event) => BehaviourAction::Event(f(event)),
+    }
+  }
+}
+
+/// An application protocol running on top of the overlay, such as gossip or
+/// block sync. The node takes care of the connections and tells the
+/// behaviour about them, the behaviour exchanges its own messages with the
+/// connected peers.
+///
+/// Messages are tagged with a protocol id, the node hands every message to
+/// the behaviour supporting its protocol and drops it otherwise. Behaviours
+/// are combined by nesting them in tuples, `()` runs none.
+pub trait Behaviour {
+  /// Events the behaviour reports to the runtime.
+  type Event;
+
+  /// Whether messages of `protocol` are meant for this behaviour.
+  fn supports(&self, protocol: ProtocolId) -> bool;
+
+  /// Called when a connection to a peer has been established.
+  fn on_peer_connected(&mut self, peer_id: PeerId);
+
+  /// Called when the connection to a peer has been closed.
+  fn on_peer_disconnected(&mut self, peer_id: PeerId);
+
+  /// Called with a message a connected peer sent for a supported protocol.
+  fn on_message(
+    &mut self,
+    peer_id: PeerId,
+    protocol: ProtocolId,
+    payload: Vec<u8>,
+  );
+
+  /// Called with a request a connected peer made for a supported protocol,
+  /// answer it with `BehaviourAction::Respond`. Requests left unanswered
+  /// time out on the other end.
+  fn on_request(
+    &mut self,
+    _peer_id: PeerId,
+    _protocol: ProtocolId,
+    _id: RequestId,
+    _payload: Vec<u8>,
+  ) {
+  }
+
+  /// Called with the answer to a `BehaviourAction::Request`, along with the
+  /// payload of the request it answers.
+  fn on_response(
+    &mut self,
+    _peer_id: PeerId,
+    _protocol: ProtocolId,
+    _request: Vec<u8>,
+    _response: Vec<u8>,
+  ) {
+  }
+
+  /// Called when a `BehaviourAction::Request` has not been answered in time.
+  /// Requests to a peer that disconnects are dropped without a call, the
+  /// behaviour learns about it through `on_peer_disconnected`.
+  fn on_request_timeout(
+    &mut self,
+    _peer_id: PeerId,
+    _protocol: ProtocolId,
+    _request: Vec<u8>,
+  ) {
+  }
+
+  /// Called when a message, request or response of the behaviour could not
+  /// be sent, because its payload exceeds `MAX_PAYLOAD_SIZE`, the peer can
+  /// not keep up or is no longer connected.
+  fn on_send_failed(
+    &mut self,
+    _peer_id: PeerId,
+    _protocol: ProtocolId,
+    _error: NetworkError,
+  ) {
+  }
+
+  /// Polled by the node while it is part of the overlay, registers the task
+  /// to be woken up when the behaviour has something to do.
+  fn poll(
+    &mut self,
+    cx: &mut Context<'_>,
+  ) -> Poll<BehaviourAction<Self::Event>>;
+}
+
+impl Behaviour for () {
+  type Event = Infallible;
+
+  fn supports(&self, _: ProtocolId) -> bool {
+    false
+  }
+
+  fn on_peer_connected(&mut self, _: PeerId) {}
+
+  fn on_peer_disconnected(&mut self, _: PeerId) {}
+
+  fn on_message(&mut self, _: PeerId, _: ProtocolId, _: Vec<u8>) {}
+
+  fn poll(&mut self, _: &mut Context<'_>) -> Poll<BehaviourAction<Infallible>> {
+    Poll::Pending
+  }
+}
+
+/// Runs two behaviours side by side. A protocol supported by both is handled
+/// by the first one.
+impl<A: Behaviour, B: Behaviour> Behaviour for (A, B) {
+  type Event = Either<A::Event, B::Event>;
+
+  fn supports(&self, protocol: ProtocolId) -> bool {
+    self.0.supports(protocol) || self.1.supports(protocol)
+  }
+
+  fn on_peer_connected(&mut self, peer_id: PeerId) {
+    self.0.on_peer_connected(peer_id);
+    self.1.on_peer_connected(peer_id);
+  }
+
+  fn on_peer_disconnected(&mut self, peer_id: PeerId) {
+    self.0.on_peer_disconnected(peer_id);
+    self.1.on_peer_disconnected(peer_id);
+  }
+
+  fn on_message(
+    &mut self,
+    peer_id: PeerId,
+    protocol: ProtocolId,
+    payload: Vec<u8>,
+  ) {
+    if self.0.supports(protocol) {
+      self.0.on_message(peer_id, protocol, payload);
+    } else if self.1.supports(protocol) {
+      self.1.on_message(peer_id, protocol, payload);
+    }
+  }
+
+  fn on_request(
+    &mut self,
+    peer_id: PeerId,
+    protocol: ProtocolId,
+    id: RequestId,
+    payload: Vec<u8>,
+  ) {
+    if self.0.supports(protocol) {
+      self.0.on_request(peer_id, protocol, id, payload);
+    } else if self.1.supports(protocol) {
+      self.1.on_request(peer_id, protocol, id, payload);
+    }
+  }
+
+  fn on_response(
+    &mut self,
+    peer_id: PeerId,
+    protocol: ProtocolId,
+    request: Vec<u8>,
+    response: Vec<u8>,
+  ) {
+    if self.0.supports(protocol) {
+      self.0.on_response(peer_id, protocol, request, response);
+    } else if self.1.supports(protocol) {
+      self.1.on_response(peer_id, protocol, request, response);
+    }
+  }
+
+  fn on_request_timeout(
+    &mut self,
+    peer_id: PeerId,
+    protocol: ProtocolId,
+    request: Vec<u8>,
+  ) {
+    if self.0.supports(protocol) {
+      self.0.on_request_timeout(peer_id, protocol, request);
+    } else if self.1.supports(protocol) {
+      self.1.on_request_timeout(peer_id, protocol, request);
+    }
+  }
+
+  fn on_send_failed(
+    &mut self,
+    peer_id: PeerId,
+    protocol: ProtocolId,
+    error: NetworkError,
+  ) {
+    if self.0.supports(protocol) {
+      self.0.on_send_failed(peer_id, protocol, error);
+    } else if self.1.supports(protocol) {
+      self.1.on_send_failed(peer_id, protocol, error);
+    }
+  }
+
+  fn poll(
+    &mut self,
+    cx: &mut Context<'_>,
+  ) -> Poll<BehaviourAction<Self::Event>> {
+    if let Poll::Ready(action) = self.0.poll(cx) {
+      return Poll::Ready(action.map_event(Either::Left));
+    }
+    self
+      .1
+      .poll(cx)
+      .map(|action| action.map_event(Either::Right))
+  }
+}
@@ -0,0 +1,19 @@
+pub mod sim;
+pub mod wall;
+
+use std::{future::Future, time::Duration};
+
+/// A source of time and timers. Components that need to wait take a clock
+/// instead of creating wall-clock timers directly, so the same code can run
+/// against simulated time or real time.
+pub trait Clock: Clone + Unpin {
+  /// Future that resolves once the requested duration has elapsed.
+  type Delay: Future<Output = ()> + Unpin;
+
+  /// Time elapsed since the epoch of the clock, the start of the
+  /// simulation or the unix epoch.
+  fn now(&self) -> Duration;
+
+  /// Creates a timer that fires `duration` from now.
+  fn delay(&self, duration: Duration) -> Self::Delay;
+}
@@ -0,0 +1,251 @@
+use {
+  crate::clock::Clock,
+  futures::Future,
+  std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    ops::Bound,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+    time::Duration,
+  },
+};
+
+// Timers are keyed by their deadline and a sequence number, so timers sharing
+// a deadline fire in the order they were created.
+type TimerKey = (Duration, u64);
+
+#[derive(Default)]
+struct SimClockState {
+  now: Duration,
+  next_timer_id: u64,
+  timers: BTreeMap<TimerKey, Option<Waker>>,
+}
+
+/// Virtual time source for the simulation. Time only moves when the
+/// simulation executor calls `advance`, which jumps straight to the next
+/// pending deadline. This makes runs reproducible for a given seed and lets
+/// them execute as fast as the CPU allows.
+#[derive(Clone, Default)]
+pub struct SimClock {
+  state: Rc<RefCell<SimClockState>>,
+}
+
+impl SimClock {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The earliest deadline after the current time, this is where `advance`
+  /// will move the clock to.
+  pub fn next_deadline(&self) -> Option<Duration> {
+    let state = self.state.borrow();
+    let upcoming = (Bound::Excluded((state.now, u64::MAX)), Bound::Unbounded);
+    state
+      .timers
+      .range(upcoming)
+      .next()
+      .map(|((deadline, _), _)| *deadline)
+  }
+
+  /// Moves time forward to the next deadline after the current time and
+  /// wakes every timer that has expired. Returns the new time, or `None`
+  /// when no timers are pending.
+  pub fn advance(&self) -> Option<Duration> {
+    let next = self.next_deadline()?;
+    self.advance_to(next);
+    Some(next)
+  }
+
+  /// Moves time forward to `time` and wakes every timer that has expired.
+  /// Time never moves backwards, an earlier `time` only wakes the expired
+  /// timers.
+  pub fn advance_to(&self, time: Duration) {
+    let wakers: Vec<Waker> = {
+      let mut state = self.state.borrow_mut();
+      state.now = state.now.max(time);
+      let now = state.now;
+
+      state
+        .timers
+        .range_mut(..=(now, u64::MAX))
+        .filter_map(|(_, waker)| waker.take())
+        .collect()
+    };
+
+    // wake outside of the borrow, a waker is free to poll the timer again
+    for waker in wakers {
+      waker.wake();
+    }
+  }
+}
+
+impl Clock for SimClock {
+  type Delay = SimDelay;
+
+  fn now(&self) -> Duration {
+    self.state.borrow().now
+  }
+
+  fn delay(&self, duration: Duration) -> Self::Delay {
+    let mut state = self.state.borrow_mut();
+    // deadlines beyond the representable time are clamped to its end
+    let key = (state.now.saturating_add(duration), state.next_timer_id);
+    state.next_timer_id += 1;
+
+    // register the deadline right away, the clock must not skip past a timer
+    // that has not been polled yet
+    state.timers.insert(key, None);
+
+    SimDelay {
+      clock: self.clone(),
+      key,
+    }
+  }
+}
+
+/// Timer created by `SimClock::delay`, fires once the simulated time reaches
+/// its deadline.
+pub struct SimDelay {
+  clock: SimClock,
+  key: TimerKey,
+}
+
+impl Future for SimDelay {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let mut state = self.clock.state.borrow_mut();
+    let (deadline, _) = self.key;
+
+    if state.now >= deadline {
+      state.timers.remove(&self.key);
+      return Poll::Ready(());
+    }
+
+    state.timers.insert(self.key, Some(cx.waker().clone()));
+    Poll::Pending
+  }
+}
+
+impl Drop for SimDelay {
+  fn drop(&mut self) {
+    self.clock.state.borrow_mut().timers.remove(&self.key);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    futures::{task::noop_waker_ref, FutureExt},
+    std::{
+      sync::{Arc, Mutex},
+      task::Wake,
+    },
+  };
+
+  /// Records the order in which timers are woken.
+  struct Recorder {
+    id: usize,
+    woken: Arc<Mutex<Vec<usize>>>,
+  }
+
+  impl Wake for Recorder {
+    fn wake(self: Arc<Self>) {
+      self.woken.lock().unwrap().push(self.id);
+    }
+  }
+
+  fn poll(delay: &mut SimDelay) -> Poll<()> {
+    delay.poll_unpin(&mut Context::from_waker(noop_waker_ref()))
+  }
+
+  #[test]
+  fn advance_jumps_to_the_next_deadline() {
+    let clock = SimClock::new();
+    let mut late = clock.delay(Duration::from_secs(5));
+    let mut early = clock.delay(Duration::from_secs(2));
+    assert_eq!(clock.next_deadline(), Some(Duration::from_secs(2)));
+
+    assert_eq!(clock.advance(), Some(Duration::from_secs(2)));
+    assert_eq!(clock.now(), Duration::from_secs(2));
+    assert!(poll(&mut early).is_ready());
+    assert!(poll(&mut late).is_pending());
+
+    assert_eq!(clock.advance(), Some(Duration::from_secs(5)));
+    assert!(poll(&mut late).is_ready());
+    assert_eq!(clock.advance(), None);
+    assert_eq!(clock.now(), Duration::from_secs(5));
+  }
+
+  #[test]
+  fn timers_sharing_a_deadline_fire_in_creation_order() {
+    let clock = SimClock::new();
+    let woken = Arc::new(Mutex::new(Vec::new()));
+    let mut delays: Vec<_> = [3, 1, 3, 1]
+      .into_iter()
+      .map(|secs| clock.delay(Duration::from_secs(secs)))
+      .collect();
+    for (id, delay) in delays.iter_mut().enumerate() {
+      let waker = Arc::new(Recorder {
+        id,
+        woken: Arc::clone(&woken),
+      })
+      .into();
+      assert!(delay
+        .poll_unpin(&mut Context::from_waker(&waker))
+        .is_pending());
+    }
+
+    assert_eq!(clock.advance(), Some(Duration::from_secs(1)));
+    assert_eq!(clock.advance(), Some(Duration::from_secs(3)));
+    assert_eq!(*woken.lock().unwrap(), vec![1, 3, 0, 2]);
+  }
+
+  #[test]
+  fn time_never_moves_backwards() {
+    let clock = SimClock::new();
+    clock.advance_to(Duration::from_secs(4));
+    clock.advance_to(Duration::from_secs(1));
+    assert_eq!(clock.now(), Duration::from_secs(4));
+
+    // a timer created later counts from the current time
+    let mut delay = clock.delay(Duration::from_secs(1));
+    assert_eq!(clock.next_deadline(), Some(Duration::from_secs(5)));
+    assert!(poll(&mut delay).is_pending());
+  }
+
+  #[test]
+  fn dropped_timer_is_forgotten() {
+    let clock = SimClock::new();
+    let mut kept = clock.delay(Duration::from_secs(3));
+    drop(clock.delay(Duration::from_secs(1)));
+
+    assert_eq!(clock.next_deadline(), Some(Duration::from_secs(3)));
+    assert_eq!(clock.advance(), Some(Duration::from_secs(3)));
+    assert!(poll(&mut kept).is_ready());
+    assert_eq!(clock.next_deadline(), None);
+  }
+
+  #[test]
+  fn unpolled_timer_holds_the_clock() {
+    let clock = SimClock::new();
+    let mut delay = clock.delay(Duration::from_secs(2));
+    let _later = clock.delay(Duration::from_secs(7));
+
+    // the clock stops at the deadline even though nobody polled the timer
+    assert_eq!(clock.advance(), Some(Duration::from_secs(2)));
+    assert!(poll(&mut delay).is_ready());
+  }
+
+  #[test]
+  fn endless_timer_does_not_overflow() {
+    let clock = SimClock::new();
+    clock.advance_to(Duration::from_secs(1));
+    let mut endless = clock.delay(Duration::MAX);
+    assert_eq!(clock.next_deadline(), Some(Duration::MAX));
+    assert!(poll(&mut endless).is_pending());
+  }
+}
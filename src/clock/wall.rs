@@ -0,0 +1,44 @@
+use {
+  crate::clock::Clock,
+  futures_timer::Delay,
+  std::time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Clock backed by the operating system, for running nodes outside of the
+/// simulation. It counts from the unix epoch, so its time keeps growing
+/// across restarts, and moves on monotonically once created.
+#[derive(Clone, Copy)]
+pub struct WallClock {
+  started: Instant,
+  // the time since the unix epoch when the clock was created
+  epoch_offset: Duration,
+}
+
+impl Default for WallClock {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl WallClock {
+  pub fn new() -> Self {
+    WallClock {
+      started: Instant::now(),
+      epoch_offset: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default(),
+    }
+  }
+}
+
+impl Clock for WallClock {
+  type Delay = Delay;
+
+  fn now(&self) -> Duration {
+    self.epoch_offset + self.started.elapsed()
+  }
+
+  fn delay(&self, duration: Duration) -> Self::Delay {
+    Delay::new(duration)
+  }
+}
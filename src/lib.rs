@@ -1,4 +1,18 @@
+//! Simulation testing framework of an overlay network. The same `node::Node`
+//! runs over the simulated network of `network::sim` and over real transports
+//! such as `network::tcp` and `network::udp`.
+//!
+//! # Security
+//!
+//! `network::handshake::Authenticated` checks that the remote holds the key
+//! behind the peer id it claims, nothing more. Traffic is neither encrypted
+//! nor authenticated and the handshake is not bound to the connection it runs
+//! over, whoever sits between two peers can relay their handshakes, then read,
+//! drop and inject messages on both connections. Run nodes on a network you
+//! trust or over a transport that is secured on its own.
 pub mod b58;
+pub mod behaviour;
+pub mod clock;
 pub mod network;
 pub mod node;
 pub mod node_config;
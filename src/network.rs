@@ -1,12 +1,19 @@
+pub mod codec;
+pub mod handshake;
 pub mod memory;
+pub mod peer_record;
 pub mod sim;
+pub mod tcp;
+pub mod udp;
 
 use {
   crate::{
     primitives::Pubkey,
-    types::{NodeAddress, PeerId},
+    types::{NodeAddress, PeerId, Signature},
   },
-  std::{collections::HashSet, future::Future},
+  multiaddr::Multiaddr,
+  peer_record::PeerRecord,
+  std::future::Future,
   thiserror::Error,
 };
 
@@ -18,28 +25,231 @@ pub enum NetworkError {
   PeerNotFound,
   #[error("not connected")]
   NotConnected,
+  #[error("unsupported address {0}")]
+  UnsupportedAddress(Multiaddr),
+  #[error("send queue of {0} is full")]
+  Backpressure(PeerId),
+  #[error("message of {size} bytes exceeds the limit of {limit} bytes")]
+  MessageTooLarge { size: usize, limit: usize },
+  #[error("transport error: {0}")]
+  Transport(#[from] std::io::Error),
 }
 
 pub type NetworkResult<T> = Result<T, NetworkError>;
 
+impl From<codec::EncodeError> for NetworkError {
+  fn from(err: codec::EncodeError) -> Self {
+    match err {
+      codec::EncodeError::TooLarge(size) => NetworkError::MessageTooLarge {
+        size,
+        limit: codec::MAX_FRAME_SIZE,
+      },
+    }
+  }
+}
+
 /// A network interface that can send and receive messages and emit network
 /// events.
+///
+/// There is at most one connection per peer. A connection replacing the
+/// existing one of a peer is reported as `PeerDisconnected` followed by an
+/// established event, an established event for a connected peer means a dial
+/// resolved to the existing connection.
+///
+/// Transports that can not tell who is on the other end of a connection
+/// hold a newer connection of a connected peer apart as its replacement,
+/// see `NetworkEvent::ReplacementEstablished`. The replacement methods do
+/// nothing for transports that never report one.
 pub trait Network: Future<Output = NetworkEvent> {
+  /// Our peer id and the address the network is reachable on.
+  fn address(&self) -> &NodeAddress;
   fn add_peer(&mut self, peer_id: Pubkey, addr: NodeAddress);
   fn connect(&mut self, peer_id: PeerId) -> NetworkResult<()>;
-  fn disconnect(&mut self, peer_id: PeerId) -> NetworkResult<()>;
+  fn disconnect(
+    &mut self,
+    peer_id: PeerId,
+    reason: DisconnectReason,
+  ) -> NetworkResult<()>;
   fn send(
     &mut self,
     peer_id: PeerId,
     message: ProtocolMessage,
   ) -> NetworkResult<()>;
+
+  /// Sends a message over the replacement waiting for the connection of
+  /// `peer_id`.
+  fn send_replacement(
+    &mut self,
+    _peer_id: PeerId,
+    _message: ProtocolMessage,
+  ) -> NetworkResult<()> {
+    Err(NetworkError::NotConnected)
+  }
+
+  /// Closes the connection of `peer_id` and carries on over its replacement.
+  /// Nothing is reported, the caller tells whoever relied on the old
+  /// connection.
+  fn accept_replacement(&mut self, _peer_id: PeerId) -> NetworkResult<()> {
+    Err(NetworkError::NotConnected)
+  }
+
+  /// Closes the replacement waiting for the connection of `peer_id`, the
+  /// connection itself stays.
+  fn reject_replacement(&mut self, _peer_id: PeerId) -> NetworkResult<()> {
+    Err(NetworkError::NotConnected)
+  }
+}
+
+/// Identifies a request among the requests sent to the same peer, the
+/// response carries the same id.
+pub type RequestId = u64;
+
+/// Identifies the application protocol a message belongs to, see
+/// `behaviour::Behaviour`.
+pub type ProtocolId = u16;
+
+/// Requests that are answered with a `Response`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+  /// Asks for a random selection of the peers the remote knows of.
+  GetPeers,
+  /// A request of an application protocol, handed to the behaviour
+  /// supporting the protocol.
+  Application {
+    protocol: ProtocolId,
+    payload: Vec<u8>,
+  },
+}
+
+/// Answers to a `Request`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+  /// Answers `GetPeers` with the signed records telling how to reach the
+  /// peers.
+  PeerList { peers: Vec<PeerRecord> },
+  /// Answers an application request of the same protocol.
+  Application {
+    protocol: ProtocolId,
+    payload: Vec<u8>,
+  },
+}
+
+impl Response {
+  /// Whether this is the kind of response `request` expects.
+  pub fn answers(&self, request: &Request) -> bool {
+    match (request, self) {
+      (Request::GetPeers, Response::PeerList { .. }) => true,
+      (
+        Request::Application { protocol, .. },
+        Response::Application {
+          protocol: answered, ..
+        },
+      ) => protocol == answered,
+      _ => false,
+    }
+  }
 }
 
 /// Protocol Messages that can be send over the network
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ProtocolMessage {
-  /// A random PeerList communicating a set of peers I am connected to.
-  PeerList { peers: HashSet<PeerId> },
+  /// A request the remote answers with a response carrying the same id.
+  Request {
+    id: RequestId,
+    request: Request,
+  },
+  Response {
+    id: RequestId,
+    response: Response,
+  },
+  /// The sender is leaving the network and is about to disconnect.
+  Goodbye,
+  /// Asks the remote to prove that it owns its peer id by signing the
+  /// challenge.
+  Handshake {
+    challenge: [u8; 32],
+  },
+  /// Answers a handshake with a signature over the challenge.
+  HandshakeResponse {
+    signature: Signature,
+  },
+  /// Checks that the remote is still alive, answered with a pong carrying
+  /// the same nonce.
+  Ping {
+    nonce: u64,
+  },
+  Pong {
+    nonce: u64,
+  },
+  /// A message of an application protocol, handed to the behaviour
+  /// supporting the protocol.
+  Application {
+    protocol: ProtocolId,
+    payload: Vec<u8>,
+  },
+}
+
+impl ProtocolMessage {
+  /// The number of bytes the message takes up on the wire: the version,
+  /// tag and payload length header of `codec` followed by the payload.
+  pub fn encoded_size(&self) -> usize {
+    const CHALLENGE: usize = 32;
+    const SIGNATURE: usize = 64;
+    const NONCE: usize = 8;
+    const REQUEST_ID: usize = 8;
+    const KIND: usize = 1;
+    const PROTOCOL_ID: usize = 2;
+
+    codec::HEADER_SIZE
+      + match self {
+        ProtocolMessage::Request {
+          request: Request::GetPeers,
+          ..
+        } => REQUEST_ID + KIND,
+        ProtocolMessage::Request {
+          request: Request::Application { payload, .. },
+          ..
+        }
+        | ProtocolMessage::Response {
+          response: Response::Application { payload, .. },
+          ..
+        } => REQUEST_ID + KIND + PROTOCOL_ID + payload.len(),
+        ProtocolMessage::Response {
+          response: Response::PeerList { peers },
+          ..
+        } => {
+          REQUEST_ID
+            + KIND
+            + peers.iter().map(PeerRecord::encoded_size).sum::<usize>()
+        }
+        ProtocolMessage::Goodbye => 0,
+        ProtocolMessage::Handshake { .. } => CHALLENGE,
+        ProtocolMessage::HandshakeResponse { .. } => SIGNATURE,
+        ProtocolMessage::Ping { .. } | ProtocolMessage::Pong { .. } => NONCE,
+        ProtocolMessage::Application { payload, .. } => {
+          PROTOCOL_ID + payload.len()
+        }
+      }
+  }
+}
+
+/// Why a connection was closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+  /// We asked to close the connection.
+  LocalRequest,
+  /// The remote asked to close the connection.
+  RemoteRequest,
+  /// The remote stopped responding.
+  Timeout,
+  /// The connection was dropped to make room for other peers.
+  Churn,
+  /// The remote misbehaved and is not welcome anymore.
+  Ban,
+  /// The transport failed underneath the connection.
+  TransportError,
+  /// The remote could not prove that it owns its peer id.
+  HandshakeFailed,
 }
 
 /// Events that can be emitted by a network.
@@ -52,10 +262,26 @@ pub enum NetworkEvent {
   /// A new peer has connected to the network.
   InboundEstablished { peer_id: PeerId },
   /// A peer has disconnected from the network.
-  PeerDisconnected { peer_id: PeerId },
+  PeerDisconnected {
+    peer_id: PeerId,
+    reason: DisconnectReason,
+  },
   /// A message has been received from a peer.
   MessageReceived {
     peer_id: PeerId,
     message: ProtocolMessage,
   },
+  /// A connected peer opened another connection. Anyone can claim the peer
+  /// id of a connected peer, so the newer connection is held apart until
+  /// `Network::accept_replacement` swaps it in or
+  /// `Network::reject_replacement` closes it. A replacement whose connection
+  /// closes first takes its place and is reported as established.
+  ReplacementEstablished { peer_id: PeerId, outbound: bool },
+  /// A message has been received over the replacement of a connection.
+  ReplacementMessage {
+    peer_id: PeerId,
+    message: ProtocolMessage,
+  },
+  /// The replacement of a connection closed before it was accepted.
+  ReplacementClosed { peer_id: PeerId },
 }
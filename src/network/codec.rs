@@ -0,0 +1,524 @@
+use {
+  super::{
+    peer_record::{PeerRecord, MAX_ADDRESSES},
+    ProtocolMessage,
+    Request,
+    Response,
+  },
+  crate::types::{PeerId, Pubkey, Signature},
+  multiaddr::Multiaddr,
+  std::io::{self, Read, Write},
+  thiserror::Error,
+};
+
+/// Frames larger than this are rejected, so a broken peer can not make us
+/// allocate arbitrary amounts of memory.
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Version of the wire format, bumped on incompatible changes.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// Version byte, tag byte and the payload length as a big endian `u32`.
+pub const HEADER_SIZE: usize = 6;
+
+// tag 0 was the unsolicited peer list of version 1
+const TAG_GOODBYE: u8 = 1;
+const TAG_HANDSHAKE: u8 = 2;
+const TAG_HANDSHAKE_RESPONSE: u8 = 3;
+const TAG_PING: u8 = 4;
+const TAG_PONG: u8 = 5;
+const TAG_REQUEST: u8 = 6;
+const TAG_RESPONSE: u8 = 7;
+const TAG_APPLICATION: u8 = 8;
+
+// kinds of requests and responses, following the request id
+const KIND_GET_PEERS: u8 = 0;
+const KIND_PEER_LIST: u8 = 0;
+const KIND_APPLICATION: u8 = 1;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DecodeError {
+  #[error("message truncated, expected {expected} bytes but got {actual}")]
+  Truncated { expected: usize, actual: usize },
+  #[error("{0} trailing bytes after the message")]
+  TrailingBytes(usize),
+  #[error("unsupported protocol version {0}")]
+  UnsupportedVersion(u8),
+  #[error("unknown message tag {0}")]
+  UnknownTag(u8),
+  #[error("message of {0} bytes exceeds the maximum frame size")]
+  TooLarge(usize),
+  #[error("invalid payload: {0}")]
+  InvalidPayload(&'static str),
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum EncodeError {
+  #[error("message of {0} bytes exceeds the maximum frame size")]
+  TooLarge(usize),
+}
+
+/// Encodes a message as a header followed by the payload, see
+/// `ProtocolMessage::encoded_size`. Messages that do not fit in a frame are
+/// rejected, the remote would not accept them.
+pub fn encode(message: &ProtocolMessage) -> Result<Vec<u8>, EncodeError> {
+  let size = message.encoded_size();
+  if size > MAX_FRAME_SIZE {
+    return Err(EncodeError::TooLarge(size));
+  }
+
+  let mut buf = Vec::with_capacity(size);
+  buf.push(PROTOCOL_VERSION);
+  match message {
+    ProtocolMessage::Request { id, request } => {
+      buf.push(TAG_REQUEST);
+      let len = message.encoded_size() - HEADER_SIZE;
+      buf.extend_from_slice(&(len as u32).to_be_bytes());
+      buf.extend_from_slice(&id.to_be_bytes());
+      match request {
+        Request::GetPeers => buf.push(KIND_GET_PEERS),
+        Request::Application { protocol, payload } => {
+          encode_application(&mut buf, *protocol, payload)
+        }
+      }
+    }
+    ProtocolMessage::Response { id, response } => {
+      buf.push(TAG_RESPONSE);
+      let len = message.encoded_size() - HEADER_SIZE;
+      buf.extend_from_slice(&(len as u32).to_be_bytes());
+      buf.extend_from_slice(&id.to_be_bytes());
+      match response {
+        Response::PeerList { peers } => {
+          buf.push(KIND_PEER_LIST);
+          // sort the records, the same message always encodes to the same
+          // bytes
+          let mut peers: Vec<&PeerRecord> = peers.iter().collect();
+          peers.sort_by_key(|record| *record.peer_id());
+          for record in peers {
+            record.write_body(&mut buf);
+            buf.extend_from_slice(&record.signature().to_bytes());
+          }
+        }
+        Response::Application { protocol, payload } => {
+          encode_application(&mut buf, *protocol, payload)
+        }
+      }
+    }
+    ProtocolMessage::Goodbye => {
+      buf.push(TAG_GOODBYE);
+      buf.extend_from_slice(&0u32.to_be_bytes());
+    }
+    ProtocolMessage::Handshake { challenge } => {
+      buf.push(TAG_HANDSHAKE);
+      buf.extend_from_slice(&(challenge.len() as u32).to_be_bytes());
+      buf.extend_from_slice(challenge);
+    }
+    ProtocolMessage::HandshakeResponse { signature } => {
+      let signature = signature.to_bytes();
+      buf.push(TAG_HANDSHAKE_RESPONSE);
+      buf.extend_from_slice(&(signature.len() as u32).to_be_bytes());
+      buf.extend_from_slice(&signature);
+    }
+    ProtocolMessage::Ping { nonce } => encode_nonce(&mut buf, TAG_PING, *nonce),
+    ProtocolMessage::Pong { nonce } => encode_nonce(&mut buf, TAG_PONG, *nonce),
+    ProtocolMessage::Application { protocol, payload } => {
+      buf.push(TAG_APPLICATION);
+      let len = message.encoded_size() - HEADER_SIZE;
+      buf.extend_from_slice(&(len as u32).to_be_bytes());
+      buf.extend_from_slice(&protocol.to_be_bytes());
+      buf.extend_from_slice(payload);
+    }
+  }
+  Ok(buf)
+}
+
+fn encode_application(buf: &mut Vec<u8>, protocol: u16, payload: &[u8]) {
+  buf.push(KIND_APPLICATION);
+  buf.extend_from_slice(&protocol.to_be_bytes());
+  buf.extend_from_slice(payload);
+}
+
+fn encode_nonce(buf: &mut Vec<u8>, tag: u8, nonce: u64) {
+  let nonce = nonce.to_be_bytes();
+  buf.push(tag);
+  buf.extend_from_slice(&(nonce.len() as u32).to_be_bytes());
+  buf.extend_from_slice(&nonce);
+}
+
+/// Decodes a message produced by `encode`. The input comes from untrusted
+/// peers: every length is checked before it is used and only canonical
+/// encodings are accepted, malformed input never panics.
+pub fn decode(buf: &[u8]) -> Result<ProtocolMessage, DecodeError> {
+  let Some((header, payload)) = buf.split_first_chunk::<HEADER_SIZE>() else {
+    return Err(DecodeError::Truncated {
+      expected: HEADER_SIZE,
+      actual: buf.len(),
+    });
+  };
+
+  let [version, tag, len @ ..] = *header;
+  if version != PROTOCOL_VERSION {
+    return Err(DecodeError::UnsupportedVersion(version));
+  }
+
+  let len = u32::from_be_bytes(len) as usize;
+  if len > MAX_FRAME_SIZE - HEADER_SIZE {
+    return Err(DecodeError::TooLarge(len));
+  }
+  if payload.len() < len {
+    return Err(DecodeError::Truncated {
+      expected: HEADER_SIZE + len,
+      actual: buf.len(),
+    });
+  }
+  if payload.len() > len {
+    return Err(DecodeError::TrailingBytes(payload.len() - len));
+  }
+
+  match tag {
+    TAG_REQUEST => {
+      let mut reader = Reader(payload);
+      let id = u64::from_be_bytes(reader.array()?);
+      let request = match reader.array()? {
+        [KIND_GET_PEERS] if reader.is_empty() => Request::GetPeers,
+        [KIND_GET_PEERS] => {
+          return Err(DecodeError::InvalidPayload("request carries no body"))
+        }
+        [KIND_APPLICATION] => {
+          let protocol = u16::from_be_bytes(reader.array()?);
+          Request::Application {
+            protocol,
+            payload: reader.0.to_vec(),
+          }
+        }
+        _ => return Err(DecodeError::InvalidPayload("unknown request")),
+      };
+      Ok(ProtocolMessage::Request { id, request })
+    }
+    TAG_RESPONSE => {
+      let mut reader = Reader(payload);
+      let id = u64::from_be_bytes(reader.array()?);
+      let response = match reader.array()? {
+        [KIND_PEER_LIST] => Response::PeerList {
+          peers: decode_peer_list(reader)?,
+        },
+        [KIND_APPLICATION] => {
+          let protocol = u16::from_be_bytes(reader.array()?);
+          Response::Application {
+            protocol,
+            payload: reader.0.to_vec(),
+          }
+        }
+        _ => return Err(DecodeError::InvalidPayload("unknown response")),
+      };
+      Ok(ProtocolMessage::Response { id, response })
+    }
+    TAG_GOODBYE if payload.is_empty() => Ok(ProtocolMessage::Goodbye),
+    TAG_GOODBYE => {
+      Err(DecodeError::InvalidPayload("goodbye carries no payload"))
+    }
+    TAG_HANDSHAKE => {
+      let challenge = payload.try_into().map_err(|_| {
+        DecodeError::InvalidPayload("challenge must be 32 bytes")
+      })?;
+      Ok(ProtocolMessage::Handshake { challenge })
+    }
+    TAG_HANDSHAKE_RESPONSE => {
+      let signature: [u8; 64] = payload.try_into().map_err(|_| {
+        DecodeError::InvalidPayload("signature must be 64 bytes")
+      })?;
+      Ok(ProtocolMessage::HandshakeResponse {
+        signature: Signature::from_bytes(signature),
+      })
+    }
+    TAG_PING | TAG_PONG => {
+      let nonce: [u8; 8] = payload
+        .try_into()
+        .map_err(|_| DecodeError::InvalidPayload("nonce must be 8 bytes"))?;
+      let nonce = u64::from_be_bytes(nonce);
+      Ok(match tag {
+        TAG_PING => ProtocolMessage::Ping { nonce },
+        _ => ProtocolMessage::Pong { nonce },
+      })
+    }
+    TAG_APPLICATION => {
+      let mut reader = Reader(payload);
+      let protocol = u16::from_be_bytes(reader.array()?);
+      Ok(ProtocolMessage::Application {
+        protocol,
+        payload: reader.0.to_vec(),
+      })
+    }
+    tag => Err(DecodeError::UnknownTag(tag)),
+  }
+}
+
+/// Reads peer records up to the end of the payload.
+fn decode_peer_list(
+  mut reader: Reader,
+) -> Result<Vec<PeerRecord>, DecodeError> {
+  let mut peers = Vec::new();
+  let mut previous: Option<PeerId> = None;
+  while !reader.is_empty() {
+    let peer_id = Pubkey::from_bytes(reader.array()?);
+    // `encode` sorts the records, anything else is not canonical
+    if previous.is_some_and(|previous| previous >= peer_id) {
+      return Err(DecodeError::InvalidPayload(
+        "peer list is not sorted or has duplicates",
+      ));
+    }
+    previous = Some(peer_id);
+
+    let seq = u64::from_be_bytes(reader.array()?);
+    let [count] = reader.array()?;
+    if count as usize > MAX_ADDRESSES {
+      return Err(DecodeError::InvalidPayload("too many addresses in record"));
+    }
+
+    let mut addresses = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+      let len = u16::from_be_bytes(reader.array()?) as usize;
+      let address =
+        Multiaddr::try_from(reader.take(len)?.to_vec()).map_err(|_| {
+          DecodeError::InvalidPayload("invalid address in record")
+        })?;
+      addresses.push(address);
+    }
+
+    let signature = Signature::from_bytes(reader.array()?);
+    peers.push(PeerRecord::from_parts(peer_id, addresses, seq, signature));
+  }
+
+  Ok(peers)
+}
+
+/// Reads a payload front to back, running past its end is an error.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+  fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+    if self.0.len() < len {
+      return Err(DecodeError::InvalidPayload("payload truncated"));
+    }
+    let (bytes, rest) = self.0.split_at(len);
+    self.0 = rest;
+    Ok(bytes)
+  }
+
+  fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+    Ok(self.take(N)?.try_into().unwrap())
+  }
+}
+
+/// Writes `payload` prefixed with its length as a big endian `u32`.
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+  if payload.len() > MAX_FRAME_SIZE {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "frame too large",
+    ));
+  }
+
+  writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+  writer.write_all(payload)?;
+  writer.flush()
+}
+
+/// Reads a frame written by `write_frame`.
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+  let mut len = [0u8; 4];
+  reader.read_exact(&mut len)?;
+  let len = u32::from_be_bytes(len) as usize;
+  if len > MAX_FRAME_SIZE {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "frame too large",
+    ));
+  }
+
+  let mut payload = vec![0u8; len];
+  reader.read_exact(&mut payload)?;
+  Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::types::Keypair,
+    rand::{rngs::StdRng, Rng, SeedableRng},
+  };
+
+  fn records() -> Vec<PeerRecord> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut records: Vec<PeerRecord> = (0..3)
+      .map(|i| {
+        let addresses = (0..i)
+          .map(|port| format!("/ip4/10.0.0.{i}/tcp/{port}").parse().unwrap())
+          .collect();
+        PeerRecord::new(&Keypair::generate(&mut rng), addresses, i as u64)
+      })
+      .collect();
+    // decoding yields the records in the order they are encoded in
+    records.sort_by_key(|record| *record.peer_id());
+    records
+  }
+
+  fn messages() -> Vec<ProtocolMessage> {
+    let keypair = Keypair::generate(&mut StdRng::seed_from_u64(1));
+    vec![
+      ProtocolMessage::Request {
+        id: 1,
+        request: Request::GetPeers,
+      },
+      ProtocolMessage::Response {
+        id: u64::MAX,
+        response: Response::PeerList { peers: vec![] },
+      },
+      ProtocolMessage::Response {
+        id: 2,
+        response: Response::PeerList { peers: records() },
+      },
+      ProtocolMessage::Request {
+        id: 3,
+        request: Request::Application {
+          protocol: 6,
+          payload: vec![],
+        },
+      },
+      ProtocolMessage::Response {
+        id: 3,
+        response: Response::Application {
+          protocol: 6,
+          payload: b"answer".to_vec(),
+        },
+      },
+      ProtocolMessage::Goodbye,
+      ProtocolMessage::Handshake { challenge: [7; 32] },
+      ProtocolMessage::HandshakeResponse {
+        signature: keypair.sign(b"challenge"),
+      },
+      ProtocolMessage::Ping { nonce: 3 },
+      ProtocolMessage::Pong { nonce: 4 },
+      ProtocolMessage::Application {
+        protocol: 5,
+        payload: vec![],
+      },
+      ProtocolMessage::Application {
+        protocol: u16::MAX,
+        payload: b"payload".to_vec(),
+      },
+    ]
+  }
+
+  #[test]
+  fn round_trip() {
+    for message in messages() {
+      let encoded = encode(&message).unwrap();
+      assert_eq!(encoded.len(), message.encoded_size(), "{:?}", message);
+      assert_eq!(decode(&encoded), Ok(message));
+    }
+  }
+
+  #[test]
+  fn truncated_messages_are_rejected() {
+    for message in messages() {
+      let encoded = encode(&message).unwrap();
+      for len in 0..encoded.len() {
+        assert!(decode(&encoded[..len]).is_err(), "{:?} at {}", message, len);
+      }
+    }
+  }
+
+  #[test]
+  fn trailing_bytes_are_rejected() {
+    let mut encoded = encode(&ProtocolMessage::Goodbye).unwrap();
+    encoded.push(0);
+    assert_eq!(decode(&encoded), Err(DecodeError::TrailingBytes(1)));
+  }
+
+  #[test]
+  fn corrupted_payloads_never_panic() {
+    let mut rng = StdRng::seed_from_u64(2);
+    for message in messages() {
+      let encoded = encode(&message).unwrap();
+      for _ in 0..200 {
+        let mut corrupted = encoded.clone();
+        let index =
+          rng.gen_range(HEADER_SIZE.min(corrupted.len() - 1)..corrupted.len());
+        corrupted[index] = rng.gen();
+        let _ = decode(&corrupted);
+      }
+    }
+  }
+
+  #[test]
+  fn malformed_headers_are_rejected() {
+    let mut encoded = encode(&ProtocolMessage::Ping { nonce: 1 }).unwrap();
+
+    encoded[0] = PROTOCOL_VERSION + 1;
+    assert_eq!(
+      decode(&encoded),
+      Err(DecodeError::UnsupportedVersion(PROTOCOL_VERSION + 1))
+    );
+
+    encoded[0] = PROTOCOL_VERSION;
+    encoded[1] = 0;
+    assert_eq!(decode(&encoded), Err(DecodeError::UnknownTag(0)));
+
+    encoded[1] = TAG_PING;
+    encoded[2..HEADER_SIZE].copy_from_slice(&u32::MAX.to_be_bytes());
+    assert_eq!(
+      decode(&encoded),
+      Err(DecodeError::TooLarge(u32::MAX as usize))
+    );
+  }
+
+  #[test]
+  fn unsorted_peer_lists_are_rejected() {
+    let message = ProtocolMessage::Response {
+      id: 0,
+      response: Response::PeerList { peers: records() },
+    };
+    let encoded = encode(&message).unwrap();
+
+    // swap the first two records
+    let records = records();
+    let first = HEADER_SIZE + 9;
+    let second = first + records[0].encoded_size();
+    let third = second + records[1].encoded_size();
+    let mut swapped = encoded[..first].to_vec();
+    swapped.extend_from_slice(&encoded[second..third]);
+    swapped.extend_from_slice(&encoded[first..second]);
+    swapped.extend_from_slice(&encoded[third..]);
+
+    assert!(matches!(
+      decode(&swapped),
+      Err(DecodeError::InvalidPayload(_))
+    ));
+  }
+
+  #[test]
+  fn oversized_messages_are_not_encoded() {
+    let message = ProtocolMessage::Application {
+      protocol: 0,
+      payload: vec![0; MAX_FRAME_SIZE],
+    };
+    assert_eq!(
+      encode(&message),
+      Err(EncodeError::TooLarge(message.encoded_size()))
+    );
+
+    let largest = ProtocolMessage::Application {
+      protocol: 0,
+      payload: vec![0; MAX_FRAME_SIZE - HEADER_SIZE - 2],
+    };
+    let encoded = encode(&largest).unwrap();
+    assert_eq!(encoded.len(), MAX_FRAME_SIZE);
+    assert_eq!(decode(&encoded), Ok(largest));
+  }
+}
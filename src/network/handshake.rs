@@ -0,0 +1,828 @@
+use {
+  super::{
+    DisconnectReason,
+    Network,
+    NetworkError,
+    NetworkEvent,
+    NetworkResult,
+    ProtocolMessage,
+  },
+  crate::{
+    clock::Clock,
+    types::{NodeAddress, NodeIdentity, PeerId, Pubkey, Signature},
+  },
+  futures::FutureExt,
+  rand::Rng,
+  std::{
+    collections::{BTreeMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+  },
+};
+
+/// A connection whose remote has not answered the challenge within this
+/// time is closed.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Separates handshake signatures from anything else signed with the key.
+const DOMAIN: &[u8] = b"c2n-handshake-v1";
+
+// The role of the signer on the connection. The dialer and the listener
+// sign different transcripts, so a peer can not send our own answer back to
+// us as its answer.
+const DIALER: u8 = 0;
+const LISTENER: u8 = 1;
+
+/// The bytes `signer` signs to answer the `challenge` of `verifier`. Both
+/// peer ids and the role of the signer are included, so a signature only
+/// answers challenges of the verifier named in it and only from the role it
+/// names. Nothing in it identifies the connection, a signature relayed over
+/// another connection answers just as well, see `Authenticated`.
+fn transcript(
+  challenge: &[u8; 32],
+  signer: &PeerId,
+  verifier: &PeerId,
+  signer_role: u8,
+) -> Vec<u8> {
+  let mut transcript = Vec::with_capacity(DOMAIN.len() + 3 * 32 + 1);
+  transcript.extend_from_slice(DOMAIN);
+  transcript.extend_from_slice(challenge);
+  transcript.extend_from_slice(&signer.to_bytes());
+  transcript.extend_from_slice(&verifier.to_bytes());
+  transcript.push(signer_role);
+  transcript
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+  Inbound,
+  Outbound,
+}
+
+impl Direction {
+  /// Our role on a connection in this direction.
+  fn local_role(self) -> u8 {
+    match self {
+      Direction::Outbound => DIALER,
+      Direction::Inbound => LISTENER,
+    }
+  }
+
+  /// The role of the remote on a connection in this direction.
+  fn remote_role(self) -> u8 {
+    match self {
+      Direction::Outbound => LISTENER,
+      Direction::Inbound => DIALER,
+    }
+  }
+}
+
+struct PendingHandshake<D> {
+  direction: Direction,
+  challenge: [u8; 32],
+  timeout: D,
+}
+
+/// Wraps a network so only peers proving that they own their peer id get
+/// through. Each side sends a random challenge once a connection is
+/// established and the remote signs it with the key behind its peer id.
+///
+/// Connections are reported to the node once the remote answered our
+/// challenge. A wrong signature or no answer within `HANDSHAKE_TIMEOUT`
+/// closes the connection, a dial then fails. Messages of peers that are not
+/// authenticated yet are dropped. The replacement of a connection is
+/// challenged the same way and only swapped in once its remote answered, a
+/// connection merely claiming the peer id of a connected peer leaves the
+/// existing connection alone.
+///
+/// The handshake proves that the key holder answered a challenge, not that
+/// it is on the other end of the connection. Challenges are answered before
+/// the remote is authenticated and neither side derives a session key, so a
+/// man in the middle between two peers can pass the challenge of one to the
+/// other and the answer back, then read and inject messages on both
+/// connections. Peers that need more than an authenticated peer id have to
+/// run over a transport that is secured on its own.
+pub struct Authenticated<N, C: Clock, R> {
+  network: N,
+  keypair: NodeIdentity,
+  clock: C,
+  rng: R,
+  pending: BTreeMap<PeerId, PendingHandshake<C::Delay>>,
+  // handshakes over the replacements of connections
+  replacing: BTreeMap<PeerId, PendingHandshake<C::Delay>>,
+  // the direction of the connection to each authenticated peer
+  authenticated: BTreeMap<PeerId, Direction>,
+  // events raised while handling an event of the wrapped network
+  events: VecDeque<NetworkEvent>,
+}
+
+impl<N, C, R> Authenticated<N, C, R>
+where
+  N: Network + Unpin,
+  C: Clock,
+  R: Rng + Unpin,
+{
+  pub fn new(network: N, keypair: NodeIdentity, clock: C, rng: R) -> Self {
+    Self {
+      network,
+      keypair,
+      clock,
+      rng,
+      pending: Default::default(),
+      replacing: Default::default(),
+      authenticated: Default::default(),
+      events: Default::default(),
+    }
+  }
+
+  pub fn inner(&self) -> &N {
+    &self.network
+  }
+
+  pub fn inner_mut(&mut self) -> &mut N {
+    &mut self.network
+  }
+
+  pub fn is_authenticated(&self, peer_id: &PeerId) -> bool {
+    self.authenticated.contains_key(peer_id)
+  }
+
+  fn local_peer_id(&self) -> &Pubkey {
+    self.keypair.public()
+  }
+
+  fn handshake(&mut self, direction: Direction) -> PendingHandshake<C::Delay> {
+    PendingHandshake {
+      direction,
+      challenge: self.rng.gen(),
+      timeout: self.clock.delay(HANDSHAKE_TIMEOUT),
+    }
+  }
+
+  fn start(&mut self, peer_id: PeerId, direction: Direction) {
+    let handshake = self.handshake(direction);
+    let challenge = handshake.challenge;
+    self.pending.insert(peer_id, handshake);
+
+    if let Err(err) = self
+      .network
+      .send(peer_id, ProtocolMessage::Handshake { challenge })
+    {
+      tracing::debug!("Failed to send handshake to {}: {}", peer_id, err);
+      self.reject(peer_id);
+    }
+  }
+
+  /// Challenges the replacement of the connection of a peer.
+  fn start_replacement(&mut self, peer_id: PeerId, direction: Direction) {
+    let handshake = self.handshake(direction);
+    let challenge = handshake.challenge;
+    self.replacing.insert(peer_id, handshake);
+
+    if let Err(err) = self
+      .network
+      .send_replacement(peer_id, ProtocolMessage::Handshake { challenge })
+    {
+      tracing::debug!("Failed to send handshake to {}: {}", peer_id, err);
+      self.reject_replacement(peer_id);
+    }
+  }
+
+  fn verify(&mut self, peer_id: PeerId, signature: Signature) {
+    let Some(handshake) = self.pending.get(&peer_id) else {
+      return;
+    };
+
+    if !self.is_answered(&peer_id, handshake, &signature) {
+      tracing::warn!("Peer {} failed to prove its identity", peer_id);
+      self.reject(peer_id);
+      return;
+    }
+
+    let direction = handshake.direction;
+    self.pending.remove(&peer_id);
+    self.authenticated.insert(peer_id, direction);
+    self.events.push_back(established(peer_id, direction));
+  }
+
+  /// Swaps in the replacement of a connection once its remote proved to be
+  /// the peer.
+  fn verify_replacement(&mut self, peer_id: PeerId, signature: Signature) {
+    let Some(handshake) = self.replacing.get(&peer_id) else {
+      return;
+    };
+
+    if !self.is_answered(&peer_id, handshake, &signature) {
+      tracing::warn!("Replacement of {} failed to prove its identity", peer_id);
+      self.reject_replacement(peer_id);
+      return;
+    }
+
+    let direction = handshake.direction;
+    self.replacing.remove(&peer_id);
+    if let Err(err) = self.network.accept_replacement(peer_id) {
+      tracing::debug!("Failed to replace connection of {}: {}", peer_id, err);
+      return;
+    }
+
+    // the old connection is closed, whatever trusted it starts over
+    self.pending.remove(&peer_id);
+    if self.authenticated.insert(peer_id, direction).is_some() {
+      self.events.push_back(NetworkEvent::PeerDisconnected {
+        peer_id,
+        reason: DisconnectReason::TransportError,
+      });
+    }
+    self.events.push_back(established(peer_id, direction));
+  }
+
+  /// Whether `signature` answers the challenge of `handshake`.
+  fn is_answered(
+    &self,
+    peer_id: &PeerId,
+    handshake: &PendingHandshake<C::Delay>,
+    signature: &Signature,
+  ) -> bool {
+    let transcript = transcript(
+      &handshake.challenge,
+      peer_id,
+      self.local_peer_id(),
+      handshake.direction.remote_role(),
+    );
+    peer_id.verify(&transcript, signature)
+  }
+
+  /// Closes the connection of a peer that did not authenticate, a dial to it
+  /// fails.
+  fn reject(&mut self, peer_id: PeerId) {
+    let Some(handshake) = self.pending.remove(&peer_id) else {
+      return;
+    };
+
+    if let Err(err) = self
+      .network
+      .disconnect(peer_id, DisconnectReason::HandshakeFailed)
+    {
+      tracing::debug!("Failed to disconnect {}: {}", peer_id, err);
+    }
+    if handshake.direction == Direction::Outbound {
+      self
+        .events
+        .push_back(NetworkEvent::OutboundFailure { peer_id });
+    }
+  }
+
+  /// Closes the replacement of a connection whose remote did not
+  /// authenticate, the connection itself stays.
+  fn reject_replacement(&mut self, peer_id: PeerId) {
+    if self.replacing.remove(&peer_id).is_none() {
+      return;
+    }
+
+    if let Err(err) = self.network.reject_replacement(peer_id) {
+      tracing::debug!("Failed to reject replacement of {}: {}", peer_id, err);
+    }
+  }
+
+  /// Answers the challenge of a peer we are connected to.
+  fn answer(&mut self, peer_id: PeerId, challenge: [u8; 32]) {
+    let direction = match self.pending.get(&peer_id) {
+      Some(handshake) => Some(handshake.direction),
+      None => self.authenticated.get(&peer_id).copied(),
+    };
+    let Some(direction) = direction else {
+      tracing::debug!("Ignoring challenge of unknown {}", peer_id);
+      return;
+    };
+
+    let response = self.response(&peer_id, &challenge, direction);
+    if let Err(err) = self.network.send(peer_id, response) {
+      tracing::debug!("Failed to answer handshake of {}: {}", peer_id, err);
+    }
+  }
+
+  /// Answers the challenge received over the replacement of a connection.
+  fn answer_replacement(&mut self, peer_id: PeerId, challenge: [u8; 32]) {
+    let Some(direction) = self.replacing.get(&peer_id).map(|h| h.direction)
+    else {
+      tracing::debug!("Ignoring challenge of unknown {}", peer_id);
+      return;
+    };
+
+    let response = self.response(&peer_id, &challenge, direction);
+    if let Err(err) = self.network.send_replacement(peer_id, response) {
+      tracing::debug!("Failed to answer handshake of {}: {}", peer_id, err);
+    }
+  }
+
+  /// Signs the challenge of `peer_id` received over a connection in
+  /// `direction`.
+  fn response(
+    &self,
+    peer_id: &PeerId,
+    challenge: &[u8; 32],
+    direction: Direction,
+  ) -> ProtocolMessage {
+    let transcript = transcript(
+      challenge,
+      self.local_peer_id(),
+      peer_id,
+      direction.local_role(),
+    );
+    ProtocolMessage::HandshakeResponse {
+      signature: self.keypair.sign(&transcript),
+    }
+  }
+
+  fn handle_event(&mut self, event: NetworkEvent) {
+    match event {
+      // nobody else holds our key, a peer claiming it is an impostor
+      NetworkEvent::InboundEstablished { peer_id }
+      | NetworkEvent::OutboundEstablished { peer_id }
+        if peer_id == *self.local_peer_id() =>
+      {
+        tracing::warn!("Rejecting a connection claiming our own peer id");
+        if let Err(err) = self
+          .network
+          .disconnect(peer_id, DisconnectReason::HandshakeFailed)
+        {
+          tracing::debug!("Failed to disconnect {}: {}", peer_id, err);
+        }
+        if matches!(event, NetworkEvent::OutboundEstablished { .. }) {
+          self
+            .events
+            .push_back(NetworkEvent::OutboundFailure { peer_id });
+        }
+      }
+      // a dial that resolved to the existing connection, a new connection is
+      // preceded by the disconnect of the old one
+      NetworkEvent::InboundEstablished { peer_id }
+      | NetworkEvent::OutboundEstablished { peer_id }
+        if self.authenticated.contains_key(&peer_id) =>
+      {
+        self.events.push_back(event)
+      }
+      // the handshake on the existing connection is under way, a new
+      // challenge would invalidate the answer to the first one
+      NetworkEvent::InboundEstablished { peer_id }
+      | NetworkEvent::OutboundEstablished { peer_id }
+        if self.pending.contains_key(&peer_id) => {}
+      NetworkEvent::InboundEstablished { peer_id } => {
+        self.start(peer_id, Direction::Inbound)
+      }
+      NetworkEvent::OutboundEstablished { peer_id } => {
+        self.start(peer_id, Direction::Outbound)
+      }
+      NetworkEvent::MessageReceived {
+        peer_id,
+        message: ProtocolMessage::Handshake { challenge },
+      } => self.answer(peer_id, challenge),
+      NetworkEvent::MessageReceived {
+        peer_id,
+        message: ProtocolMessage::HandshakeResponse { signature },
+      } => self.verify(peer_id, signature),
+      NetworkEvent::MessageReceived { peer_id, message } => {
+        if self.authenticated.contains_key(&peer_id) {
+          self
+            .events
+            .push_back(NetworkEvent::MessageReceived { peer_id, message });
+        } else {
+          tracing::debug!("Dropping message of unauthenticated {}", peer_id);
+        }
+      }
+      NetworkEvent::PeerDisconnected { peer_id, reason } => {
+        // the replacement takes the place of the connection, the handshake
+        // over it carries on and the established event for it is swallowed
+        // until the remote answered
+        let replacement = self.replacing.remove(&peer_id);
+        if self.authenticated.remove(&peer_id).is_some() {
+          self
+            .events
+            .push_back(NetworkEvent::PeerDisconnected { peer_id, reason });
+        } else if let Some(handshake) = self.pending.remove(&peer_id) {
+          // the node never learned about the connection, only a dial fails
+          if handshake.direction == Direction::Outbound {
+            self
+              .events
+              .push_back(NetworkEvent::OutboundFailure { peer_id });
+          }
+        }
+        if let Some(replacement) = replacement {
+          self.pending.insert(peer_id, replacement);
+        }
+      }
+      NetworkEvent::OutboundFailure { peer_id } => self
+        .events
+        .push_back(NetworkEvent::OutboundFailure { peer_id }),
+      NetworkEvent::ReplacementEstablished { peer_id, outbound } => {
+        let direction = match outbound {
+          true => Direction::Outbound,
+          false => Direction::Inbound,
+        };
+        self.start_replacement(peer_id, direction)
+      }
+      NetworkEvent::ReplacementMessage {
+        peer_id,
+        message: ProtocolMessage::Handshake { challenge },
+      } => self.answer_replacement(peer_id, challenge),
+      NetworkEvent::ReplacementMessage {
+        peer_id,
+        message: ProtocolMessage::HandshakeResponse { signature },
+      } => self.verify_replacement(peer_id, signature),
+      NetworkEvent::ReplacementMessage { peer_id, .. } => {
+        tracing::debug!("Dropping message of the replacement of {}", peer_id);
+      }
+      NetworkEvent::ReplacementClosed { peer_id } => {
+        self.replacing.remove(&peer_id);
+      }
+    }
+  }
+}
+
+fn established(peer_id: PeerId, direction: Direction) -> NetworkEvent {
+  match direction {
+    Direction::Inbound => NetworkEvent::InboundEstablished { peer_id },
+    Direction::Outbound => NetworkEvent::OutboundEstablished { peer_id },
+  }
+}
+
+impl<N, C, R> Future for Authenticated<N, C, R>
+where
+  N: Network + Unpin,
+  C: Clock,
+  R: Rng + Unpin,
+{
+  type Output = NetworkEvent;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+
+    loop {
+      if let Some(event) = this.events.pop_front() {
+        return Poll::Ready(event);
+      }
+
+      let expired: Vec<PeerId> = this
+        .pending
+        .iter_mut()
+        .filter_map(|(peer_id, handshake)| {
+          handshake
+            .timeout
+            .poll_unpin(cx)
+            .is_ready()
+            .then_some(*peer_id)
+        })
+        .collect();
+      for peer_id in expired {
+        tracing::debug!("Handshake with {} timed out", peer_id);
+        this.reject(peer_id);
+      }
+      let expired: Vec<PeerId> = this
+        .replacing
+        .iter_mut()
+        .filter_map(|(peer_id, handshake)| {
+          handshake
+            .timeout
+            .poll_unpin(cx)
+            .is_ready()
+            .then_some(*peer_id)
+        })
+        .collect();
+      for peer_id in expired {
+        tracing::debug!("Handshake with replacement of {} timed out", peer_id);
+        this.reject_replacement(peer_id);
+      }
+      if !this.events.is_empty() {
+        continue;
+      }
+
+      match this.network.poll_unpin(cx) {
+        Poll::Ready(event) => this.handle_event(event),
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+  }
+}
+
+impl<N, C, R> Network for Authenticated<N, C, R>
+where
+  N: Network + Unpin,
+  C: Clock,
+  R: Rng + Unpin,
+{
+  fn address(&self) -> &NodeAddress {
+    self.network.address()
+  }
+
+  fn add_peer(&mut self, peer_id: Pubkey, addr: NodeAddress) {
+    self.network.add_peer(peer_id, addr)
+  }
+
+  fn connect(&mut self, peer_id: PeerId) -> NetworkResult<()> {
+    if self.pending.contains_key(&peer_id)
+      || self.authenticated.contains_key(&peer_id)
+    {
+      return Err(NetworkError::AlreadyConnected(peer_id));
+    }
+    self.network.connect(peer_id)
+  }
+
+  fn disconnect(
+    &mut self,
+    peer_id: PeerId,
+    reason: DisconnectReason,
+  ) -> NetworkResult<()> {
+    if !self.authenticated.contains_key(&peer_id) {
+      return Err(NetworkError::NotConnected);
+    }
+    // the network closes the replacement along with the connection
+    self.replacing.remove(&peer_id);
+    self.network.disconnect(peer_id, reason)
+  }
+
+  fn send(
+    &mut self,
+    peer_id: PeerId,
+    message: ProtocolMessage,
+  ) -> NetworkResult<()> {
+    if !self.authenticated.contains_key(&peer_id) {
+      return Err(NetworkError::NotConnected);
+    }
+    self.network.send(peer_id, message)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::{
+      clock::wall::WallClock,
+      network::{
+        codec,
+        tcp::{self, TcpNetwork},
+      },
+    },
+    futures::{
+      executor::block_on,
+      future::{select, Either},
+      FutureExt,
+    },
+    futures_timer::Delay,
+    rand::{rngs::StdRng, SeedableRng},
+    std::{io::Write, net::TcpStream},
+  };
+
+  type TcpAuthenticated = Authenticated<TcpNetwork, WallClock, StdRng>;
+
+  fn bind(seed: u64) -> (TcpAuthenticated, NodeIdentity) {
+    let keypair = NodeIdentity::generate(&mut StdRng::seed_from_u64(seed));
+    let address = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    let network = TcpNetwork::bind((*keypair.public(), address)).unwrap();
+    let authenticated = Authenticated::new(
+      network,
+      keypair.clone(),
+      WallClock::new(),
+      StdRng::seed_from_u64(seed),
+    );
+    (authenticated, keypair)
+  }
+
+  /// Polls both networks until one of them emits an event, `None` when
+  /// nothing happens for `timeout`.
+  fn next_event(
+    a: &mut TcpAuthenticated,
+    b: &mut TcpAuthenticated,
+    timeout: Duration,
+  ) -> Option<(bool, NetworkEvent)> {
+    block_on(async {
+      let either = select(&mut *a, &mut *b);
+      match select(either, Delay::new(timeout)).await {
+        Either::Left((Either::Left((event, _)), _)) => Some((true, event)),
+        Either::Left((Either::Right((event, _)), _)) => Some((false, event)),
+        Either::Right(_) => None,
+      }
+    })
+  }
+
+  fn write_frame(stream: &mut TcpStream, payload: &[u8]) {
+    stream
+      .write_all(&(payload.len() as u32).to_be_bytes())
+      .unwrap();
+    stream.write_all(payload).unwrap();
+  }
+
+  /// A network whose events are scripted by the test, recording what the
+  /// wrapper does with it.
+  struct Scripted {
+    address: NodeAddress,
+    events: VecDeque<NetworkEvent>,
+    sent: Vec<(PeerId, ProtocolMessage)>,
+    disconnected: Vec<(PeerId, DisconnectReason)>,
+  }
+
+  impl Future for Scripted {
+    type Output = NetworkEvent;
+
+    fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Self::Output> {
+      self
+        .get_mut()
+        .events
+        .pop_front()
+        .map_or(Poll::Pending, Poll::Ready)
+    }
+  }
+
+  impl Network for Scripted {
+    fn address(&self) -> &NodeAddress {
+      &self.address
+    }
+
+    fn add_peer(&mut self, _: Pubkey, _: NodeAddress) {}
+
+    fn connect(&mut self, _: PeerId) -> NetworkResult<()> {
+      Ok(())
+    }
+
+    fn disconnect(
+      &mut self,
+      peer_id: PeerId,
+      reason: DisconnectReason,
+    ) -> NetworkResult<()> {
+      self.disconnected.push((peer_id, reason));
+      Ok(())
+    }
+
+    fn send(
+      &mut self,
+      peer_id: PeerId,
+      message: ProtocolMessage,
+    ) -> NetworkResult<()> {
+      self.sent.push((peer_id, message));
+      Ok(())
+    }
+  }
+
+  fn scripted(
+    keypair: &NodeIdentity,
+    events: Vec<NetworkEvent>,
+  ) -> Authenticated<Scripted, WallClock, StdRng> {
+    let network = Scripted {
+      address: (*keypair.public(), "/memory/1".parse().unwrap()),
+      events: events.into(),
+      sent: Vec::new(),
+      disconnected: Vec::new(),
+    };
+    Authenticated::new(
+      network,
+      keypair.clone(),
+      WallClock::new(),
+      StdRng::seed_from_u64(0),
+    )
+  }
+
+  #[test]
+  fn own_answer_does_not_pass_as_the_answer_of_the_remote() {
+    let keypair = NodeIdentity::generate(&mut StdRng::seed_from_u64(1));
+    let id = *keypair.public();
+    let challenge = [7; 32];
+
+    // what a peer claiming our id gets from us when it reflects our
+    // challenge, and what we check its answer against
+    let ours = keypair.sign(&transcript(&challenge, &id, &id, DIALER));
+    assert!(id.verify(&transcript(&challenge, &id, &id, DIALER), &ours));
+    assert!(!id.verify(&transcript(&challenge, &id, &id, LISTENER), &ours));
+  }
+
+  #[test]
+  fn peer_claiming_our_peer_id_is_rejected() {
+    let keypair = NodeIdentity::generate(&mut StdRng::seed_from_u64(1));
+    let id = *keypair.public();
+    let mut network = scripted(&keypair, vec![
+      NetworkEvent::InboundEstablished { peer_id: id },
+      NetworkEvent::OutboundEstablished { peer_id: id },
+    ]);
+
+    // only the dial fails, the node never hears of the connections
+    assert!(matches!(
+      (&mut network).now_or_never(),
+      Some(NetworkEvent::OutboundFailure { peer_id }) if peer_id == id
+    ));
+    assert!((&mut network).now_or_never().is_none());
+    assert_eq!(network.inner().disconnected, vec![
+      (id, DisconnectReason::HandshakeFailed),
+      (id, DisconnectReason::HandshakeFailed),
+    ]);
+    assert!(network.inner().sent.is_empty());
+  }
+
+  #[test]
+  fn tcp_connection_claiming_our_peer_id_is_closed() {
+    let (mut a, a_keypair) = bind(1);
+    let (mut b, _) = bind(2);
+
+    let addr = tcp::socket_addr(&a.inner().address().1).unwrap();
+    let mut attacker = TcpStream::connect(addr).unwrap();
+    write_frame(&mut attacker, &a_keypair.public().to_bytes());
+
+    // a sends its own peer id, then closes the connection
+    attacker
+      .set_read_timeout(Some(Duration::from_secs(5)))
+      .unwrap();
+    codec::read_frame(&mut attacker).unwrap();
+    assert!(codec::read_frame(&mut attacker).is_err());
+    assert!(next_event(&mut a, &mut b, Duration::from_millis(500)).is_none());
+  }
+
+  /// Connects `a` to `b` and waits until both authenticated each other.
+  fn connect(a: &mut TcpAuthenticated, b: &mut TcpAuthenticated) {
+    let b_id = *b.local_peer_id();
+    a.add_peer(b_id, b.inner().address().clone());
+    a.connect(b_id).unwrap();
+    let mut established = 0;
+    while established < 2 {
+      match next_event(a, b, Duration::from_secs(5)) {
+        Some((_, NetworkEvent::OutboundEstablished { .. }))
+        | Some((_, NetworkEvent::InboundEstablished { .. })) => {
+          established += 1
+        }
+        event => panic!("unexpected event {:?}", event),
+      }
+    }
+  }
+
+  #[test]
+  fn hijacked_session_is_not_trusted() {
+    let (mut a, _) = bind(1);
+    let (mut b, _) = bind(2);
+    connect(&mut a, &mut b);
+    let a_id = *a.local_peer_id();
+    let b_id = *b.local_peer_id();
+    assert!(b.is_authenticated(&a_id));
+
+    // a second connection to b claiming to be a, without its key
+    let addr = tcp::socket_addr(&b.inner().address().1).unwrap();
+    let mut attacker = TcpStream::connect(addr).unwrap();
+    write_frame(&mut attacker, &a_id.to_bytes());
+    let message = ProtocolMessage::Ping { nonce: 666 };
+    write_frame(&mut attacker, &codec::encode(&message).unwrap());
+
+    if let Some((_, event)) =
+      next_event(&mut a, &mut b, Duration::from_millis(500))
+    {
+      panic!("unexpected event {:?}", event);
+    }
+    assert!(b.is_authenticated(&a_id));
+
+    // the session of a carries on
+    a.send(b_id, ProtocolMessage::Ping { nonce: 7 }).unwrap();
+    assert!(matches!(
+      next_event(&mut a, &mut b, Duration::from_secs(5)),
+      Some((false, NetworkEvent::MessageReceived {
+        peer_id,
+        message: ProtocolMessage::Ping { nonce: 7 },
+      })) if peer_id == a_id
+    ));
+    drop(attacker);
+  }
+
+  #[test]
+  fn restarted_peer_replaces_its_connection() {
+    let (mut a, _) = bind(1);
+    let (mut b, _) = bind(2);
+    connect(&mut a, &mut b);
+    let a_id = *a.local_peer_id();
+
+    // a comes back on another port while b still holds the old connection,
+    // which b closes once the new one is authenticated
+    let (mut restarted, _) = bind(1);
+    let b_id = *b.local_peer_id();
+    restarted.add_peer(b_id, b.inner().address().clone());
+    restarted.connect(b_id).unwrap();
+    let mut events = Vec::new();
+    while events.len() < 3 {
+      match next_event(&mut restarted, &mut b, Duration::from_secs(5)) {
+        Some((from_restarted, event)) => events.push((from_restarted, event)),
+        None => panic!("only got {:?}", events),
+      }
+    }
+    let b_events: Vec<_> = events.iter().filter(|(r, _)| !r).collect();
+    assert!(matches!(b_events[..], [
+      (_, NetworkEvent::PeerDisconnected { peer_id: first, .. }),
+      (_, NetworkEvent::InboundEstablished { peer_id: second }),
+    ] if *first == a_id && *second == a_id));
+    assert!(b.is_authenticated(&a_id));
+    drop(a);
+
+    b.send(a_id, ProtocolMessage::Ping { nonce: 7 }).unwrap();
+    assert!(matches!(
+      next_event(&mut restarted, &mut b, Duration::from_secs(5)),
+      Some((true, NetworkEvent::MessageReceived {
+        peer_id,
+        message: ProtocolMessage::Ping { nonce: 7 },
+      })) if peer_id == b_id
+    ));
+  }
+}
@@ -1,63 +1,394 @@
 use {
-  super::{NetworkResult, ProtocolMessage},
+  super::{DisconnectReason, NetworkError, NetworkResult, ProtocolMessage},
   crate::{
     network::{Network, NetworkEvent},
     primitives::Pubkey,
     types::{NodeAddress, PeerId},
   },
   futures::Future,
+  multiaddr::Multiaddr,
   std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap, VecDeque},
     pin::Pin,
-    task::{Context, Poll},
+    rc::Rc,
+    task::{Context, Poll, Waker},
   },
 };
 
-pub struct MemoryNetwork {
-  // events: VecDeque<NetworkEvent>,
+type RcNetworkEventQueue = Rc<RefCell<EventQueue>>;
+
+/// Events waiting for an endpoint. Pushing an event wakes the task that last
+/// found the queue empty.
+#[derive(Default)]
+struct EventQueue {
+  events: VecDeque<NetworkEvent>,
+  waker: Option<Waker>,
 }
 
-impl Default for MemoryNetwork {
-  fn default() -> Self {
-    Self::new()
+impl EventQueue {
+  fn push_back(&mut self, event: NetworkEvent) {
+    self.events.push_back(event);
+    if let Some(waker) = self.waker.take() {
+      waker.wake();
+    }
+  }
+
+  fn poll_pop(&mut self, cx: &mut Context<'_>) -> Poll<NetworkEvent> {
+    match self.events.pop_front() {
+      Some(event) => Poll::Ready(event),
+      None => {
+        self.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
   }
 }
 
-impl MemoryNetwork {
+struct Endpoint {
+  address: Multiaddr,
+  events: RcNetworkEventQueue,
+}
+
+#[derive(Default)]
+struct MemoryHubState {
+  endpoints: HashMap<PeerId, Endpoint>,
+  addresses: HashMap<Multiaddr, PeerId>,
+  // established connections, ordered as (lower, higher) peer id
+  connections: BTreeSet<(PeerId, PeerId)>,
+}
+
+impl MemoryHubState {
+  fn push_event(&self, peer_id: &PeerId, event: NetworkEvent) {
+    if let Some(endpoint) = self.endpoints.get(peer_id) {
+      endpoint.events.borrow_mut().push_back(event);
+    }
+  }
+
+  /// Drops every connection of `peer_id`, the remotes learn right away that
+  /// the connection is gone.
+  fn sever(&mut self, peer_id: PeerId) {
+    let connections: Vec<(PeerId, PeerId)> = self
+      .connections
+      .iter()
+      .filter(|(a, b)| *a == peer_id || *b == peer_id)
+      .copied()
+      .collect();
+
+    for (a, b) in connections {
+      self.connections.remove(&(a, b));
+      let remote_peer_id = if a == peer_id { b } else { a };
+      self.push_event(&remote_peer_id, NetworkEvent::PeerDisconnected {
+        peer_id,
+        reason: DisconnectReason::TransportError,
+      });
+    }
+  }
+}
+
+/// In-process switchboard the memory networks connect through. Connections
+/// are established instantly and messages are delivered in the order they
+/// were sent, there is no randomness involved.
+#[derive(Clone, Default)]
+pub struct MemoryHub(Rc<RefCell<MemoryHubState>>);
+
+impl MemoryHub {
   pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Resolves a `/memory/N` address to the peer listening on it.
+  pub fn resolve(&self, address: &Multiaddr) -> Option<PeerId> {
+    self.0.borrow().addresses.get(address).copied()
+  }
+
+  pub fn is_connected(&self, a: &PeerId, b: &PeerId) -> bool {
+    self
+      .0
+      .borrow()
+      .connections
+      .contains(&connection_key(*a, *b))
+  }
+}
+
+fn connection_key(a: PeerId, b: PeerId) -> (PeerId, PeerId) {
+  (a.min(b), a.max(b))
+}
+
+/// A network endpoint attached to a `MemoryHub`.
+pub struct MemoryNetwork {
+  address: NodeAddress,
+  hub: MemoryHub,
+  events: RcNetworkEventQueue,
+  // addresses learned through `add_peer`
+  peers: HashMap<PeerId, Multiaddr>,
+}
+
+impl MemoryNetwork {
+  /// Attaches a new endpoint listening on `address` to the hub. An endpoint
+  /// with the same peer id is replaced and loses its connections.
+  pub fn new(hub: &MemoryHub, address: NodeAddress) -> Self {
+    let events: RcNetworkEventQueue = Default::default();
+
+    let mut state = hub.0.borrow_mut();
+    let (peer_id, multiaddr) = address.clone();
+    state.sever(peer_id);
+    state.addresses.insert(multiaddr.clone(), peer_id);
+    state.endpoints.insert(peer_id, Endpoint {
+      address: multiaddr,
+      events: Rc::clone(&events),
+    });
+    drop(state);
+
     MemoryNetwork {
-      // events: VecDeque::new(),
+      address,
+      hub: hub.clone(),
+      events,
+      peers: Default::default(),
     }
   }
+
+  pub fn peer_id(&self) -> PeerId {
+    self.address.0
+  }
+
+  /// Whether the dial to `peer_id` reaches it, taking the address learned
+  /// through `add_peer` into account.
+  fn is_reachable(&self, state: &MemoryHubState, peer_id: &PeerId) -> bool {
+    let Some(endpoint) = state.endpoints.get(peer_id) else {
+      return false;
+    };
+
+    self
+      .peers
+      .get(peer_id)
+      .is_none_or(|address| *address == endpoint.address)
+  }
 }
 
 impl Future for MemoryNetwork {
   type Output = NetworkEvent;
 
-  fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
-    Poll::Pending
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    self.events.borrow_mut().poll_pop(cx)
   }
 }
 
 impl Network for MemoryNetwork {
-  fn connect(&mut self, _peer_id: PeerId) -> NetworkResult<()> {
-    // Simulate connecting to a peer
+  fn address(&self) -> &NodeAddress {
+    &self.address
+  }
+
+  fn connect(&mut self, peer_id: PeerId) -> NetworkResult<()> {
+    let local_peer_id = self.peer_id();
+    let mut state = self.hub.0.borrow_mut();
+    let key = connection_key(local_peer_id, peer_id);
+    if state.connections.contains(&key) {
+      return Err(NetworkError::AlreadyConnected(peer_id));
+    }
+
+    if peer_id == local_peer_id || !self.is_reachable(&state, &peer_id) {
+      state
+        .push_event(&local_peer_id, NetworkEvent::OutboundFailure { peer_id });
+      return Ok(());
+    }
+
+    state.connections.insert(key);
+    state.push_event(&local_peer_id, NetworkEvent::OutboundEstablished {
+      peer_id,
+    });
+    state.push_event(&peer_id, NetworkEvent::InboundEstablished {
+      peer_id: local_peer_id,
+    });
+
     Ok(())
   }
 
-  fn disconnect(&mut self, peer_id: PeerId) -> NetworkResult<()> {
+  fn disconnect(
+    &mut self,
+    peer_id: PeerId,
+    reason: DisconnectReason,
+  ) -> NetworkResult<()> {
+    let local_peer_id = self.peer_id();
+    let mut state = self.hub.0.borrow_mut();
+    if !state
+      .connections
+      .remove(&connection_key(local_peer_id, peer_id))
+    {
+      return Err(NetworkError::NotConnected);
+    }
+
+    state.push_event(&local_peer_id, NetworkEvent::PeerDisconnected {
+      peer_id,
+      reason,
+    });
+    state.push_event(&peer_id, NetworkEvent::PeerDisconnected {
+      peer_id: local_peer_id,
+      reason: DisconnectReason::RemoteRequest,
+    });
+
     Ok(())
   }
 
-  fn add_peer(&mut self, _peer_id: Pubkey, _addr: NodeAddress) {
-    // Simulate adding a peer
+  fn add_peer(&mut self, peer_id: Pubkey, addr: NodeAddress) {
+    tracing::debug!("Adding peer_id: {:?} with address: {:?}", peer_id, addr);
+    self.peers.insert(peer_id, addr.1);
   }
 
   fn send(
     &mut self,
-    _peer_id: PeerId,
-    _message: ProtocolMessage,
+    peer_id: PeerId,
+    message: ProtocolMessage,
   ) -> NetworkResult<()> {
-    // Simulate sending a message
+    let local_peer_id = self.peer_id();
+    let state = self.hub.0.borrow();
+    if !state
+      .connections
+      .contains(&connection_key(local_peer_id, peer_id))
+    {
+      return Err(NetworkError::NotConnected);
+    }
+
+    state.push_event(&peer_id, NetworkEvent::MessageReceived {
+      peer_id: local_peer_id,
+      message,
+    });
+
     Ok(())
   }
 }
+
+impl Drop for MemoryNetwork {
+  fn drop(&mut self) {
+    let local_peer_id = self.peer_id();
+    let Ok(mut state) = self.hub.0.try_borrow_mut() else {
+      return;
+    };
+
+    // a newer endpoint with the same peer id has taken over
+    let is_registered = state
+      .endpoints
+      .get(&local_peer_id)
+      .is_some_and(|endpoint| Rc::ptr_eq(&endpoint.events, &self.events));
+    if !is_registered {
+      return;
+    }
+
+    state.endpoints.remove(&local_peer_id);
+    state.addresses.remove(&self.address.1);
+    state.sever(local_peer_id);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::types::Keypair,
+    futures::FutureExt,
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  fn endpoint(hub: &MemoryHub, seed: u64) -> MemoryNetwork {
+    let keypair = Keypair::generate(&mut StdRng::seed_from_u64(seed));
+    let address = format!("/memory/{seed}").parse().unwrap();
+    MemoryNetwork::new(hub, (*keypair.public(), address))
+  }
+
+  fn next_event(network: &mut MemoryNetwork) -> Option<NetworkEvent> {
+    network.now_or_never()
+  }
+
+  #[test]
+  fn connect_send_and_disconnect() {
+    let hub = MemoryHub::new();
+    let mut a = endpoint(&hub, 1);
+    let mut b = endpoint(&hub, 2);
+    let (a_id, b_id) = (a.peer_id(), b.peer_id());
+
+    a.connect(b_id).unwrap();
+    assert!(hub.is_connected(&a_id, &b_id));
+    assert!(matches!(
+      next_event(&mut a),
+      Some(NetworkEvent::OutboundEstablished { peer_id }) if peer_id == b_id
+    ));
+    assert!(matches!(
+      next_event(&mut b),
+      Some(NetworkEvent::InboundEstablished { peer_id }) if peer_id == a_id
+    ));
+    assert!(matches!(
+      a.connect(b_id),
+      Err(NetworkError::AlreadyConnected(_))
+    ));
+
+    a.send(b_id, ProtocolMessage::Ping { nonce: 1 }).unwrap();
+    a.send(b_id, ProtocolMessage::Ping { nonce: 2 }).unwrap();
+    for expected in [1, 2] {
+      assert!(matches!(
+        next_event(&mut b),
+        Some(NetworkEvent::MessageReceived {
+          peer_id,
+          message: ProtocolMessage::Ping { nonce },
+        }) if peer_id == a_id && nonce == expected
+      ));
+    }
+
+    b.disconnect(a_id, DisconnectReason::LocalRequest).unwrap();
+    assert!(!hub.is_connected(&a_id, &b_id));
+    assert!(matches!(
+      next_event(&mut a),
+      Some(NetworkEvent::PeerDisconnected {
+        reason: DisconnectReason::RemoteRequest,
+        ..
+      })
+    ));
+    assert!(matches!(
+      next_event(&mut b),
+      Some(NetworkEvent::PeerDisconnected {
+        reason: DisconnectReason::LocalRequest,
+        ..
+      })
+    ));
+    assert!(matches!(
+      a.send(b_id, ProtocolMessage::Goodbye),
+      Err(NetworkError::NotConnected)
+    ));
+    assert!(next_event(&mut a).is_none());
+  }
+
+  #[test]
+  fn dialing_a_wrong_address_fails() {
+    let hub = MemoryHub::new();
+    let mut a = endpoint(&hub, 1);
+    let b = endpoint(&hub, 2);
+
+    a.add_peer(b.peer_id(), (b.peer_id(), "/memory/3".parse().unwrap()));
+    a.connect(b.peer_id()).unwrap();
+    assert!(matches!(
+      next_event(&mut a),
+      Some(NetworkEvent::OutboundFailure { .. })
+    ));
+    assert!(!hub.is_connected(&a.peer_id(), &b.peer_id()));
+  }
+
+  #[test]
+  fn dropping_an_endpoint_severs_its_connections() {
+    let hub = MemoryHub::new();
+    let mut a = endpoint(&hub, 1);
+    let b = endpoint(&hub, 2);
+    let b_id = b.peer_id();
+
+    a.connect(b_id).unwrap();
+    next_event(&mut a);
+    drop(b);
+
+    assert!(matches!(
+      next_event(&mut a),
+      Some(NetworkEvent::PeerDisconnected {
+        peer_id,
+        reason: DisconnectReason::TransportError,
+      }) if peer_id == b_id
+    ));
+    assert_eq!(hub.resolve(&"/memory/2".parse().unwrap()), None);
+  }
+}
@@ -0,0 +1,135 @@
+use {
+  crate::types::{Keypair, NodeAddress, PeerId, Signature},
+  multiaddr::Multiaddr,
+};
+
+/// A record carries at most this many addresses.
+pub const MAX_ADDRESSES: usize = 8;
+
+// Separates record signatures from anything else signed with the key.
+const DOMAIN: &[u8] = b"c2n-peer-record-v1";
+
+/// The addresses a peer can be dialed on, signed by the peer itself so no
+/// one else can make up addresses for it. A record with a higher sequence
+/// number replaces an older one of the same peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerRecord {
+  peer_id: PeerId,
+  addresses: Vec<Multiaddr>,
+  seq: u64,
+  signature: Signature,
+}
+
+impl PeerRecord {
+  /// Creates a record for the peer owning `keypair`. Addresses beyond
+  /// `MAX_ADDRESSES` are left out.
+  pub fn new(
+    keypair: &Keypair,
+    mut addresses: Vec<Multiaddr>,
+    seq: u64,
+  ) -> Self {
+    addresses.retain(|address| address.as_ref().len() <= u16::MAX as usize);
+    addresses.truncate(MAX_ADDRESSES);
+    let peer_id = *keypair.public();
+
+    let mut signed = DOMAIN.to_vec();
+    write_body(&mut signed, &peer_id, &addresses, seq);
+    let signature = keypair.sign(&signed);
+
+    PeerRecord {
+      peer_id,
+      addresses,
+      seq,
+      signature,
+    }
+  }
+
+  /// Assembles a record received from the network, use `verify` before
+  /// trusting it.
+  pub(crate) fn from_parts(
+    peer_id: PeerId,
+    addresses: Vec<Multiaddr>,
+    seq: u64,
+    signature: Signature,
+  ) -> Self {
+    PeerRecord {
+      peer_id,
+      addresses,
+      seq,
+      signature,
+    }
+  }
+
+  pub fn peer_id(&self) -> &PeerId {
+    &self.peer_id
+  }
+
+  pub fn addresses(&self) -> &[Multiaddr] {
+    &self.addresses
+  }
+
+  pub fn seq(&self) -> u64 {
+    self.seq
+  }
+
+  pub fn signature(&self) -> &Signature {
+    &self.signature
+  }
+
+  /// The address to hand to the network, the first one in the record.
+  pub fn node_address(&self) -> Option<NodeAddress> {
+    let address = self.addresses.first()?;
+    Some((self.peer_id, address.clone()))
+  }
+
+  /// Whether the record has been signed by the peer it describes.
+  pub fn verify(&self) -> bool {
+    if self.addresses.len() > MAX_ADDRESSES {
+      return false;
+    }
+
+    let mut signed = DOMAIN.to_vec();
+    self.write_body(&mut signed);
+    self.peer_id.verify(&signed, &self.signature)
+  }
+
+  /// Writes everything but the signature in the layout of
+  /// `codec::encode`.
+  pub(crate) fn write_body(&self, buf: &mut Vec<u8>) {
+    write_body(buf, &self.peer_id, &self.addresses, self.seq);
+  }
+
+  /// The number of bytes the record takes up on the wire: the peer id, the
+  /// sequence number, the number of addresses, every address prefixed with
+  /// its two byte length and the signature.
+  pub fn encoded_size(&self) -> usize {
+    const PEER_ID: usize = 32;
+    const SEQ: usize = 8;
+    const COUNT: usize = 1;
+    const LENGTH: usize = 2;
+    const SIGNATURE: usize = 64;
+
+    let addresses: usize = self
+      .addresses
+      .iter()
+      .map(|address| LENGTH + address.as_ref().len())
+      .sum();
+    PEER_ID + SEQ + COUNT + addresses + SIGNATURE
+  }
+}
+
+fn write_body(
+  buf: &mut Vec<u8>,
+  peer_id: &PeerId,
+  addresses: &[Multiaddr],
+  seq: u64,
+) {
+  buf.extend_from_slice(&peer_id.to_bytes());
+  buf.extend_from_slice(&seq.to_be_bytes());
+  buf.push(addresses.len() as u8);
+  for address in addresses {
+    let bytes = address.as_ref();
+    buf.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    buf.extend_from_slice(bytes);
+  }
+}
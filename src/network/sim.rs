@@ -1,6 +1,14 @@
+pub mod fault;
+pub mod latency;
+
 use {
-  super::{NetworkEvent, NetworkResult, ProtocolMessage},
+  self::{
+    fault::{LinkFaultConfig, NetworkFault, Partition, PartitionMode},
+    latency::LinkLatency,
+  },
+  super::{DisconnectReason, NetworkEvent, NetworkResult, ProtocolMessage},
   crate::{
+    clock::{sim::SimClock, Clock},
     network::{Network, NetworkError},
     primitives::Pubkey,
     types::{NodeAddress, PeerId},
@@ -12,15 +20,14 @@ use {
     FutureExt,
     StreamExt,
   },
-  futures_timer::Delay,
   rand::Rng,
   std::{
     cell::RefCell,
-    collections::{HashMap, VecDeque},
+    collections::{BTreeSet, HashMap, VecDeque},
     ops::Range,
     pin::Pin,
     rc::Rc,
-    task::{Context, Poll},
+    task::{Context, Poll, Waker},
     time::Duration,
   },
 };
@@ -28,14 +35,64 @@ use {
 // TODO: The protocol message should include a from PeerId to indicate the
 // origin of the message. Once this is added, we will be able to track the
 // communication between peers.
-type RcProtocolMessageQueue = Rc<RefCell<VecDeque<(PeerId, ProtocolMessage)>>>;
-type RcSimNetworkEventQueue = Rc<RefCell<VecDeque<(PeerId, SimNetworkEvent)>>>;
+type RcProtocolMessageQueue = Rc<RefCell<SimQueue<(PeerId, ProtocolMessage)>>>;
+type RcSimNetworkEventQueue = Rc<RefCell<SimQueue<(PeerId, SimNetworkEvent)>>>;
+
+/// Queue shared between the simulation network and its clients. Pushing an
+/// item wakes the task that last found the queue empty.
+pub struct SimQueue<T> {
+  items: VecDeque<T>,
+  waker: Option<Waker>,
+}
+
+impl<T> Default for SimQueue<T> {
+  fn default() -> Self {
+    SimQueue {
+      items: VecDeque::new(),
+      waker: None,
+    }
+  }
+}
+
+impl<T> SimQueue<T> {
+  pub fn push_back(&mut self, item: T) {
+    self.items.push_back(item);
+    if let Some(waker) = self.waker.take() {
+      waker.wake();
+    }
+  }
+
+  /// Pops the next item, or registers the task to be woken on the next push.
+  pub fn poll_pop(&mut self, cx: &mut Context<'_>) -> Poll<T> {
+    match self.items.pop_front() {
+      Some(item) => Poll::Ready(item),
+      None => {
+        self.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+}
 
 // Configuration for the simulation network.
 #[derive(Clone)]
 pub struct SimNetworkConfig {
   connection_delay: Range<Duration>,
   connection_fail_prob: f64,
+  /// How long it takes a peer to notice that the other side of a connection
+  /// crashed.
+  crash_detection_timeout: Duration,
+  /// Upload capacity of the node in bytes per second, unlimited when `None`.
+  upload_bandwidth: Option<u64>,
+  /// Download capacity of the node in bytes per second, unlimited when
+  /// `None`.
+  download_bandwidth: Option<u64>,
+  /// Probability that the remote is not told about a disconnect initiated by
+  /// this node, leaving it with a half-open connection.
+  half_open_prob: f64,
+  /// How long a half-open connection lasts before the keepalive of the
+  /// remote fails.
+  keepalive_timeout: Duration,
 }
 
 impl Default for SimNetworkConfig {
@@ -43,18 +100,80 @@ impl Default for SimNetworkConfig {
     SimNetworkConfig {
       connection_delay: Duration::from_millis(100)..Duration::from_millis(2000),
       connection_fail_prob: 0.1,
+      crash_detection_timeout: Duration::from_secs(5),
+      upload_bandwidth: None,
+      download_bandwidth: None,
+      half_open_prob: 0.0,
+      keepalive_timeout: Duration::from_secs(10),
     }
   }
 }
 
+impl SimNetworkConfig {
+  pub fn with_upload_bandwidth(mut self, bytes_per_second: u64) -> Self {
+    self.upload_bandwidth = Some(bytes_per_second);
+    self
+  }
+
+  pub fn with_download_bandwidth(mut self, bytes_per_second: u64) -> Self {
+    self.download_bandwidth = Some(bytes_per_second);
+    self
+  }
+
+  pub fn with_half_open_prob(mut self, half_open_prob: f64) -> Self {
+    self.half_open_prob = half_open_prob;
+    self
+  }
+
+  pub fn with_keepalive_timeout(mut self, keepalive_timeout: Duration) -> Self {
+    self.keepalive_timeout = keepalive_timeout;
+    self
+  }
+}
+
+/// The time it takes to push `size` bytes through a link of the given
+/// capacity.
+fn transmission_time(size: usize, bandwidth: Option<u64>) -> Duration {
+  match bandwidth {
+    Some(bytes_per_second) if bytes_per_second > 0 => {
+      Duration::from_secs_f64(size as f64 / bytes_per_second as f64)
+    }
+    _ => Duration::ZERO,
+  }
+}
+
+/// Bytes and messages a node has sent and received.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+  pub bytes_sent: u64,
+  pub bytes_received: u64,
+  pub messages_sent: u64,
+  pub messages_received: u64,
+}
+
+impl std::ops::AddAssign for TrafficStats {
+  fn add_assign(&mut self, other: Self) {
+    self.bytes_sent += other.bytes_sent;
+    self.bytes_received += other.bytes_received;
+    self.messages_sent += other.messages_sent;
+    self.messages_received += other.messages_received;
+  }
+}
+
+// Bandwidth usage of a node. Messages are transmitted one after the other,
+// a message has to wait until the link is done with the messages before it.
+#[derive(Default)]
+struct NodeTraffic {
+  upload_busy_until: Duration,
+  download_busy_until: Duration,
+  stats: TrafficStats,
+}
+
 pub enum SimNetworkEvent {
   InboundEstablished {
     from: PeerId,
     queue: RcProtocolMessageQueue,
   },
-  InboundFailure {
-    from: PeerId,
-  },
   OutboundEstablished {
     to: PeerId,
     queue: RcProtocolMessageQueue,
@@ -63,7 +182,8 @@ pub enum SimNetworkEvent {
     to: PeerId,
   },
   Disconnected {
-    from: PeerId,
+    peer_id: PeerId,
+    reason: DisconnectReason,
   },
 }
 
@@ -73,6 +193,31 @@ enum DialerOutcome {
   Failure(PeerId, PeerId),
 }
 
+// A peer learning that its connection is gone, as (peer, remote peer,
+// notification id).
+type PendingDisconnect = (PeerId, PeerId, u64);
+
+// A client dropped while the network was busy, handled on the next poll of
+// the network.
+struct CrashReport {
+  peer_id: PeerId,
+  queue: RcProtocolMessageQueue,
+  peer_ids: Vec<PeerId>,
+}
+
+// Directed link between two peers, as (from, to).
+pub(crate) type Link = (PeerId, PeerId);
+
+// A message that has crossed its link and is about to be handed to the
+// receiver.
+struct Delivery {
+  from: PeerId,
+  to: PeerId,
+  queue: RcProtocolMessageQueue,
+  message: ProtocolMessage,
+  size: usize,
+}
+
 #[derive(Clone)]
 pub struct ClientConnection {
   peer_id: PeerId,
@@ -99,8 +244,29 @@ impl ClientConnection {
 // messages to the network.
 pub struct SimNetwork<R> {
   rng: R,
+  clock: SimClock,
   clients: HashMap<PeerId, ClientConnection>,
   dialer: FuturesUnordered<LocalBoxFuture<'static, DialerOutcome>>,
+  disconnects: FuturesUnordered<LocalBoxFuture<'static, PendingDisconnect>>,
+  // the disconnects the peers have not been told about yet, keyed by (peer,
+  // remote peer). A notification is only delivered while it is the latest
+  // one of its peers.
+  unnotified: HashMap<(PeerId, PeerId), (u64, DisconnectReason)>,
+  next_notification: u64,
+  latency: LinkLatency,
+  // messages in flight, resolving once they have crossed their link
+  deliveries: FuturesUnordered<LocalBoxFuture<'static, Delivery>>,
+  // the deadline of the last message sent over a link, messages on a link
+  // are delivered in the order they were sent
+  last_delivery: HashMap<Link, Duration>,
+  traffic: HashMap<PeerId, NodeTraffic>,
+  // established connections, ordered as (lower, higher) peer id
+  connections: BTreeSet<Link>,
+  partition: Option<Partition>,
+  link_faults: LinkFaultConfig,
+  faults: FuturesUnordered<LocalBoxFuture<'static, NetworkFault>>,
+  crashes: Rc<RefCell<SimQueue<CrashReport>>>,
+  waker: Option<Waker>,
 }
 
 pub struct SimNetworkFuture<R>(pub Rc<RefCell<SimNetwork<R>>>);
@@ -128,13 +294,29 @@ impl<R: Unpin> Future for SimNetwork<R> {
     cx: &mut Context<'_>,
   ) -> Poll<Self::Output> {
     let mut this = self.as_mut();
+    this.waker = Some(cx.waker().clone());
 
     while let Poll::Ready(Some(outcome)) = this.dialer.poll_next_unpin(cx) {
       match outcome {
         DialerOutcome::Success(from_peer_id, to_peer_id) => {
+          // either side might have crashed while the dial was in flight, or
+          // the peers can not reach each other
+          if !this.clients.contains_key(&from_peer_id)
+            || !this.clients.contains_key(&to_peer_id)
+            || this.is_separated(&from_peer_id, &to_peer_id)
+          {
+            this.dial_failed(from_peer_id, to_peer_id);
+            continue;
+          }
+
+          // a peer still waiting to learn that its previous connection is
+          // gone learns now, before the new connection is reported
+          this.flush_disconnect(to_peer_id, from_peer_id);
+          this.flush_disconnect(from_peer_id, to_peer_id);
+          let from_connection = &this.clients[&from_peer_id];
+          let to_connection = &this.clients[&to_peer_id];
+
           // create a connection
-          let from_connection = this.clients.get(&from_peer_id).unwrap();
-          let to_connection = this.clients.get(&to_peer_id).unwrap();
           from_connection.push_event(
             to_peer_id,
             SimNetworkEvent::OutboundEstablished {
@@ -143,13 +325,20 @@ impl<R: Unpin> Future for SimNetwork<R> {
             },
           );
 
+          // the peers dialed each other at the same time, the dial resolves
+          // to the connection dialed first so both agree on who dialed it
+          if this
+            .connections
+            .contains(&link_key(from_peer_id, to_peer_id))
+          {
+            continue;
+          }
+
           tracing::warn!(
             "InboundEstablished from: {:?} to: {:?}",
             from_peer_id,
             to_peer_id,
           );
-          let from_connection = this.clients.get(&from_peer_id).unwrap();
-          let to_connection = this.clients.get(&to_peer_id).unwrap();
           to_connection.push_event(
             from_peer_id,
             SimNetworkEvent::InboundEstablished {
@@ -157,39 +346,295 @@ impl<R: Unpin> Future for SimNetwork<R> {
               queue: Rc::clone(&from_connection.queue),
             },
           );
+          this.connections.insert(link_key(from_peer_id, to_peer_id));
         }
         DialerOutcome::Failure(from_peer_id, to_peer_id) => {
-          let from_connection = this.clients.get(&from_peer_id).unwrap();
-          from_connection
-            .push_event(to_peer_id, SimNetworkEvent::OutboundFailure {
-              to: to_peer_id,
-            });
-
-          let to_connection = this.clients.get(&to_peer_id).unwrap();
-          to_connection
-            .push_event(from_peer_id, SimNetworkEvent::InboundFailure {
-              from: from_peer_id,
-            });
+          this.dial_failed(from_peer_id, to_peer_id);
         }
       }
     }
 
+    loop {
+      let Poll::Ready(report) = this.crashes.borrow_mut().poll_pop(cx) else {
+        break;
+      };
+      this.client_crashed(report.peer_id, &report.queue, report.peer_ids);
+    }
+
+    while let Poll::Ready(Some(fault)) = this.faults.poll_next_unpin(cx) {
+      match fault {
+        NetworkFault::Partition(partition) => this.partition(partition),
+        NetworkFault::Heal => this.heal(),
+      }
+    }
+
+    // deliver the messages that have arrived
+    while let Poll::Ready(Some(delivery)) = this.deliveries.poll_next_unpin(cx)
+    {
+      if this.is_separated(&delivery.from, &delivery.to) {
+        tracing::debug!(
+          "Message from {} to {} lost in partition",
+          delivery.from,
+          delivery.to
+        );
+        continue;
+      }
+
+      let stats = &mut this.traffic.entry(delivery.to).or_default().stats;
+      stats.bytes_received += delivery.size as u64;
+      stats.messages_received += 1;
+      delivery
+        .queue
+        .borrow_mut()
+        .push_back((delivery.from, delivery.message));
+    }
+
+    while let Poll::Ready(Some((peer_id, remote_peer_id, id))) =
+      this.disconnects.poll_next_unpin(cx)
+    {
+      // delivered already, the peers connected again in the meantime
+      let key = (peer_id, remote_peer_id);
+      if this
+        .unnotified
+        .get(&key)
+        .is_some_and(|(latest, _)| *latest == id)
+      {
+        this.flush_disconnect(peer_id, remote_peer_id);
+      }
+    }
+
     Poll::Pending
   }
 }
 
 impl<R> SimNetwork<R> {
-  pub fn build(rng: R) -> Rc<RefCell<Self>> {
+  pub fn build(rng: R, clock: SimClock) -> Rc<RefCell<Self>> {
     Rc::new(RefCell::new(Self {
       rng,
+      clock,
       clients: Default::default(),
       dialer: Default::default(),
+      disconnects: Default::default(),
+      unnotified: Default::default(),
+      next_notification: 0,
+      latency: Default::default(),
+      deliveries: Default::default(),
+      last_delivery: Default::default(),
+      traffic: Default::default(),
+      connections: Default::default(),
+      partition: None,
+      link_faults: Default::default(),
+      faults: Default::default(),
+      crashes: Default::default(),
+      waker: None,
     }))
   }
 
+  /// Replaces the latency models used to deliver messages.
+  pub fn set_latency(&mut self, latency: LinkLatency) {
+    self.latency = latency;
+  }
+
+  pub fn latency_mut(&mut self) -> &mut LinkLatency {
+    &mut self.latency
+  }
+
+  /// The traffic of a single node, messages still in flight are counted as
+  /// sent but not yet as received.
+  pub fn traffic(&self, peer_id: &PeerId) -> TrafficStats {
+    self
+      .traffic
+      .get(peer_id)
+      .map(|traffic| traffic.stats)
+      .unwrap_or_default()
+  }
+
+  /// The traffic of all nodes added together.
+  pub fn total_traffic(&self) -> TrafficStats {
+    let mut total = TrafficStats::default();
+    for traffic in self.traffic.values() {
+      total += traffic.stats;
+    }
+    total
+  }
+
+  /// Splits the network, peers on different sides of the partition can no
+  /// longer dial or message each other. A previous partition is replaced.
+  pub fn partition(&mut self, partition: Partition) {
+    tracing::info!("Partitioning the network: {:?}", partition.mode());
+
+    if partition.mode() == PartitionMode::Disconnect {
+      let severed: Vec<Link> = self
+        .connections
+        .iter()
+        .filter(|(a, b)| partition.separates(a, b))
+        .copied()
+        .collect();
+
+      for (a, b) in severed {
+        self.connections.remove(&(a, b));
+        for (peer_id, remote_peer_id) in [(a, b), (b, a)] {
+          if let Some(connection) = self.clients.get(&peer_id) {
+            connection.push_event(
+              remote_peer_id,
+              SimNetworkEvent::Disconnected {
+                peer_id: remote_peer_id,
+                reason: DisconnectReason::TransportError,
+              },
+            );
+          }
+        }
+      }
+    }
+
+    self.partition = Some(partition);
+  }
+
+  /// Removes the partition, the peers can reach each other again. Severed
+  /// connections are not restored, the peers have to dial again.
+  pub fn heal(&mut self) {
+    tracing::info!("Healing the network partition");
+    self.partition = None;
+  }
+
+  /// Replaces the loss, duplication and reordering of the links.
+  pub fn set_link_faults(&mut self, link_faults: LinkFaultConfig) {
+    self.link_faults = link_faults;
+  }
+
+  pub fn link_faults_mut(&mut self) -> &mut LinkFaultConfig {
+    &mut self.link_faults
+  }
+
+  pub fn is_partitioned(&self) -> bool {
+    self.partition.is_some()
+  }
+
+  /// Applies `fault` once the simulated time reaches `at`.
+  pub fn schedule_fault(&mut self, at: Duration, fault: NetworkFault) {
+    let delay = self.clock.delay(at.saturating_sub(self.clock.now()));
+    self.faults.push(
+      async move {
+        delay.await;
+        fault
+      }
+      .boxed_local(),
+    );
+
+    if let Some(waker) = self.waker.take() {
+      waker.wake();
+    }
+  }
+
+  fn is_separated(&self, a: &PeerId, b: &PeerId) -> bool {
+    self
+      .partition
+      .as_ref()
+      .is_some_and(|partition| partition.separates(a, b))
+  }
+
   pub fn register_client(&mut self, client: &SimNetworkClient<R>) {
     self.clients.insert(client.peer_id(), client.connection());
   }
+
+  /// Called when a client is dropped without disconnecting, as happens when
+  /// its node crashes. The peers it was connected to notice after their
+  /// crash detection timeout.
+  fn client_crashed(
+    &mut self,
+    peer_id: PeerId,
+    queue: &RcProtocolMessageQueue,
+    peer_ids: Vec<PeerId>,
+  ) {
+    // the node might have been restarted already, leave the new client alone
+    let is_registered = self
+      .clients
+      .get(&peer_id)
+      .is_some_and(|connection| Rc::ptr_eq(&connection.queue, queue));
+    if !is_registered {
+      return;
+    }
+    self.clients.remove(&peer_id);
+    self
+      .connections
+      .retain(|(a, b)| *a != peer_id && *b != peer_id);
+
+    for remote_peer_id in peer_ids {
+      let Some(remote) = self.clients.get(&remote_peer_id) else {
+        continue;
+      };
+
+      let timeout = remote.config.crash_detection_timeout;
+      self.notify_disconnect(
+        timeout,
+        remote_peer_id,
+        peer_id,
+        DisconnectReason::Timeout,
+      );
+    }
+  }
+
+  /// Tells `peer_id` after `delay` that its connection to `remote_peer_id` is
+  /// gone.
+  fn notify_disconnect(
+    &mut self,
+    delay: Duration,
+    peer_id: PeerId,
+    remote_peer_id: PeerId,
+    reason: DisconnectReason,
+  ) {
+    let id = self.next_notification;
+    self.next_notification += 1;
+    self
+      .unnotified
+      .insert((peer_id, remote_peer_id), (id, reason));
+
+    let delay = self.clock.delay(delay);
+    self.disconnects.push(
+      async move {
+        delay.await;
+        (peer_id, remote_peer_id, id)
+      }
+      .boxed_local(),
+    );
+
+    if let Some(waker) = self.waker.take() {
+      waker.wake();
+    }
+  }
+
+  /// Tells `peer_id` right away about the disconnect from `remote_peer_id`
+  /// it is still waiting for, if any.
+  fn flush_disconnect(&mut self, peer_id: PeerId, remote_peer_id: PeerId) {
+    let Some((_, reason)) = self.unnotified.remove(&(peer_id, remote_peer_id))
+    else {
+      return;
+    };
+
+    if let Some(connection) = self.clients.get(&peer_id) {
+      tracing::debug!(
+        "{} lost connection to {}: {:?}",
+        peer_id,
+        remote_peer_id,
+        reason
+      );
+      connection.push_event(remote_peer_id, SimNetworkEvent::Disconnected {
+        peer_id: remote_peer_id,
+        reason,
+      });
+    }
+  }
+
+  /// Tells the dialer that its dial failed. The dial never reached the
+  /// remote, which has nothing to learn about it.
+  fn dial_failed(&self, from_peer_id: PeerId, to_peer_id: PeerId) {
+    if let Some(from_connection) = self.clients.get(&from_peer_id) {
+      from_connection
+        .push_event(to_peer_id, SimNetworkEvent::OutboundFailure {
+          to: to_peer_id,
+        });
+    }
+  }
 }
 
 impl<R: Rng> SimNetwork<R> {
@@ -199,40 +644,220 @@ impl<R: Rng> SimNetwork<R> {
     let from = self.clients.get(&from_peer_id).unwrap();
     // let to = self.clients.get(&to_peer_id).unwrap();
 
-    let delay = self.rng.gen_range(from.config.connection_delay.clone());
+    let delay = self
+      .clock
+      .delay(self.rng.gen_range(from.config.connection_delay.clone()));
     let is_failure = self.rng.gen_bool(from.config.connection_fail_prob);
     let delayed_dialer_outcome = if is_failure {
       async move {
-        Delay::new(delay).await;
+        delay.await;
         DialerOutcome::Failure(from_peer_id, to_peer_id)
       }
       .boxed_local()
     } else {
       async move {
-        Delay::new(delay).await;
+        delay.await;
         DialerOutcome::Success(from_peer_id, to_peer_id)
       }
       .boxed_local()
     };
 
     self.dialer.push(delayed_dialer_outcome);
+
+    // the dialer only polls the new entry once the network is polled again
+    if let Some(waker) = self.waker.take() {
+      waker.wake();
+    }
   }
 
-  pub fn disconnect(&mut self, from_peer_id: PeerId, to_peer_id: PeerId) {
-    // remove the connection
+  /// Delivers a message onto the queue of the remote once it has been
+  /// uploaded by the sender, has crossed the link and has been downloaded by
+  /// the receiver. Both ends reserve their bandwidth when the message is
+  /// sent, messages queue up behind each other on a saturated node. The
+  /// faults of the link may drop, duplicate or reorder the message.
+  fn send(
+    &mut self,
+    from_peer_id: PeerId,
+    to_peer_id: PeerId,
+    queue: RcProtocolMessageQueue,
+    message: ProtocolMessage,
+  ) {
+    let now = self.clock.now();
+    let size = message.encoded_size();
+
+    // the remote is gone and the sender has not noticed yet
+    if !self
+      .connections
+      .contains(&link_key(from_peer_id, to_peer_id))
+    {
+      tracing::debug!(
+        "Message from {} to {} lost on a closed connection",
+        from_peer_id,
+        to_peer_id
+      );
+      return;
+    }
+
+    let bandwidth = |peer_id| {
+      self.clients.get(&peer_id).map(|connection| {
+        (
+          connection.config.upload_bandwidth,
+          connection.config.download_bandwidth,
+        )
+      })
+    };
+    let (upload_bandwidth, _) = bandwidth(from_peer_id).unwrap_or_default();
+    let (_, download_bandwidth) = bandwidth(to_peer_id).unwrap_or_default();
+
+    let sender = self.traffic.entry(from_peer_id).or_default();
+    let uploaded = sender.upload_busy_until.max(now)
+      + transmission_time(size, upload_bandwidth);
+    sender.upload_busy_until = uploaded;
+    sender.stats.bytes_sent += size as u64;
+    sender.stats.messages_sent += 1;
+
+    let latency = self
+      .latency
+      .model(from_peer_id, to_peer_id)
+      .sample(&mut self.rng);
+
+    let faults = self.link_faults.faults(from_peer_id, to_peer_id);
+    if chance(&mut self.rng, faults.drop_prob) {
+      tracing::debug!(
+        "Message from {} to {} dropped",
+        from_peer_id,
+        to_peer_id
+      );
+      return;
+    }
+
+    let receiver = self.traffic.entry(to_peer_id).or_default();
+    let downloaded = receiver.download_busy_until.max(uploaded + latency)
+      + transmission_time(size, download_bandwidth);
+    receiver.download_busy_until = downloaded;
+
+    let link = (from_peer_id, to_peer_id);
+    let deadline = if chance(&mut self.rng, faults.reorder_prob) {
+      // held back for another trip over the link, without holding back the
+      // messages sent after it
+      let extra = self
+        .latency
+        .model(from_peer_id, to_peer_id)
+        .sample(&mut self.rng);
+      downloaded + extra
+    } else {
+      // a message never overtakes the one sent before it on the same link
+      let deadline = self
+        .last_delivery
+        .get(&link)
+        .map_or(downloaded, |last| downloaded.max(*last));
+      self.last_delivery.insert(link, deadline);
+      deadline
+    };
+
+    if chance(&mut self.rng, faults.duplicate_prob) {
+      self.deliver_at(deadline, Delivery {
+        from: from_peer_id,
+        to: to_peer_id,
+        queue: Rc::clone(&queue),
+        message: message.clone(),
+        size,
+      });
+    }
+
+    self.deliver_at(deadline, Delivery {
+      from: from_peer_id,
+      to: to_peer_id,
+      queue,
+      message,
+      size,
+    });
+  }
+
+  fn deliver_at(&mut self, deadline: Duration, delivery: Delivery) {
+    let delay = self.clock.delay(deadline.saturating_sub(self.clock.now()));
+    self.deliveries.push(
+      async move {
+        delay.await;
+        delivery
+      }
+      .boxed_local(),
+    );
+
+    if let Some(waker) = self.waker.take() {
+      waker.wake();
+    }
+  }
+
+  /// Closes the connection between two peers. The initiating peer learns
+  /// right away, the remote once the close has crossed the link, after the
+  /// messages sent before it. When the close gets lost the remote keeps a
+  /// half-open connection until its keepalive times out, or until the peers
+  /// connect again.
+  pub fn disconnect(
+    &mut self,
+    from_peer_id: PeerId,
+    to_peer_id: PeerId,
+    reason: DisconnectReason,
+  ) {
+    let was_connected =
+      self.connections.remove(&link_key(from_peer_id, to_peer_id));
     let from_connection = self.clients.get(&from_peer_id).unwrap();
+    let config = from_connection.config.clone();
     from_connection.push_event(to_peer_id, SimNetworkEvent::Disconnected {
-      from: to_peer_id,
+      peer_id: to_peer_id,
+      reason,
     });
 
-    let to_connection = self.clients.get(&to_peer_id).unwrap();
-    to_connection.push_event(from_peer_id, SimNetworkEvent::Disconnected {
-      from: from_peer_id,
-    });
+    // the remote might have crashed or be disconnected already, in which case
+    // there is nobody to tell
+    if !was_connected || !self.clients.contains_key(&to_peer_id) {
+      return;
+    }
+
+    if chance(&mut self.rng, config.half_open_prob) {
+      tracing::debug!("{} is left half-open to {}", to_peer_id, from_peer_id);
+      self.notify_disconnect(
+        config.keepalive_timeout,
+        to_peer_id,
+        from_peer_id,
+        DisconnectReason::Timeout,
+      );
+      return;
+    }
+
+    let now = self.clock.now();
+    let latency = self
+      .latency
+      .model(from_peer_id, to_peer_id)
+      .sample(&mut self.rng);
+    let arrival = self
+      .last_delivery
+      .get(&(from_peer_id, to_peer_id))
+      .map_or(now + latency, |last| (now + latency).max(*last));
+    self.notify_disconnect(
+      arrival - now,
+      to_peer_id,
+      from_peer_id,
+      DisconnectReason::RemoteRequest,
+    );
   }
 }
 
+/// Draws whether an event with probability `prob` happens. Nothing is drawn
+/// when it can not happen, so a network without faults consumes the same
+/// randomness as before.
+fn chance<R: Rng>(rng: &mut R, prob: f64) -> bool {
+  prob > 0.0 && rng.gen_bool(prob.min(1.0))
+}
+
+/// Key of the undirected link between two peers.
+pub(crate) fn link_key(a: PeerId, b: PeerId) -> Link {
+  (a.min(b), a.max(b))
+}
+
 pub struct SimNetworkClient<R> {
+  #[allow(dead_code)]
   rng: R,
   config: SimNetworkConfig,
   address: NodeAddress,
@@ -240,6 +865,7 @@ pub struct SimNetworkClient<R> {
   connections: HashMap<PeerId, RcProtocolMessageQueue>,
   queue: RcProtocolMessageQueue,
   events: RcSimNetworkEventQueue,
+  crashes: Rc<RefCell<SimQueue<CrashReport>>>,
 }
 
 impl<R: Unpin> Future for SimNetworkClient<R> {
@@ -247,12 +873,12 @@ impl<R: Unpin> Future for SimNetworkClient<R> {
 
   fn poll(
     mut self: Pin<&mut Self>,
-    _cx: &mut Context<'_>,
+    cx: &mut Context<'_>,
   ) -> Poll<Self::Output> {
     let mut this = self.as_mut();
     // check for simnetwork events
-    let maybe_sim_network_event = this.events.borrow_mut().pop_front();
-    if let Some((_from_peer_id, event)) = maybe_sim_network_event {
+    let maybe_sim_network_event = this.events.borrow_mut().poll_pop(cx);
+    if let Poll::Ready((_from_peer_id, event)) = maybe_sim_network_event {
       match event {
         SimNetworkEvent::InboundEstablished { from, queue } => {
           this.connections.insert(from, queue);
@@ -260,9 +886,6 @@ impl<R: Unpin> Future for SimNetworkClient<R> {
             peer_id: from,
           });
         }
-        SimNetworkEvent::InboundFailure { from } => {
-          return Poll::Ready(NetworkEvent::OutboundFailure { peer_id: from });
-        }
         SimNetworkEvent::OutboundEstablished { to, queue } => {
           this.connections.insert(to, queue);
           return Poll::Ready(NetworkEvent::OutboundEstablished {
@@ -272,15 +895,22 @@ impl<R: Unpin> Future for SimNetworkClient<R> {
         SimNetworkEvent::OutboundFailure { to } => {
           return Poll::Ready(NetworkEvent::OutboundFailure { peer_id: to });
         }
-        SimNetworkEvent::Disconnected { from } => {
-          this.connections.remove(&from);
-          return Poll::Ready(NetworkEvent::PeerDisconnected { peer_id: from });
+        // the connection might have been closed on our side already
+        SimNetworkEvent::Disconnected { peer_id, reason } => {
+          if this.connections.remove(&peer_id).is_some() {
+            return Poll::Ready(NetworkEvent::PeerDisconnected {
+              peer_id,
+              reason,
+            });
+          }
         }
       }
     }
 
     // if we have protocol message in our queue, return it as a network event
-    if let Some((from_peer_id, message)) = this.queue.borrow_mut().pop_front() {
+    if let Poll::Ready((from_peer_id, message)) =
+      this.queue.borrow_mut().poll_pop(cx)
+    {
       return Poll::Ready(NetworkEvent::MessageReceived {
         peer_id: from_peer_id,
         message,
@@ -292,17 +922,28 @@ impl<R: Unpin> Future for SimNetworkClient<R> {
 }
 
 impl<R: Rng + Unpin> Network for SimNetworkClient<R> {
+  fn address(&self) -> &NodeAddress {
+    &self.address
+  }
+
   fn send(
     &mut self,
     peer_id: PeerId,
     message: ProtocolMessage,
   ) -> NetworkResult<()> {
-    let connection = self
+    let queue = self
       .connections
       .get(&peer_id)
       .ok_or(NetworkError::NotConnected)?;
 
-    connection.borrow_mut().push_back((self.peer_id(), message));
+    // the message travels through the network, which applies the latency of
+    // the link
+    self.network.borrow_mut().send(
+      self.peer_id(),
+      peer_id,
+      Rc::clone(queue),
+      message,
+    );
 
     Ok(())
   }
@@ -323,7 +964,11 @@ impl<R: Rng + Unpin> Network for SimNetworkClient<R> {
     Ok(())
   }
 
-  fn disconnect(&mut self, peer_id: PeerId) -> NetworkResult<()> {
+  fn disconnect(
+    &mut self,
+    peer_id: PeerId,
+    reason: DisconnectReason,
+  ) -> NetworkResult<()> {
     tracing::debug!("Disconnect from {} peer_id: {}", self.peer_id(), peer_id);
     if !self.connections.contains_key(&peer_id) {
       return Err(NetworkError::NotConnected);
@@ -332,12 +977,34 @@ impl<R: Rng + Unpin> Network for SimNetworkClient<R> {
     self
       .network
       .borrow_mut()
-      .disconnect(self.peer_id(), peer_id);
+      .disconnect(self.peer_id(), peer_id, reason);
 
     Ok(())
   }
 }
 
+impl<R> Drop for SimNetworkClient<R> {
+  fn drop(&mut self) {
+    // Dropping the client is how a node leaves the network, any connection it
+    // still has is left dangling as if the node crashed.
+    let mut peer_ids: Vec<PeerId> = self.connections.keys().copied().collect();
+    // keep the order of the timeouts independent of the hasher
+    peer_ids.sort();
+    match self.network.try_borrow_mut() {
+      Ok(mut network) => {
+        network.client_crashed(self.peer_id(), &self.queue, peer_ids)
+      }
+      // the client is dropped from within the network, which learns about
+      // the crash on its next poll
+      Err(_) => self.crashes.borrow_mut().push_back(CrashReport {
+        peer_id: self.peer_id(),
+        queue: Rc::clone(&self.queue),
+        peer_ids,
+      }),
+    }
+  }
+}
+
 impl<R> SimNetworkClient<R> {
   pub fn peer_id(&self) -> PeerId {
     self.address.0
@@ -358,19 +1025,29 @@ impl<R: Rng> SimNetworkClient<R> {
     rng: R,
     network: Rc<RefCell<SimNetwork<R>>>,
     address: NodeAddress,
+  ) -> Self {
+    Self::with_config(rng, network, address, Default::default())
+  }
+
+  pub fn with_config(
+    rng: R,
+    network: Rc<RefCell<SimNetwork<R>>>,
+    address: NodeAddress,
+    config: SimNetworkConfig,
   ) -> Self {
     let queue = Default::default();
     let events = Default::default();
 
+    let crashes = Rc::clone(&network.borrow().crashes);
     let client = SimNetworkClient {
-      // TODO: make this a var
-      config: Default::default(),
+      config,
       rng,
       address,
       network: Rc::clone(&network),
       connections: Default::default(),
       queue,
       events,
+      crashes,
     };
 
     // register this client with the network so we can send messages
@@ -379,3 +1056,469 @@ impl<R: Rng> SimNetworkClient<R> {
     client
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    fault::LinkFaults,
+    futures::task::noop_waker_ref,
+    latency::LatencyModel,
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  type Client = SimNetworkClient<StdRng>;
+
+  fn address(seed: u64) -> NodeAddress {
+    (
+      PeerId::unique(&mut StdRng::seed_from_u64(seed)),
+      format!("/memory/{seed}").parse().unwrap(),
+    )
+  }
+
+  fn network() -> (SimClock, Rc<RefCell<SimNetwork<StdRng>>>) {
+    let clock = SimClock::new();
+    let network = SimNetwork::build(StdRng::seed_from_u64(0), clock.clone());
+    (clock, network)
+  }
+
+  /// A config without randomness in establishing connections.
+  fn config() -> SimNetworkConfig {
+    SimNetworkConfig {
+      connection_delay: Duration::from_millis(100)..Duration::from_millis(101),
+      connection_fail_prob: 0.0,
+      ..Default::default()
+    }
+  }
+
+  fn client(
+    network: &Rc<RefCell<SimNetwork<StdRng>>>,
+    seed: u64,
+    config: SimNetworkConfig,
+  ) -> Client {
+    SimNetworkClient::with_config(
+      StdRng::seed_from_u64(seed),
+      Rc::clone(network),
+      address(seed),
+      config,
+    )
+  }
+
+  /// Runs the network and its clients for `duration` of simulated time and
+  /// returns the events of each client.
+  fn run(
+    clock: &SimClock,
+    network: &Rc<RefCell<SimNetwork<StdRng>>>,
+    clients: &mut [&mut Client],
+    duration: Duration,
+  ) -> Vec<Vec<NetworkEvent>> {
+    run_timed(clock, network, clients, duration)
+      .into_iter()
+      .map(|events| events.into_iter().map(|(_, event)| event).collect())
+      .collect()
+  }
+
+  /// Like `run`, along with the time each event was reported at.
+  fn run_timed(
+    clock: &SimClock,
+    network: &Rc<RefCell<SimNetwork<StdRng>>>,
+    clients: &mut [&mut Client],
+    duration: Duration,
+  ) -> Vec<Vec<(Duration, NetworkEvent)>> {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let deadline = clock.now() + duration;
+    let mut events: Vec<Vec<_>> = clients.iter().map(|_| Vec::new()).collect();
+    loop {
+      let _ = network.borrow_mut().poll_unpin(&mut cx);
+      let mut progressed = false;
+      for (client, events) in clients.iter_mut().zip(events.iter_mut()) {
+        while let Poll::Ready(event) = client.poll_unpin(&mut cx) {
+          progressed = true;
+          events.push((clock.now(), event));
+        }
+      }
+      if progressed {
+        continue;
+      }
+      match clock.next_deadline() {
+        Some(next) if next <= deadline => clock.advance_to(next),
+        _ => {
+          clock.advance_to(deadline);
+          return events;
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn failed_dial_is_only_reported_to_the_dialer() {
+    let (clock, network) = network();
+    let failing = SimNetworkConfig {
+      connection_fail_prob: 1.0,
+      ..config()
+    };
+    let mut a = client(&network, 1, failing);
+    let mut b = client(&network, 2, config());
+    let b_id = b.peer_id();
+
+    a.connect(b_id).unwrap();
+    let events = run(
+      &clock,
+      &network,
+      &mut [&mut a, &mut b],
+      Duration::from_secs(1),
+    );
+    assert!(matches!(
+      events[0][..],
+      [NetworkEvent::OutboundFailure { peer_id }] if peer_id == b_id
+    ));
+    assert!(events[1].is_empty());
+  }
+
+  fn connect(
+    clock: &SimClock,
+    network: &Rc<RefCell<SimNetwork<StdRng>>>,
+    a: &mut Client,
+    b: &mut Client,
+  ) {
+    a.connect(b.peer_id()).unwrap();
+    let events = run(clock, network, &mut [a, b], Duration::from_secs(1));
+    assert!(matches!(events[0][..], [
+      NetworkEvent::OutboundEstablished { .. }
+    ]));
+    assert!(matches!(events[1][..], [
+      NetworkEvent::InboundEstablished { .. }
+    ]));
+  }
+
+  /// Connects `a` to `b` again while `b` has not learned yet that its
+  /// previous connection to `a` is gone, `b` learns before the new
+  /// connection is reported.
+  fn reconnect(
+    clock: &SimClock,
+    network: &Rc<RefCell<SimNetwork<StdRng>>>,
+    a: &mut Client,
+    b: &mut Client,
+  ) {
+    let a_id = a.peer_id();
+    a.connect(b.peer_id()).unwrap();
+    let events = run(clock, network, &mut [a, b], Duration::from_secs(1));
+    assert!(matches!(events[1][..], [
+      NetworkEvent::PeerDisconnected { peer_id: first, .. },
+      NetworkEvent::InboundEstablished { peer_id: second },
+    ] if first == a_id && second == a_id));
+  }
+
+  #[test]
+  fn crash_is_reported_before_the_restarted_node_connects_again() {
+    let (clock, network) = network();
+    let mut a = client(&network, 1, config());
+    let mut b = client(&network, 2, config());
+    let a_id = a.peer_id();
+    connect(&clock, &network, &mut a, &mut b);
+
+    // a crashes and comes back before b noticed
+    drop(a);
+    let mut a = client(&network, 1, config());
+    reconnect(&clock, &network, &mut a, &mut b);
+
+    // the crash detection timeout passes without closing the new connection
+    let events = run(
+      &clock,
+      &network,
+      &mut [&mut a, &mut b],
+      Duration::from_secs(10),
+    );
+    assert!(events.iter().all(Vec::is_empty));
+    assert!(network
+      .borrow()
+      .connections
+      .contains(&link_key(a_id, b.peer_id())));
+  }
+
+  #[test]
+  fn half_open_connection_is_closed_before_the_peer_connects_again() {
+    let (clock, network) = network();
+    let half_open = SimNetworkConfig {
+      half_open_prob: 1.0,
+      ..config()
+    };
+    let mut a = client(&network, 1, half_open);
+    let mut b = client(&network, 2, config());
+    let a_id = a.peer_id();
+    connect(&clock, &network, &mut a, &mut b);
+
+    // the close gets lost, a dials again before b's keepalive times out
+    a.disconnect(b.peer_id(), DisconnectReason::LocalRequest)
+      .unwrap();
+    let events = run(
+      &clock,
+      &network,
+      &mut [&mut a, &mut b],
+      Duration::from_secs(1),
+    );
+    assert!(matches!(events[0][..], [
+      NetworkEvent::PeerDisconnected { .. }
+    ]));
+    assert!(events[1].is_empty());
+    reconnect(&clock, &network, &mut a, &mut b);
+
+    // the keepalive timeout passes without closing the new connection
+    let events = run(
+      &clock,
+      &network,
+      &mut [&mut a, &mut b],
+      Duration::from_secs(10),
+    );
+    assert!(events.iter().all(Vec::is_empty));
+    assert!(network
+      .borrow()
+      .connections
+      .contains(&link_key(a_id, b.peer_id())));
+  }
+
+  /// A message of a fixed size, its challenge tells the messages apart.
+  fn numbered(number: u8) -> ProtocolMessage {
+    ProtocolMessage::Handshake {
+      challenge: [number; 32],
+    }
+  }
+
+  /// Connects `a` to `b` over links taking 10ms, sends three messages of 38
+  /// bytes and returns how long after sending each of them arrived.
+  fn arrivals(
+    a_config: SimNetworkConfig,
+    b_config: SimNetworkConfig,
+  ) -> Vec<u128> {
+    let (clock, network) = network();
+    network
+      .borrow_mut()
+      .set_latency(LinkLatency::new(LatencyModel::Constant(
+        Duration::from_millis(10),
+      )));
+    let mut a = client(&network, 1, a_config);
+    let mut b = client(&network, 2, b_config);
+    let b_id = b.peer_id();
+    connect(&clock, &network, &mut a, &mut b);
+
+    let sent = clock.now();
+    for _ in 0..3 {
+      a.send(b_id, numbered(0)).unwrap();
+    }
+    let events = run_timed(
+      &clock,
+      &network,
+      &mut [&mut a, &mut b],
+      Duration::from_secs(10),
+    );
+    assert_eq!(network.borrow().traffic(&b_id), TrafficStats {
+      bytes_received: 3 * 38,
+      messages_received: 3,
+      ..Default::default()
+    });
+    events[1]
+      .iter()
+      .map(|(at, _)| (*at - sent).as_millis())
+      .collect()
+  }
+
+  #[test]
+  fn messages_queue_up_behind_the_upload_bandwidth() {
+    let slow = config().with_upload_bandwidth(38);
+    assert_eq!(arrivals(slow, config()), vec![1010, 2010, 3010]);
+  }
+
+  #[test]
+  fn messages_queue_up_behind_the_download_bandwidth() {
+    let slow = config().with_download_bandwidth(19);
+    assert_eq!(arrivals(config(), slow), vec![2010, 4010, 6010]);
+  }
+
+  #[test]
+  fn unlimited_bandwidth_only_adds_latency() {
+    assert_eq!(arrivals(config(), config()), vec![10, 10, 10]);
+  }
+
+  #[test]
+  fn partition_severs_crossing_connections_until_healed() {
+    let (clock, network) = network();
+    let mut a = client(&network, 1, config());
+    let mut b = client(&network, 2, config());
+    let mut c = client(&network, 3, config());
+    let (a_id, b_id, c_id) = (a.peer_id(), b.peer_id(), c.peer_id());
+    connect(&clock, &network, &mut a, &mut b);
+    connect(&clock, &network, &mut a, &mut c);
+
+    network.borrow_mut().partition(Partition::new(
+      vec![vec![a_id, b_id], vec![c_id]],
+      PartitionMode::Disconnect,
+    ));
+    let events = run(
+      &clock,
+      &network,
+      &mut [&mut a, &mut b, &mut c],
+      Duration::from_secs(1),
+    );
+    assert!(matches!(events[0][..], [NetworkEvent::PeerDisconnected {
+      peer_id,
+      reason: DisconnectReason::TransportError,
+    }] if peer_id == c_id));
+    assert!(events[1].is_empty());
+    assert!(matches!(events[2][..], [NetworkEvent::PeerDisconnected {
+      peer_id,
+      reason: DisconnectReason::TransportError,
+    }] if peer_id == a_id));
+
+    // dials across the partition fail, the peers on one side still talk
+    a.connect(c_id).unwrap();
+    a.send(b_id, ProtocolMessage::Goodbye).unwrap();
+    let events = run(
+      &clock,
+      &network,
+      &mut [&mut a, &mut b, &mut c],
+      Duration::from_secs(1),
+    );
+    assert!(matches!(
+      events[0][..],
+      [NetworkEvent::OutboundFailure { peer_id }] if peer_id == c_id
+    ));
+    assert!(matches!(events[1][..], [NetworkEvent::MessageReceived {
+      message: ProtocolMessage::Goodbye,
+      ..
+    }]));
+
+    // severed connections stay closed after healing, until dialed again
+    network.borrow_mut().heal();
+    assert!(!network.borrow().is_partitioned());
+    connect(&clock, &network, &mut a, &mut c);
+  }
+
+  #[test]
+  fn stalled_partition_loses_messages_but_keeps_connections() {
+    let (clock, network) = network();
+    let mut a = client(&network, 1, config());
+    let mut b = client(&network, 2, config());
+    let b_id = b.peer_id();
+    connect(&clock, &network, &mut a, &mut b);
+
+    network.borrow_mut().partition(Partition::new(
+      vec![vec![a.peer_id()]],
+      PartitionMode::Stall,
+    ));
+    a.send(b_id, ProtocolMessage::Goodbye).unwrap();
+    let events = run(
+      &clock,
+      &network,
+      &mut [&mut a, &mut b],
+      Duration::from_secs(1),
+    );
+    assert!(events.iter().all(Vec::is_empty));
+
+    network.borrow_mut().heal();
+    a.send(b_id, ProtocolMessage::Goodbye).unwrap();
+    let events = run(
+      &clock,
+      &network,
+      &mut [&mut a, &mut b],
+      Duration::from_secs(1),
+    );
+    assert!(matches!(events[1][..], [NetworkEvent::MessageReceived {
+      message: ProtocolMessage::Goodbye,
+      ..
+    }]));
+  }
+
+  #[test]
+  fn scheduled_faults_apply_at_their_time() {
+    let (clock, network) = network();
+    let mut a = client(&network, 1, config());
+    let partition =
+      Partition::new(vec![vec![a.peer_id()]], PartitionMode::Disconnect);
+    network.borrow_mut().schedule_fault(
+      Duration::from_secs(5),
+      NetworkFault::Partition(partition),
+    );
+    network
+      .borrow_mut()
+      .schedule_fault(Duration::from_secs(8), NetworkFault::Heal);
+
+    let mut partitioned = Vec::new();
+    for _ in 0..9 {
+      run(&clock, &network, &mut [&mut a], Duration::from_secs(1));
+      partitioned.push(network.borrow().is_partitioned());
+    }
+    assert_eq!(partitioned, [
+      false, false, false, false, true, true, true, false, false
+    ]);
+  }
+
+  /// Sends messages numbered 0 to 19 from `a` to `b` over links with
+  /// `faults` and returns the numbers `b` received, in the order it received
+  /// them.
+  fn received_messages(faults: LinkFaults) -> Vec<u8> {
+    let (clock, network) = network();
+    let mut a = client(&network, 1, config());
+    let mut b = client(&network, 2, config());
+    let (a_id, b_id) = (a.peer_id(), b.peer_id());
+    connect(&clock, &network, &mut a, &mut b);
+
+    network
+      .borrow_mut()
+      .link_faults_mut()
+      .set_link(a_id, b_id, faults);
+    for number in 0..20 {
+      a.send(b_id, numbered(number)).unwrap();
+    }
+    let events = run(
+      &clock,
+      &network,
+      &mut [&mut a, &mut b],
+      Duration::from_secs(1),
+    );
+    assert_eq!(network.borrow().traffic(&a_id).messages_sent, 20);
+    events[1]
+      .iter()
+      .map(|event| match event {
+        NetworkEvent::MessageReceived {
+          message: ProtocolMessage::Handshake { challenge },
+          ..
+        } => challenge[0],
+        event => panic!("unexpected event {event:?}"),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn faultless_link_delivers_in_order() {
+    let received = received_messages(LinkFaults::default());
+    assert_eq!(received, (0..20).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn lossy_link_drops_messages() {
+    let received = received_messages(LinkFaults::default().with_drop_prob(1.0));
+    assert!(received.is_empty());
+
+    let received = received_messages(LinkFaults::default().with_drop_prob(0.5));
+    assert!(!received.is_empty() && received.len() < 20);
+    assert!(received.is_sorted());
+  }
+
+  #[test]
+  fn duplicating_link_delivers_messages_twice() {
+    let received =
+      received_messages(LinkFaults::default().with_duplicate_prob(1.0));
+    let expected: Vec<_> =
+      (0..20).flat_map(|number| [number, number]).collect();
+    assert_eq!(received, expected);
+  }
+
+  #[test]
+  fn reordering_link_lets_messages_overtake() {
+    let mut received =
+      received_messages(LinkFaults::default().with_reorder_prob(1.0));
+    assert!(!received.is_sorted());
+    received.sort();
+    assert_eq!(received, (0..20).collect::<Vec<_>>());
+  }
+}
@@ -0,0 +1,148 @@
+use {
+  super::{link_key, Link},
+  crate::types::PeerId,
+  std::collections::HashMap,
+};
+
+/// What happens to the connections that cross a partition.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PartitionMode {
+  /// Both ends are told the connection dropped.
+  #[default]
+  Disconnect,
+  /// The connections stay open, but messages across them are lost.
+  Stall,
+}
+
+/// Splits the network into groups that cannot reach each other. Peers that
+/// are not part of any group form one more group together.
+#[derive(Clone, Debug)]
+pub struct Partition {
+  groups: HashMap<PeerId, usize>,
+  mode: PartitionMode,
+}
+
+impl Partition {
+  pub fn new(groups: Vec<Vec<PeerId>>, mode: PartitionMode) -> Self {
+    let groups = groups
+      .into_iter()
+      .enumerate()
+      .flat_map(|(group, peer_ids)| {
+        peer_ids.into_iter().map(move |peer_id| (peer_id, group))
+      })
+      .collect();
+
+    Self { groups, mode }
+  }
+
+  pub fn mode(&self) -> PartitionMode {
+    self.mode
+  }
+
+  /// Whether `a` and `b` are on different sides of the partition.
+  pub fn separates(&self, a: &PeerId, b: &PeerId) -> bool {
+    self.groups.get(a) != self.groups.get(b)
+  }
+}
+
+/// A fault applied to the network at a scheduled simulated time.
+#[derive(Clone, Debug)]
+pub enum NetworkFault {
+  Partition(Partition),
+  Heal,
+}
+
+/// Probabilities with which a link misbehaves, drawn for every message.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkFaults {
+  /// The message is lost.
+  pub drop_prob: f64,
+  /// The message is delivered twice.
+  pub duplicate_prob: f64,
+  /// The message may be overtaken by the messages sent after it.
+  pub reorder_prob: f64,
+}
+
+impl LinkFaults {
+  pub fn with_drop_prob(mut self, drop_prob: f64) -> Self {
+    self.drop_prob = drop_prob;
+    self
+  }
+
+  pub fn with_duplicate_prob(mut self, duplicate_prob: f64) -> Self {
+    self.duplicate_prob = duplicate_prob;
+    self
+  }
+
+  pub fn with_reorder_prob(mut self, reorder_prob: f64) -> Self {
+    self.reorder_prob = reorder_prob;
+    self
+  }
+}
+
+/// The faults of every link, a link without faults of its own uses the
+/// default. Links are symmetric.
+#[derive(Clone, Debug, Default)]
+pub struct LinkFaultConfig {
+  default: LinkFaults,
+  links: HashMap<Link, LinkFaults>,
+}
+
+impl LinkFaultConfig {
+  pub fn new(default: LinkFaults) -> Self {
+    Self {
+      default,
+      links: Default::default(),
+    }
+  }
+
+  pub fn set_link(&mut self, a: PeerId, b: PeerId, faults: LinkFaults) {
+    self.links.insert(link_key(a, b), faults);
+  }
+
+  pub fn faults(&self, from: PeerId, to: PeerId) -> LinkFaults {
+    self
+      .links
+      .get(&link_key(from, to))
+      .copied()
+      .unwrap_or(self.default)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  fn peer_id(seed: u64) -> PeerId {
+    PeerId::unique(&mut StdRng::seed_from_u64(seed))
+  }
+
+  #[test]
+  fn unlisted_peers_form_a_group_of_their_own() {
+    let (a, b, c, d) = (peer_id(1), peer_id(2), peer_id(3), peer_id(4));
+    let partition =
+      Partition::new(vec![vec![a], vec![b]], PartitionMode::default());
+
+    assert!(partition.separates(&a, &b));
+    assert!(partition.separates(&a, &c));
+    assert!(partition.separates(&b, &c));
+    assert!(!partition.separates(&c, &d));
+    assert!(!partition.separates(&a, &a));
+  }
+
+  #[test]
+  fn link_faults_override_the_default_in_both_directions() {
+    let (a, b, c) = (peer_id(1), peer_id(2), peer_id(3));
+    let default = LinkFaults::default().with_drop_prob(0.1);
+    let lossy = LinkFaults::default().with_drop_prob(0.9);
+    let mut config = LinkFaultConfig::new(default);
+    config.set_link(a, b, lossy);
+
+    assert_eq!(config.faults(a, b), lossy);
+    assert_eq!(config.faults(b, a), lossy);
+    assert_eq!(config.faults(a, c), default);
+  }
+}
@@ -0,0 +1,162 @@
+use {
+  super::link_key,
+  crate::types::PeerId,
+  rand::Rng,
+  std::{collections::HashMap, f64::consts::TAU, ops::Range, time::Duration},
+};
+
+/// Sampled latencies are capped here, the tail of a wide distribution would
+/// otherwise reach durations that can not be represented.
+pub const MAX_LATENCY: Duration = Duration::from_secs(3600);
+
+/// Identifies a geographic region in the region latency matrix.
+pub type Region = usize;
+
+/// Distribution the delivery latency of a single message is drawn from.
+#[derive(Clone, Debug)]
+pub enum LatencyModel {
+  /// Every message takes exactly this long.
+  Constant(Duration),
+  /// Latencies are spread evenly over the range.
+  Uniform(Range<Duration>),
+  /// Latencies follow a normal distribution, clamped at zero.
+  Normal { mean: Duration, std_dev: Duration },
+  /// Latencies follow a log-normal distribution, which has the long tail
+  /// seen on real links. `sigma` is the standard deviation of the underlying
+  /// normal distribution.
+  LogNormal { median: Duration, sigma: f64 },
+}
+
+impl Default for LatencyModel {
+  fn default() -> Self {
+    LatencyModel::Uniform(Duration::from_millis(10)..Duration::from_millis(100))
+  }
+}
+
+impl LatencyModel {
+  pub fn sample<R: Rng>(&self, rng: &mut R) -> Duration {
+    match self {
+      LatencyModel::Constant(latency) => *latency,
+      LatencyModel::Uniform(range) if range.is_empty() => range.start,
+      LatencyModel::Uniform(range) => rng.gen_range(range.clone()),
+      LatencyModel::Normal { mean, std_dev } => {
+        let latency =
+          mean.as_secs_f64() + std_dev.as_secs_f64() * standard_normal(rng);
+        clamped_latency(latency)
+      }
+      LatencyModel::LogNormal { median, sigma } => {
+        let latency =
+          median.as_secs_f64() * (sigma * standard_normal(rng)).exp();
+        clamped_latency(latency)
+      }
+    }
+  }
+}
+
+/// Converts a sampled latency in seconds into a duration between zero and
+/// `MAX_LATENCY`, treating NaN as zero.
+fn clamped_latency(secs: f64) -> Duration {
+  if secs.is_nan() || secs <= 0.0 {
+    return Duration::ZERO;
+  }
+  Duration::try_from_secs_f64(secs)
+    .map_or(MAX_LATENCY, |latency| latency.min(MAX_LATENCY))
+}
+
+/// Draws from the standard normal distribution using the Box-Muller
+/// transform.
+fn standard_normal<R: Rng>(rng: &mut R) -> f64 {
+  // gen yields [0, 1), flip it to (0, 1] to keep the logarithm finite
+  let u1 = 1.0 - rng.gen::<f64>();
+  let u2 = rng.gen::<f64>();
+  (-2.0 * u1.ln()).sqrt() * (TAU * u2).cos()
+}
+
+/// Decides which latency model applies to a link. A model set for the link
+/// itself wins over the model between the regions of both peers, which wins
+/// over the default model. Links are symmetric.
+#[derive(Clone, Debug, Default)]
+pub struct LinkLatency {
+  default: LatencyModel,
+  links: HashMap<(PeerId, PeerId), LatencyModel>,
+  regions: HashMap<PeerId, Region>,
+  region_matrix: HashMap<(Region, Region), LatencyModel>,
+}
+
+impl LinkLatency {
+  pub fn new(default: LatencyModel) -> Self {
+    Self {
+      default,
+      ..Default::default()
+    }
+  }
+
+  /// Sets the latency model of the link between two peers.
+  pub fn set_link(&mut self, a: PeerId, b: PeerId, model: LatencyModel) {
+    self.links.insert(link_key(a, b), model);
+  }
+
+  /// Places a peer in a region of the region latency matrix.
+  pub fn set_region(&mut self, peer_id: PeerId, region: Region) {
+    self.regions.insert(peer_id, region);
+  }
+
+  /// Sets the latency model between two regions, use the same region twice
+  /// for links within a region.
+  pub fn set_region_latency(
+    &mut self,
+    a: Region,
+    b: Region,
+    model: LatencyModel,
+  ) {
+    self.region_matrix.insert((a.min(b), a.max(b)), model);
+  }
+
+  pub fn model(&self, from: PeerId, to: PeerId) -> &LatencyModel {
+    if let Some(model) = self.links.get(&link_key(from, to)) {
+      return model;
+    }
+
+    let regions = self.regions.get(&from).zip(self.regions.get(&to));
+    regions
+      .and_then(|(&a, &b)| self.region_matrix.get(&(a.min(b), a.max(b))))
+      .unwrap_or(&self.default)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  #[test]
+  fn extreme_distributions_are_clamped() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let models = [
+      LatencyModel::LogNormal {
+        median: Duration::from_millis(50),
+        sigma: 1e6,
+      },
+      LatencyModel::LogNormal {
+        median: Duration::MAX,
+        sigma: 1.0,
+      },
+      LatencyModel::LogNormal {
+        median: Duration::from_millis(50),
+        sigma: f64::NAN,
+      },
+      LatencyModel::Normal {
+        mean: Duration::MAX,
+        std_dev: Duration::MAX,
+      },
+    ];
+
+    for model in models {
+      for _ in 0..100 {
+        assert!(model.sample(&mut rng) <= MAX_LATENCY);
+      }
+    }
+  }
+}
@@ -0,0 +1,809 @@
+use {
+  super::{
+    codec::{self, read_frame, write_frame},
+    DisconnectReason,
+    Network,
+    NetworkError,
+    NetworkEvent,
+    NetworkResult,
+    ProtocolMessage,
+  },
+  crate::types::{NodeAddress, PeerId, Pubkey},
+  futures::{
+    channel::mpsc::{channel, Receiver, Sender},
+    executor::block_on,
+    Future,
+    SinkExt,
+    StreamExt,
+  },
+  multiaddr::{Multiaddr, Protocol},
+  std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    net::{
+      IpAddr,
+      Ipv4Addr,
+      Ipv6Addr,
+      Shutdown,
+      SocketAddr,
+      TcpListener,
+      TcpStream,
+    },
+    pin::Pin,
+    sync::{
+      atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+      mpsc,
+      Arc,
+    },
+    task::{Context, Poll, Waker},
+    thread,
+    time::Duration,
+  },
+};
+
+/// How long dialing and the handshake that follows may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Frames queued for a connection before sending fails with
+/// `NetworkError::Backpressure`, a slow peer can not make us buffer without
+/// limit.
+pub const WRITE_QUEUE_SIZE: usize = 256;
+
+/// Events the connection threads queue up for the network before they stop
+/// reading, a peer sending faster than the network is polled is slowed down
+/// by TCP flow control instead of filling our memory.
+pub const EVENT_QUEUE_SIZE: usize = 1024;
+
+/// Accepted connections that have not sent their peer id yet. Further
+/// connections are closed right away, until the handshake of one of them
+/// completes or times out.
+pub const MAX_HANDSHAKING: usize = 64;
+
+/// Converts an `/ip4/../tcp/..` or `/ip6/../tcp/..` address into a socket
+/// address.
+pub fn socket_addr(address: &Multiaddr) -> Option<SocketAddr> {
+  let mut ip = None;
+  let mut port = None;
+  for protocol in address.iter() {
+    match protocol {
+      Protocol::Ip4(addr) => ip = Some(IpAddr::V4(addr)),
+      Protocol::Ip6(addr) => ip = Some(IpAddr::V6(addr)),
+      Protocol::Tcp(tcp_port) => port = Some(tcp_port),
+      _ => return None,
+    }
+  }
+
+  Some(SocketAddr::new(ip?, port?))
+}
+
+/// Converts a socket address into an `/ip4/../tcp/..` or `/ip6/../tcp/..`
+/// address.
+pub fn multiaddr(addr: SocketAddr) -> Multiaddr {
+  let mut address = Multiaddr::empty();
+  match addr.ip() {
+    IpAddr::V4(ip) => address.push(Protocol::Ip4(ip)),
+    IpAddr::V6(ip) => address.push(Protocol::Ip6(ip)),
+  }
+  address.push(Protocol::Tcp(addr.port()));
+  address
+}
+
+// Reported by the connection threads to the network.
+enum TcpEvent {
+  Established {
+    id: u64,
+    peer_id: PeerId,
+    stream: TcpStream,
+    outbound: bool,
+  },
+  DialFailed {
+    peer_id: PeerId,
+  },
+  Message {
+    id: u64,
+    peer_id: PeerId,
+    message: ProtocolMessage,
+  },
+  Closed {
+    id: u64,
+    peer_id: PeerId,
+    reason: DisconnectReason,
+  },
+}
+
+struct TcpConnection {
+  id: u64,
+  outbound: bool,
+  // frames to write, dropping the sender closes the connection once the
+  // queued frames are written
+  writer: mpsc::SyncSender<Vec<u8>>,
+}
+
+impl TcpConnection {
+  fn send(
+    &self,
+    peer_id: PeerId,
+    message: &ProtocolMessage,
+  ) -> NetworkResult<()> {
+    let frame = codec::encode(message)?;
+    self.writer.try_send(frame).map_err(|err| match err {
+      mpsc::TrySendError::Full(_) => NetworkError::Backpressure(peer_id),
+      mpsc::TrySendError::Disconnected(_) => NetworkError::NotConnected,
+    })
+  }
+}
+
+/// Network over TCP. Every connection is served by a thread reading frames
+/// and a thread writing them, the network itself never blocks and can be
+/// polled by any executor.
+///
+/// Right after connecting, both sides send their peer id. Peers can only be
+/// dialed once their address is known through `add_peer`.
+pub struct TcpNetwork {
+  address: NodeAddress,
+  peers: HashMap<PeerId, Multiaddr>,
+  connections: HashMap<PeerId, TcpConnection>,
+  // newer connections of connected peers, waiting to be accepted
+  replacements: HashMap<PeerId, TcpConnection>,
+  dialing: HashSet<PeerId>,
+  events_tx: Sender<TcpEvent>,
+  events_rx: Receiver<TcpEvent>,
+  // events raised by calls into the network, returned on the next polls
+  pending: VecDeque<NetworkEvent>,
+  waker: Option<Waker>,
+  next_id: Arc<AtomicU64>,
+  closed: Arc<AtomicBool>,
+}
+
+impl TcpNetwork {
+  /// Listens on the address of `address` and accepts connections for its
+  /// peer id. Port 0 picks a free port, `address()` returns the address
+  /// actually listened on.
+  pub fn bind(address: NodeAddress) -> io::Result<Self> {
+    let (peer_id, listen_address) = address;
+    let listen_addr = socket_addr(&listen_address).ok_or_else(|| {
+      io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("unsupported address {}", listen_address),
+      )
+    })?;
+
+    let listener = TcpListener::bind(listen_addr)?;
+    let local_addr = listener.local_addr()?;
+    let (events_tx, events_rx) = channel(EVENT_QUEUE_SIZE);
+    let next_id = Arc::new(AtomicU64::new(0));
+    let closed = Arc::new(AtomicBool::new(false));
+
+    spawn_listener(
+      listener,
+      peer_id,
+      events_tx.clone(),
+      Arc::clone(&next_id),
+      Arc::clone(&closed),
+    );
+
+    Ok(TcpNetwork {
+      address: (peer_id, multiaddr(local_addr)),
+      peers: Default::default(),
+      connections: Default::default(),
+      replacements: Default::default(),
+      dialing: Default::default(),
+      events_tx,
+      events_rx,
+      pending: Default::default(),
+      waker: None,
+      next_id,
+      closed,
+    })
+  }
+
+  pub fn peer_id(&self) -> PeerId {
+    self.address.0
+  }
+
+  fn push_pending(&mut self, event: NetworkEvent) {
+    self.pending.push_back(event);
+    if let Some(waker) = self.waker.take() {
+      waker.wake();
+    }
+  }
+
+  fn handle(&mut self, event: TcpEvent) -> Option<NetworkEvent> {
+    match event {
+      TcpEvent::Established {
+        id,
+        peer_id,
+        stream,
+        outbound,
+      } => {
+        if outbound {
+          self.dialing.remove(&peer_id);
+        }
+
+        // When both sides dialed each other, both keep the connection dialed
+        // by the lower peer id so they end up with the same one. A newer
+        // connection in the same direction might come from a restarted
+        // remote, or from anyone claiming its peer id. Either waits as the
+        // replacement of the existing connection until it has been accepted.
+        // A dial that lost to an existing connection resolves to that
+        // connection.
+        let preferred = outbound == (self.peer_id() < peer_id);
+        let replaces = self
+          .connections
+          .get(&peer_id)
+          .map(|existing| preferred || existing.outbound == outbound);
+        if replaces == Some(false) {
+          let _ = stream.shutdown(Shutdown::Both);
+          return outbound
+            .then_some(NetworkEvent::OutboundEstablished { peer_id });
+        }
+
+        let (writer, frames) = mpsc::sync_channel(WRITE_QUEUE_SIZE);
+        thread::spawn(move || write_frames(stream, frames));
+        let connection = TcpConnection {
+          id,
+          outbound,
+          writer,
+        };
+        if replaces == Some(true) {
+          // an earlier replacement is closed by dropping its writer
+          self.replacements.insert(peer_id, connection);
+          return Some(NetworkEvent::ReplacementEstablished {
+            peer_id,
+            outbound,
+          });
+        }
+
+        self.connections.insert(peer_id, connection);
+        Some(established(peer_id, outbound))
+      }
+      TcpEvent::DialFailed { peer_id } => {
+        self.dialing.remove(&peer_id);
+        Some(NetworkEvent::OutboundFailure { peer_id })
+      }
+      TcpEvent::Message {
+        id,
+        peer_id,
+        message,
+      } => {
+        if self.is_current(&peer_id, id) {
+          Some(NetworkEvent::MessageReceived { peer_id, message })
+        } else if self.is_replacement(&peer_id, id) {
+          Some(NetworkEvent::ReplacementMessage { peer_id, message })
+        } else {
+          None
+        }
+      }
+      TcpEvent::Closed {
+        id,
+        peer_id,
+        reason,
+      } => {
+        if self.is_replacement(&peer_id, id) {
+          self.replacements.remove(&peer_id);
+          return Some(NetworkEvent::ReplacementClosed { peer_id });
+        }
+        // a connection that was closed or replaced by us already
+        if !self.is_current(&peer_id, id) {
+          return None;
+        }
+
+        self.connections.remove(&peer_id);
+        // the replacement is all that is left of the peer
+        if let Some(replacement) = self.replacements.remove(&peer_id) {
+          self
+            .pending
+            .push_back(established(peer_id, replacement.outbound));
+          self.connections.insert(peer_id, replacement);
+        }
+        Some(NetworkEvent::PeerDisconnected { peer_id, reason })
+      }
+    }
+  }
+
+  fn is_replacement(&self, peer_id: &PeerId, id: u64) -> bool {
+    self
+      .replacements
+      .get(peer_id)
+      .is_some_and(|connection| connection.id == id)
+  }
+
+  fn is_current(&self, peer_id: &PeerId, id: u64) -> bool {
+    self
+      .connections
+      .get(peer_id)
+      .is_some_and(|connection| connection.id == id)
+  }
+}
+
+fn established(peer_id: PeerId, outbound: bool) -> NetworkEvent {
+  match outbound {
+    true => NetworkEvent::OutboundEstablished { peer_id },
+    false => NetworkEvent::InboundEstablished { peer_id },
+  }
+}
+
+impl Future for TcpNetwork {
+  type Output = NetworkEvent;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+    this.waker = Some(cx.waker().clone());
+
+    if let Some(event) = this.pending.pop_front() {
+      return Poll::Ready(event);
+    }
+
+    // the network holds a sender itself, the channel never ends
+    while let Poll::Ready(Some(event)) = this.events_rx.poll_next_unpin(cx) {
+      if let Some(event) = this.handle(event) {
+        return Poll::Ready(event);
+      }
+    }
+
+    Poll::Pending
+  }
+}
+
+impl Network for TcpNetwork {
+  /// The address the network listens on, with the port picked by the operating
+  /// system when binding to port 0.
+  fn address(&self) -> &NodeAddress {
+    &self.address
+  }
+
+  fn add_peer(&mut self, peer_id: Pubkey, addr: NodeAddress) {
+    tracing::debug!("Adding peer_id: {:?} with address: {:?}", peer_id, addr);
+    self.peers.insert(peer_id, addr.1);
+  }
+
+  fn connect(&mut self, peer_id: PeerId) -> NetworkResult<()> {
+    if self.connections.contains_key(&peer_id) {
+      return Err(NetworkError::AlreadyConnected(peer_id));
+    }
+    if self.dialing.contains(&peer_id) {
+      return Ok(());
+    }
+
+    let address = self.peers.get(&peer_id).ok_or(NetworkError::PeerNotFound)?;
+    let addr = socket_addr(address)
+      .ok_or_else(|| NetworkError::UnsupportedAddress(address.clone()))?;
+
+    tracing::debug!("Dialing {} at {}", peer_id, addr);
+    self.dialing.insert(peer_id);
+
+    let local_peer_id = self.peer_id();
+    let mut events = self.events_tx.clone();
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    thread::spawn(move || {
+      match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+        Ok(stream) => serve_connection(
+          stream,
+          local_peer_id,
+          Some(peer_id),
+          None,
+          id,
+          events,
+        ),
+        Err(err) => {
+          tracing::debug!("Failed to dial {}: {}", addr, err);
+          report(&mut events, TcpEvent::DialFailed { peer_id });
+        }
+      }
+    });
+
+    Ok(())
+  }
+
+  fn disconnect(
+    &mut self,
+    peer_id: PeerId,
+    reason: DisconnectReason,
+  ) -> NetworkResult<()> {
+    // dropping the writer closes the connection after the queued frames
+    self
+      .connections
+      .remove(&peer_id)
+      .ok_or(NetworkError::NotConnected)?;
+    self.replacements.remove(&peer_id);
+
+    self.push_pending(NetworkEvent::PeerDisconnected { peer_id, reason });
+    Ok(())
+  }
+
+  fn send(
+    &mut self,
+    peer_id: PeerId,
+    message: ProtocolMessage,
+  ) -> NetworkResult<()> {
+    let connection = self
+      .connections
+      .get(&peer_id)
+      .ok_or(NetworkError::NotConnected)?;
+    connection.send(peer_id, &message)
+  }
+
+  fn send_replacement(
+    &mut self,
+    peer_id: PeerId,
+    message: ProtocolMessage,
+  ) -> NetworkResult<()> {
+    let replacement = self
+      .replacements
+      .get(&peer_id)
+      .ok_or(NetworkError::NotConnected)?;
+    replacement.send(peer_id, &message)
+  }
+
+  fn accept_replacement(&mut self, peer_id: PeerId) -> NetworkResult<()> {
+    let replacement = self
+      .replacements
+      .remove(&peer_id)
+      .ok_or(NetworkError::NotConnected)?;
+    // dropping the writer of the old connection closes it, its reader is no
+    // longer current and goes unreported
+    self.connections.insert(peer_id, replacement);
+    Ok(())
+  }
+
+  fn reject_replacement(&mut self, peer_id: PeerId) -> NetworkResult<()> {
+    self
+      .replacements
+      .remove(&peer_id)
+      .map(drop)
+      .ok_or(NetworkError::NotConnected)
+  }
+}
+
+impl Drop for TcpNetwork {
+  fn drop(&mut self) {
+    self.closed.store(true, Ordering::Relaxed);
+    self.connections.clear();
+    self.replacements.clear();
+
+    // wake the listener from accept, so it notices it is closed. Dialing
+    // ourselves happens in the background, dropping the network never blocks.
+    if let Some(mut addr) = socket_addr(&self.address.1) {
+      if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+          SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+          SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+      }
+      thread::spawn(move || TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT));
+    }
+  }
+}
+
+/// A connection counted against `MAX_HANDSHAKING` until dropped.
+struct HandshakeSlot(Arc<AtomicUsize>);
+
+impl HandshakeSlot {
+  fn take(handshaking: &Arc<AtomicUsize>) -> Option<Self> {
+    handshaking
+      .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+        (count < MAX_HANDSHAKING).then_some(count + 1)
+      })
+      .ok()
+      .map(|_| HandshakeSlot(Arc::clone(handshaking)))
+  }
+}
+
+impl Drop for HandshakeSlot {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+fn spawn_listener(
+  listener: TcpListener,
+  local_peer_id: PeerId,
+  events: Sender<TcpEvent>,
+  next_id: Arc<AtomicU64>,
+  closed: Arc<AtomicBool>,
+) {
+  let handshaking = Arc::new(AtomicUsize::new(0));
+  thread::spawn(move || {
+    for stream in listener.incoming() {
+      if closed.load(Ordering::Relaxed) {
+        break;
+      }
+
+      let stream = match stream {
+        Ok(stream) => stream,
+        Err(err) => {
+          tracing::warn!("Failed to accept connection: {}", err);
+          continue;
+        }
+      };
+
+      let Some(slot) = HandshakeSlot::take(&handshaking) else {
+        tracing::warn!("Too many pending handshakes, closing connection");
+        let _ = stream.shutdown(Shutdown::Both);
+        continue;
+      };
+
+      let events = events.clone();
+      let id = next_id.fetch_add(1, Ordering::Relaxed);
+      thread::spawn(move || {
+        serve_connection(stream, local_peer_id, None, Some(slot), id, events)
+      });
+    }
+  });
+}
+
+/// Queues an event for the network, waiting while the queue is full. Returns
+/// whether the network is still around.
+fn report(events: &mut Sender<TcpEvent>, event: TcpEvent) -> bool {
+  block_on(events.send(event)).is_ok()
+}
+
+/// Exchanges peer ids and reads frames until the connection closes. A dialed
+/// connection carries the peer id we expect on the other side, a remote
+/// claiming our own peer id is turned away. An accepted connection holds its
+/// handshake slot until the peer ids are exchanged.
+fn serve_connection(
+  mut stream: TcpStream,
+  local_peer_id: PeerId,
+  expected: Option<PeerId>,
+  slot: Option<HandshakeSlot>,
+  id: u64,
+  mut events: Sender<TcpEvent>,
+) {
+  let handshake = handshake(&mut stream, local_peer_id)
+    .and_then(|peer_id| Ok((peer_id, stream.try_clone()?)));
+  drop(slot);
+  let (peer_id, writer) = match handshake {
+    Ok((peer_id, writer))
+      if peer_id != local_peer_id && expected.is_none_or(|e| e == peer_id) =>
+    {
+      (peer_id, writer)
+    }
+    result => {
+      if let Err(err) = result {
+        tracing::debug!("Handshake failed: {}", err);
+      }
+      if let Some(peer_id) = expected {
+        report(&mut events, TcpEvent::DialFailed { peer_id });
+      }
+      let _ = stream.shutdown(Shutdown::Both);
+      return;
+    }
+  };
+
+  let established = TcpEvent::Established {
+    id,
+    peer_id,
+    stream: writer,
+    outbound: expected.is_some(),
+  };
+  if !report(&mut events, established) {
+    return;
+  }
+
+  let reason = loop {
+    let frame = match read_frame(&mut stream) {
+      Ok(frame) => frame,
+      Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+        break DisconnectReason::RemoteRequest
+      }
+      Err(_) => break DisconnectReason::TransportError,
+    };
+
+    let message = match codec::decode(&frame) {
+      Ok(message) => message,
+      Err(err) => {
+        tracing::warn!("Received malformed message from {}: {}", peer_id, err);
+        break DisconnectReason::TransportError;
+      }
+    };
+
+    let event = TcpEvent::Message {
+      id,
+      peer_id,
+      message,
+    };
+    if !report(&mut events, event) {
+      return;
+    }
+  };
+
+  let _ = stream.shutdown(Shutdown::Both);
+  report(&mut events, TcpEvent::Closed {
+    id,
+    peer_id,
+    reason,
+  });
+}
+
+/// Sends our peer id and reads the peer id of the other side.
+fn handshake(
+  stream: &mut TcpStream,
+  local_peer_id: PeerId,
+) -> io::Result<PeerId> {
+  stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+  write_frame(stream, &local_peer_id.to_bytes())?;
+  let frame = read_frame(stream)?;
+  stream.set_read_timeout(None)?;
+
+  let key: [u8; 32] = frame.try_into().map_err(|_| {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid peer id")
+  })?;
+  Ok(Pubkey::from_bytes(key))
+}
+
+fn write_frames(mut stream: TcpStream, frames: mpsc::Receiver<Vec<u8>>) {
+  for frame in frames {
+    if write_frame(&mut stream, &frame).is_err() {
+      break;
+    }
+  }
+
+  // closes the read side as well, which ends the reading thread
+  let _ = stream.shutdown(Shutdown::Both);
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::types::Keypair,
+    futures::{
+      executor::block_on,
+      future::{select, Either},
+    },
+    futures_timer::Delay,
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  fn bind(seed: u64) -> TcpNetwork {
+    let keypair = Keypair::generate(&mut StdRng::seed_from_u64(seed));
+    let address = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    TcpNetwork::bind((*keypair.public(), address)).unwrap()
+  }
+
+  fn next_event(network: &mut TcpNetwork) -> NetworkEvent {
+    let timeout = Delay::new(Duration::from_secs(5));
+    match block_on(select(network, timeout)) {
+      Either::Left((event, _)) => event,
+      Either::Right(_) => panic!("no network event within 5s"),
+    }
+  }
+
+  #[test]
+  fn connect_send_and_disconnect() {
+    let mut a = bind(1);
+    let mut b = bind(2);
+    let (a_id, b_id) = (a.peer_id(), b.peer_id());
+    assert_ne!(socket_addr(&b.address().1).unwrap().port(), 0);
+
+    a.add_peer(b_id, b.address().clone());
+    a.connect(b_id).unwrap();
+    assert!(matches!(
+      next_event(&mut a),
+      NetworkEvent::OutboundEstablished { peer_id } if peer_id == b_id
+    ));
+    assert!(matches!(
+      next_event(&mut b),
+      NetworkEvent::InboundEstablished { peer_id } if peer_id == a_id
+    ));
+
+    a.send(b_id, ProtocolMessage::Ping { nonce: 7 }).unwrap();
+    assert!(matches!(
+      next_event(&mut b),
+      NetworkEvent::MessageReceived {
+        peer_id,
+        message: ProtocolMessage::Ping { nonce: 7 },
+      } if peer_id == a_id
+    ));
+
+    a.disconnect(b_id, DisconnectReason::LocalRequest).unwrap();
+    assert!(matches!(
+      next_event(&mut a),
+      NetworkEvent::PeerDisconnected {
+        reason: DisconnectReason::LocalRequest,
+        ..
+      }
+    ));
+    assert!(matches!(
+      next_event(&mut b),
+      NetworkEvent::PeerDisconnected {
+        reason: DisconnectReason::RemoteRequest,
+        ..
+      }
+    ));
+    assert!(matches!(
+      a.send(b_id, ProtocolMessage::Goodbye),
+      Err(NetworkError::NotConnected)
+    ));
+  }
+
+  #[test]
+  fn slow_peer_causes_backpressure() {
+    let mut a = bind(1);
+
+    // a peer that completes the handshake and then stops reading
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let keypair = Keypair::generate(&mut StdRng::seed_from_u64(2));
+    let slow_id = *keypair.public();
+    a.add_peer(
+      slow_id,
+      (slow_id, multiaddr(listener.local_addr().unwrap())),
+    );
+    a.connect(slow_id).unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+    read_frame(&mut stream).unwrap();
+    write_frame(&mut stream, &slow_id.to_bytes()).unwrap();
+    assert!(matches!(
+      next_event(&mut a),
+      NetworkEvent::OutboundEstablished { .. }
+    ));
+
+    let message = ProtocolMessage::Application {
+      protocol: 0,
+      payload: vec![0; 64 * 1024],
+    };
+    let result = (0..10_000)
+      .map(|_| a.send(slow_id, message.clone()))
+      .find(Result::is_err);
+    assert!(matches!(result, Some(Err(NetworkError::Backpressure(_)))));
+    drop(stream);
+  }
+
+  #[test]
+  fn pending_handshakes_are_capped() {
+    let a = bind(1);
+    let addr = socket_addr(&a.address().1).unwrap();
+
+    // connections that never send their peer id hold on to their slots
+    let stalled: Vec<_> = (0..MAX_HANDSHAKING)
+      .map(|_| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        read_frame(&mut stream).unwrap();
+        stream
+      })
+      .collect();
+
+    let mut rejected = TcpStream::connect(addr).unwrap();
+    rejected
+      .set_read_timeout(Some(Duration::from_secs(5)))
+      .unwrap();
+    assert_eq!(
+      read_frame(&mut rejected).unwrap_err().kind(),
+      io::ErrorKind::UnexpectedEof
+    );
+    drop(stalled);
+  }
+
+  #[test]
+  fn unpolled_network_stops_reading() {
+    let mut a = bind(1);
+    let addr = socket_addr(&a.address().1).unwrap();
+
+    // a peer flooding the network while nobody polls it
+    let keypair = Keypair::generate(&mut StdRng::seed_from_u64(2));
+    let mut stream = TcpStream::connect(addr).unwrap();
+    read_frame(&mut stream).unwrap();
+    write_frame(&mut stream, &keypair.public().to_bytes()).unwrap();
+    assert!(matches!(
+      next_event(&mut a),
+      NetworkEvent::InboundEstablished { .. }
+    ));
+
+    stream
+      .set_write_timeout(Some(Duration::from_secs(1)))
+      .unwrap();
+    let frame = codec::encode(&ProtocolMessage::Application {
+      protocol: 0,
+      payload: vec![0; 64 * 1024],
+    })
+    .unwrap();
+    let result = (0..EVENT_QUEUE_SIZE * 4)
+      .map(|_| write_frame(&mut stream, &frame))
+      .find(Result::is_err);
+    assert!(matches!(result, Some(Err(err)) if matches!(
+      err.kind(),
+      io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )));
+  }
+}
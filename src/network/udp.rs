@@ -0,0 +1,1231 @@
+use {
+  super::{
+    codec,
+    DisconnectReason,
+    Network,
+    NetworkError,
+    NetworkEvent,
+    NetworkResult,
+    ProtocolMessage,
+  },
+  crate::types::{NodeAddress, PeerId, Pubkey},
+  futures::{
+    channel::mpsc::{channel, Receiver, Sender},
+    Future,
+    StreamExt,
+  },
+  multiaddr::{Multiaddr, Protocol},
+  std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    pin::Pin,
+    sync::{
+      atomic::{AtomicBool, Ordering},
+      Arc,
+    },
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+  },
+};
+
+/// The largest payload that fits in a UDP datagram.
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// Datagrams the receiving thread queues up for the network. Datagrams
+/// arriving while the queue is full are dropped as if they got lost on the
+/// way, a peer sending faster than the network is polled can not fill our
+/// memory.
+pub const EVENT_QUEUE_SIZE: usize = 1024;
+
+/// Connections set up by a hello that have not received anything else yet.
+/// The source address of a hello is easily forged, hellos setting up further
+/// connections are ignored until one of them is confirmed or times out.
+pub const MAX_HANDSHAKING: usize = 64;
+
+const HELLO: u8 = 0;
+const HELLO_ACK: u8 = 1;
+const DATA: u8 = 2;
+const KEEPALIVE: u8 = 3;
+const CLOSE: u8 = 4;
+
+/// Converts an `/ip4/../udp/..` or `/ip6/../udp/..` address into a socket
+/// address.
+pub fn socket_addr(address: &Multiaddr) -> Option<SocketAddr> {
+  let mut ip = None;
+  let mut port = None;
+  for protocol in address.iter() {
+    match protocol {
+      Protocol::Ip4(addr) => ip = Some(IpAddr::V4(addr)),
+      Protocol::Ip6(addr) => ip = Some(IpAddr::V6(addr)),
+      Protocol::Udp(udp_port) => port = Some(udp_port),
+      _ => return None,
+    }
+  }
+
+  Some(SocketAddr::new(ip?, port?))
+}
+
+/// Converts a socket address into an `/ip4/../udp/..` or `/ip6/../udp/..`
+/// address.
+pub fn multiaddr(addr: SocketAddr) -> Multiaddr {
+  let mut address = Multiaddr::empty();
+  match addr.ip() {
+    IpAddr::V4(ip) => address.push(Protocol::Ip4(ip)),
+    IpAddr::V6(ip) => address.push(Protocol::Ip6(ip)),
+  }
+  address.push(Protocol::Udp(addr.port()));
+  address
+}
+
+/// Timings of the handshake and keepalives.
+#[derive(Clone, Debug)]
+pub struct UdpConfig {
+  /// How often an unanswered hello is sent again.
+  pub handshake_retry: Duration,
+  /// How many hellos are sent before the dial fails.
+  pub handshake_attempts: u32,
+  /// How often a keepalive is sent over an otherwise quiet connection.
+  pub keepalive_interval: Duration,
+  /// A connection that has not received anything for this long is closed.
+  pub idle_timeout: Duration,
+}
+
+impl Default for UdpConfig {
+  fn default() -> Self {
+    UdpConfig {
+      handshake_retry: Duration::from_millis(500),
+      handshake_attempts: 10,
+      keepalive_interval: Duration::from_secs(1),
+      idle_timeout: Duration::from_secs(5),
+    }
+  }
+}
+
+// Reported by the receiving thread to the network.
+enum UdpEvent {
+  Datagram { from: SocketAddr, payload: Vec<u8> },
+  Tick,
+}
+
+struct UdpConnection {
+  addr: SocketAddr,
+  last_received: Instant,
+  last_sent: Instant,
+  // whether the remote sent more than a hello, proving it receives at addr
+  confirmed: bool,
+}
+
+impl UdpConnection {
+  fn new(addr: SocketAddr, confirmed: bool) -> Self {
+    let now = Instant::now();
+    UdpConnection {
+      addr,
+      last_received: now,
+      last_sent: now,
+      confirmed,
+    }
+  }
+}
+
+struct Dial {
+  addr: SocketAddr,
+  attempts: u32,
+  last_sent: Instant,
+}
+
+/// Network over UDP. Logical connections are set up with a hello exchange
+/// carrying the peer ids and kept alive with keepalives. Datagrams can get
+/// lost, there are no retransmissions of messages. A hello of a connected
+/// peer from another address sets up a replacement of its connection, see
+/// `NetworkEvent::ReplacementEstablished`.
+///
+/// A single thread receives the datagrams, all connection state is kept by
+/// the network itself which can be polled by any executor.
+pub struct UdpNetwork {
+  config: UdpConfig,
+  address: NodeAddress,
+  socket: UdpSocket,
+  peers: HashMap<PeerId, Multiaddr>,
+  connections: HashMap<PeerId, UdpConnection>,
+  // connections of connected peers on another address, waiting to be
+  // accepted
+  replacements: HashMap<PeerId, UdpConnection>,
+  // the peers behind the addresses of connections and replacements
+  addrs: HashMap<SocketAddr, PeerId>,
+  dialing: HashMap<PeerId, Dial>,
+  events_rx: Receiver<UdpEvent>,
+  // set while a tick waits in the queue, ticks of an unpolled network are not
+  // piling up
+  tick_queued: Arc<AtomicBool>,
+  // events raised while handling datagrams or calls into the network
+  pending: VecDeque<NetworkEvent>,
+  waker: Option<Waker>,
+  closed: Arc<AtomicBool>,
+}
+
+impl UdpNetwork {
+  pub fn bind(address: NodeAddress) -> io::Result<Self> {
+    Self::bind_with_config(address, Default::default())
+  }
+
+  /// Binds to the address of `address`. Port 0 picks a free port,
+  /// `address()` returns the address actually bound to.
+  pub fn bind_with_config(
+    address: NodeAddress,
+    config: UdpConfig,
+  ) -> io::Result<Self> {
+    let (peer_id, bind_address) = address;
+    let bind_addr = socket_addr(&bind_address).ok_or_else(|| {
+      io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("unsupported address {}", bind_address),
+      )
+    })?;
+
+    let socket = UdpSocket::bind(bind_addr)?;
+    let local_addr = socket.local_addr()?;
+    let (events_tx, events_rx) = channel(EVENT_QUEUE_SIZE);
+    let tick_queued = Arc::new(AtomicBool::new(false));
+    let closed = Arc::new(AtomicBool::new(false));
+
+    // tick often enough to retry hellos and send keepalives on time
+    let tick = config.handshake_retry.min(config.keepalive_interval) / 2;
+    socket.set_read_timeout(Some(tick))?;
+    spawn_receiver(
+      socket.try_clone()?,
+      tick,
+      events_tx,
+      Arc::clone(&tick_queued),
+      Arc::clone(&closed),
+    );
+
+    Ok(UdpNetwork {
+      config,
+      address: (peer_id, multiaddr(local_addr)),
+      socket,
+      peers: Default::default(),
+      connections: Default::default(),
+      replacements: Default::default(),
+      addrs: Default::default(),
+      dialing: Default::default(),
+      events_rx,
+      tick_queued,
+      pending: Default::default(),
+      waker: None,
+      closed,
+    })
+  }
+
+  pub fn peer_id(&self) -> PeerId {
+    self.address.0
+  }
+
+  fn push_pending(&mut self, event: NetworkEvent) {
+    self.pending.push_back(event);
+    if let Some(waker) = self.waker.take() {
+      waker.wake();
+    }
+  }
+
+  fn send_to(
+    &self,
+    addr: SocketAddr,
+    kind: u8,
+    payload: &[u8],
+  ) -> io::Result<()> {
+    let mut datagram = Vec::with_capacity(1 + payload.len());
+    datagram.push(kind);
+    datagram.extend_from_slice(payload);
+    if datagram.len() > MAX_DATAGRAM_SIZE {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "message does not fit in a datagram",
+      ));
+    }
+
+    self.socket.send_to(&datagram, addr).map(|_| ())
+  }
+
+  fn send_hello(&self, addr: SocketAddr, kind: u8) {
+    if let Err(err) = self.send_to(addr, kind, &self.peer_id().to_bytes()) {
+      tracing::debug!("Failed to send hello to {}: {}", addr, err);
+    }
+  }
+
+  fn insert_connection(
+    &mut self,
+    peer_id: PeerId,
+    addr: SocketAddr,
+    confirmed: bool,
+  ) {
+    let connection = UdpConnection::new(addr, confirmed);
+    if let Some(previous) = self.connections.insert(peer_id, connection) {
+      self.addrs.remove(&previous.addr);
+    }
+    self.take_over_addr(addr, peer_id);
+  }
+
+  /// Points `addr` at `peer_id`. Another peer behind the address is gone, a
+  /// new identity is bound to the port it used.
+  fn take_over_addr(&mut self, addr: SocketAddr, peer_id: PeerId) {
+    match self.addrs.get(&addr).copied() {
+      Some(previous) if previous != peer_id => {
+        tracing::debug!("{} took over {} from {}", peer_id, addr, previous);
+        if self.is_replacement(&previous, addr) {
+          self.remove_replacement(&previous);
+          self.push_pending(NetworkEvent::ReplacementClosed {
+            peer_id: previous,
+          });
+        } else {
+          self.connection_closed(previous, DisconnectReason::TransportError);
+        }
+      }
+      _ => {}
+    }
+    self.addrs.insert(addr, peer_id);
+  }
+
+  fn remove_connection(&mut self, peer_id: &PeerId) -> Option<UdpConnection> {
+    let connection = self.connections.remove(peer_id)?;
+    self.addrs.remove(&connection.addr);
+    Some(connection)
+  }
+
+  fn remove_replacement(&mut self, peer_id: &PeerId) -> Option<UdpConnection> {
+    let replacement = self.replacements.remove(peer_id)?;
+    self.addrs.remove(&replacement.addr);
+    Some(replacement)
+  }
+
+  /// Drops the connection of a peer that went away, its replacement is all
+  /// that is left of the peer and takes its place.
+  fn connection_closed(&mut self, peer_id: PeerId, reason: DisconnectReason) {
+    self.remove_connection(&peer_id);
+    self.push_pending(NetworkEvent::PeerDisconnected { peer_id, reason });
+
+    if let Some(replacement) = self.replacements.remove(&peer_id) {
+      self.connections.insert(peer_id, replacement);
+      self.push_pending(NetworkEvent::InboundEstablished { peer_id });
+    }
+  }
+
+  /// The connections and replacements that only received a hello so far.
+  fn handshaking(&self) -> usize {
+    self
+      .connections
+      .values()
+      .chain(self.replacements.values())
+      .filter(|connection| !connection.confirmed)
+      .count()
+  }
+
+  fn is_replacement(&self, peer_id: &PeerId, addr: SocketAddr) -> bool {
+    self
+      .replacements
+      .get(peer_id)
+      .is_some_and(|replacement| replacement.addr == addr)
+  }
+
+  fn handle_datagram(&mut self, from: SocketAddr, datagram: &[u8]) {
+    let Some((&kind, payload)) = datagram.split_first() else {
+      return;
+    };
+
+    match kind {
+      HELLO | HELLO_ACK => {
+        let Ok(key) = <[u8; 32]>::try_from(payload) else {
+          return;
+        };
+        self.handle_hello(from, Pubkey::from_bytes(key), kind == HELLO);
+      }
+      DATA | KEEPALIVE | CLOSE => {
+        let Some(peer_id) = self.addrs.get(&from).copied() else {
+          return;
+        };
+        let replacement = self.is_replacement(&peer_id, from);
+        let connection = match replacement {
+          true => self.replacements.get_mut(&peer_id),
+          false => self.connections.get_mut(&peer_id),
+        };
+        if let Some(connection) = connection {
+          connection.last_received = Instant::now();
+          connection.confirmed = true;
+        }
+
+        match kind {
+          DATA => match codec::decode(payload) {
+            Ok(message) if replacement => {
+              self.push_pending(NetworkEvent::ReplacementMessage {
+                peer_id,
+                message,
+              })
+            }
+            Ok(message) => self
+              .push_pending(NetworkEvent::MessageReceived { peer_id, message }),
+            Err(err) => {
+              tracing::warn!(
+                "Received malformed message from {}: {}",
+                peer_id,
+                err
+              )
+            }
+          },
+          CLOSE if replacement => {
+            self.remove_replacement(&peer_id);
+            self.push_pending(NetworkEvent::ReplacementClosed { peer_id });
+          }
+          CLOSE => {
+            self.connection_closed(peer_id, DisconnectReason::RemoteRequest)
+          }
+          _ => {}
+        }
+      }
+      _ => tracing::debug!("Unknown datagram from {}", from),
+    }
+  }
+
+  fn handle_hello(
+    &mut self,
+    from: SocketAddr,
+    peer_id: PeerId,
+    is_hello: bool,
+  ) {
+    if peer_id == self.peer_id() {
+      return;
+    }
+
+    if let Some(dial) = self.dialing.get(&peer_id) {
+      // The peer dialed us at the same time. Both sides have to agree on who
+      // dialed the connection, the lower peer id is the dialer and waits for
+      // the answer to its own hello.
+      if is_hello && self.peer_id() < peer_id {
+        let addr = dial.addr;
+        self.send_hello(addr, HELLO);
+        return;
+      }
+
+      if is_hello {
+        self.send_hello(from, HELLO_ACK);
+        self.dialing.remove(&peer_id);
+        self.insert_connection(peer_id, from, true);
+        self.push_pending(NetworkEvent::InboundEstablished { peer_id });
+      } else if dial.addr == from {
+        self.dialing.remove(&peer_id);
+        self.insert_connection(peer_id, from, true);
+        self.push_pending(NetworkEvent::OutboundEstablished { peer_id });
+      }
+      return;
+    }
+
+    let replacement = self.is_replacement(&peer_id, from);
+    let known = self
+      .connections
+      .get(&peer_id)
+      .is_some_and(|connection| connection.addr == from || replacement);
+    if is_hello && !known && self.handshaking() >= MAX_HANDSHAKING {
+      tracing::debug!("Too many pending handshakes, ignoring {}", from);
+      return;
+    }
+
+    // answer every hello, our previous answer might have been lost
+    if is_hello {
+      self.send_hello(from, HELLO_ACK);
+    }
+    match self.connections.get_mut(&peer_id) {
+      Some(connection) if connection.addr == from => {
+        connection.last_received = Instant::now();
+      }
+      Some(_) if replacement => {
+        if let Some(replacement) = self.replacements.get_mut(&peer_id) {
+          replacement.last_received = Instant::now();
+        }
+      }
+      // The peer came back on another address, or someone else claims its
+      // peer id. The new address waits as the replacement of the connection
+      // until it has been accepted.
+      Some(_) if is_hello => {
+        self.remove_replacement(&peer_id);
+        self
+          .replacements
+          .insert(peer_id, UdpConnection::new(from, false));
+        self.take_over_addr(from, peer_id);
+        self.push_pending(NetworkEvent::ReplacementEstablished {
+          peer_id,
+          outbound: false,
+        });
+      }
+      Some(_) => {}
+      None if is_hello => {
+        self.insert_connection(peer_id, from, false);
+        self.push_pending(NetworkEvent::InboundEstablished { peer_id });
+      }
+      None => {}
+    }
+  }
+
+  /// Retries hellos, sends keepalives and closes idle connections.
+  fn handle_tick(&mut self) {
+    let now = Instant::now();
+
+    let mut failed = Vec::new();
+    for (peer_id, dial) in self.dialing.iter_mut() {
+      if now.duration_since(dial.last_sent) < self.config.handshake_retry {
+        continue;
+      }
+      if dial.attempts >= self.config.handshake_attempts {
+        failed.push(*peer_id);
+        continue;
+      }
+      dial.attempts += 1;
+      dial.last_sent = now;
+    }
+    let retries: Vec<SocketAddr> = self
+      .dialing
+      .iter()
+      .filter(|(peer_id, dial)| {
+        dial.last_sent == now && !failed.contains(peer_id)
+      })
+      .map(|(_, dial)| dial.addr)
+      .collect();
+    for addr in retries {
+      self.send_hello(addr, HELLO);
+    }
+    for peer_id in failed {
+      self.dialing.remove(&peer_id);
+      self.push_pending(NetworkEvent::OutboundFailure { peer_id });
+    }
+
+    let idle: Vec<PeerId> = self
+      .connections
+      .iter()
+      .filter(|(_, connection)| {
+        now.duration_since(connection.last_received) >= self.config.idle_timeout
+      })
+      .map(|(peer_id, _)| *peer_id)
+      .collect();
+    for peer_id in idle {
+      self.connection_closed(peer_id, DisconnectReason::Timeout);
+    }
+
+    let idle: Vec<PeerId> = self
+      .replacements
+      .iter()
+      .filter(|(_, replacement)| {
+        now.duration_since(replacement.last_received)
+          >= self.config.idle_timeout
+      })
+      .map(|(peer_id, _)| *peer_id)
+      .collect();
+    for peer_id in idle {
+      self.remove_replacement(&peer_id);
+      self.push_pending(NetworkEvent::ReplacementClosed { peer_id });
+    }
+
+    let quiet: Vec<SocketAddr> = self
+      .connections
+      .values_mut()
+      .chain(self.replacements.values_mut())
+      .filter(|connection| {
+        now.duration_since(connection.last_sent)
+          >= self.config.keepalive_interval
+      })
+      .map(|connection| {
+        connection.last_sent = now;
+        connection.addr
+      })
+      .collect();
+    for addr in quiet {
+      if let Err(err) = self.send_to(addr, KEEPALIVE, &[]) {
+        tracing::debug!("Failed to send keepalive to {}: {}", addr, err);
+      }
+    }
+  }
+}
+
+impl Future for UdpNetwork {
+  type Output = NetworkEvent;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+    this.waker = Some(cx.waker().clone());
+
+    loop {
+      if let Some(event) = this.pending.pop_front() {
+        return Poll::Ready(event);
+      }
+
+      match this.events_rx.poll_next_unpin(cx) {
+        Poll::Ready(Some(UdpEvent::Datagram { from, payload })) => {
+          this.handle_datagram(from, &payload)
+        }
+        Poll::Ready(Some(UdpEvent::Tick)) => {
+          this.tick_queued.store(false, Ordering::Relaxed);
+          this.handle_tick()
+        }
+        // the receiving thread stopped, nothing will arrive anymore
+        Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+      }
+    }
+  }
+}
+
+impl Network for UdpNetwork {
+  /// The address the network is bound to, with the port picked by the operating
+  /// system when binding to port 0.
+  fn address(&self) -> &NodeAddress {
+    &self.address
+  }
+
+  fn add_peer(&mut self, peer_id: Pubkey, addr: NodeAddress) {
+    tracing::debug!("Adding peer_id: {:?} with address: {:?}", peer_id, addr);
+    self.peers.insert(peer_id, addr.1);
+  }
+
+  fn connect(&mut self, peer_id: PeerId) -> NetworkResult<()> {
+    if self.connections.contains_key(&peer_id) {
+      return Err(NetworkError::AlreadyConnected(peer_id));
+    }
+    if self.dialing.contains_key(&peer_id) {
+      return Ok(());
+    }
+
+    let address = self.peers.get(&peer_id).ok_or(NetworkError::PeerNotFound)?;
+    let addr = socket_addr(address)
+      .ok_or_else(|| NetworkError::UnsupportedAddress(address.clone()))?;
+
+    tracing::debug!("Dialing {} at {}", peer_id, addr);
+    self.dialing.insert(peer_id, Dial {
+      addr,
+      attempts: 1,
+      last_sent: Instant::now(),
+    });
+    self.send_hello(addr, HELLO);
+
+    Ok(())
+  }
+
+  fn disconnect(
+    &mut self,
+    peer_id: PeerId,
+    reason: DisconnectReason,
+  ) -> NetworkResult<()> {
+    let connection = self
+      .remove_connection(&peer_id)
+      .ok_or(NetworkError::NotConnected)?;
+
+    // the close might get lost, the remote then times out eventually
+    let replacement = self.remove_replacement(&peer_id);
+    for addr in
+      std::iter::once(connection.addr).chain(replacement.map(|r| r.addr))
+    {
+      if let Err(err) = self.send_to(addr, CLOSE, &[]) {
+        tracing::debug!("Failed to send close to {}: {}", peer_id, err);
+      }
+    }
+
+    self.push_pending(NetworkEvent::PeerDisconnected { peer_id, reason });
+    Ok(())
+  }
+
+  fn send(
+    &mut self,
+    peer_id: PeerId,
+    message: ProtocolMessage,
+  ) -> NetworkResult<()> {
+    let connection = self
+      .connections
+      .get_mut(&peer_id)
+      .ok_or(NetworkError::NotConnected)?;
+    send_data(&self.socket, connection, &message)
+  }
+
+  fn send_replacement(
+    &mut self,
+    peer_id: PeerId,
+    message: ProtocolMessage,
+  ) -> NetworkResult<()> {
+    let replacement = self
+      .replacements
+      .get_mut(&peer_id)
+      .ok_or(NetworkError::NotConnected)?;
+    send_data(&self.socket, replacement, &message)
+  }
+
+  fn accept_replacement(&mut self, peer_id: PeerId) -> NetworkResult<()> {
+    let replacement = self
+      .replacements
+      .remove(&peer_id)
+      .ok_or(NetworkError::NotConnected)?;
+
+    // tell whoever is still on the old address, nothing is reported
+    if let Some(previous) = self.connections.insert(peer_id, replacement) {
+      self.addrs.remove(&previous.addr);
+      if let Err(err) = self.send_to(previous.addr, CLOSE, &[]) {
+        tracing::debug!("Failed to send close to {}: {}", peer_id, err);
+      }
+    }
+    Ok(())
+  }
+
+  fn reject_replacement(&mut self, peer_id: PeerId) -> NetworkResult<()> {
+    let replacement = self
+      .remove_replacement(&peer_id)
+      .ok_or(NetworkError::NotConnected)?;
+
+    if let Err(err) = self.send_to(replacement.addr, CLOSE, &[]) {
+      tracing::debug!("Failed to send close to {}: {}", peer_id, err);
+    }
+    Ok(())
+  }
+}
+
+/// Sends a message as a data datagram over `connection`.
+fn send_data(
+  socket: &UdpSocket,
+  connection: &mut UdpConnection,
+  message: &ProtocolMessage,
+) -> NetworkResult<()> {
+  // one byte of the datagram tells its kind
+  let encoded = codec::encode(message)?;
+  if encoded.len() >= MAX_DATAGRAM_SIZE {
+    return Err(NetworkError::MessageTooLarge {
+      size: encoded.len(),
+      limit: MAX_DATAGRAM_SIZE - 1,
+    });
+  }
+
+  let mut datagram = Vec::with_capacity(1 + encoded.len());
+  datagram.push(DATA);
+  datagram.extend_from_slice(&encoded);
+  socket.send_to(&datagram, connection.addr)?;
+  connection.last_sent = Instant::now();
+
+  Ok(())
+}
+
+impl Drop for UdpNetwork {
+  fn drop(&mut self) {
+    self.closed.store(true, Ordering::Relaxed);
+    for connection in
+      self.connections.values().chain(self.replacements.values())
+    {
+      let _ = self.send_to(connection.addr, CLOSE, &[]);
+    }
+  }
+}
+
+fn spawn_receiver(
+  socket: UdpSocket,
+  tick: Duration,
+  mut events: Sender<UdpEvent>,
+  tick_queued: Arc<AtomicBool>,
+  closed: Arc<AtomicBool>,
+) {
+  thread::spawn(move || {
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    let mut last_tick = Instant::now();
+
+    while !closed.load(Ordering::Relaxed) {
+      match socket.recv_from(&mut buf) {
+        Ok((len, from)) => {
+          let datagram = UdpEvent::Datagram {
+            from,
+            payload: buf[..len].to_vec(),
+          };
+          match events.try_send(datagram) {
+            Ok(()) => {}
+            Err(err) if err.is_disconnected() => break,
+            Err(_) => {
+              tracing::debug!(
+                "Event queue full, dropping datagram from {}",
+                from
+              )
+            }
+          }
+        }
+        Err(err)
+          if matches!(
+            err.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+          ) => {}
+        Err(err) => {
+          // an earlier datagram might have been refused by the remote
+          tracing::debug!("Failed to receive datagram: {}", err);
+        }
+      }
+
+      // a queued tick is as good as a new one
+      if last_tick.elapsed() >= tick
+        && !tick_queued.swap(true, Ordering::Relaxed)
+      {
+        last_tick = Instant::now();
+        match events.try_send(UdpEvent::Tick) {
+          Ok(()) => {}
+          Err(err) if err.is_disconnected() => break,
+          Err(_) => tick_queued.store(false, Ordering::Relaxed),
+        }
+      }
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::{behaviour::MAX_PAYLOAD_SIZE, network::Request, types::Keypair},
+    futures::{
+      executor::block_on,
+      future::{select, Either},
+    },
+    futures_timer::Delay,
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  fn config() -> UdpConfig {
+    UdpConfig {
+      handshake_retry: Duration::from_millis(50),
+      handshake_attempts: 3,
+      keepalive_interval: Duration::from_millis(50),
+      idle_timeout: Duration::from_millis(300),
+    }
+  }
+
+  fn peer_id(seed: u64) -> PeerId {
+    *Keypair::generate(&mut StdRng::seed_from_u64(seed)).public()
+  }
+
+  fn bind(seed: u64) -> UdpNetwork {
+    let address = "/ip4/127.0.0.1/udp/0".parse().unwrap();
+    UdpNetwork::bind_with_config((peer_id(seed), address), config()).unwrap()
+  }
+
+  fn next_event(network: &mut UdpNetwork) -> NetworkEvent {
+    let timeout = Delay::new(Duration::from_secs(5));
+    match block_on(select(network, timeout)) {
+      Either::Left((event, _)) => event,
+      Either::Right(_) => panic!("no network event within 5s"),
+    }
+  }
+
+  /// Polls the network for up to `duration`, returns the first event.
+  fn poll_for(
+    network: &mut UdpNetwork,
+    duration: Duration,
+  ) -> Option<NetworkEvent> {
+    match block_on(select(network, Delay::new(duration))) {
+      Either::Left((event, _)) => Some(event),
+      Either::Right(_) => None,
+    }
+  }
+
+  fn send_hello(socket: &UdpSocket, peer_id: PeerId, to: SocketAddr) {
+    let mut hello = vec![HELLO];
+    hello.extend_from_slice(&peer_id.to_bytes());
+    socket.send_to(&hello, to).unwrap();
+  }
+
+  fn connect(a: &mut UdpNetwork, b: &mut UdpNetwork) {
+    let b_id = b.peer_id();
+    a.add_peer(b_id, b.address().clone());
+    a.connect(b_id).unwrap();
+    assert!(matches!(
+      next_event(b),
+      NetworkEvent::InboundEstablished { peer_id } if peer_id == a.peer_id()
+    ));
+    assert!(matches!(
+      next_event(a),
+      NetworkEvent::OutboundEstablished { peer_id } if peer_id == b_id
+    ));
+  }
+
+  #[test]
+  fn hello_establishes_a_connection_carrying_messages() {
+    let mut a = bind(1);
+    let mut b = bind(2);
+    connect(&mut a, &mut b);
+
+    a.send(b.peer_id(), ProtocolMessage::Ping { nonce: 3 })
+      .unwrap();
+    assert!(matches!(
+      next_event(&mut b),
+      NetworkEvent::MessageReceived {
+        message: ProtocolMessage::Ping { nonce: 3 },
+        ..
+      }
+    ));
+  }
+
+  #[test]
+  fn simultaneous_dials_agree_on_the_dialer() {
+    let (mut a, mut b) = (bind(1), bind(2));
+    let (lower, higher) = match a.peer_id() < b.peer_id() {
+      true => (&mut a, &mut b),
+      false => (&mut b, &mut a),
+    };
+    let (lower_id, higher_id) = (lower.peer_id(), higher.peer_id());
+    lower.add_peer(higher_id, higher.address().clone());
+    higher.add_peer(lower_id, lower.address().clone());
+    lower.connect(higher_id).unwrap();
+    higher.connect(lower_id).unwrap();
+
+    assert!(matches!(
+      next_event(higher),
+      NetworkEvent::InboundEstablished { peer_id } if peer_id == lower_id
+    ));
+    assert!(matches!(
+      next_event(lower),
+      NetworkEvent::OutboundEstablished { peer_id } if peer_id == higher_id
+    ));
+  }
+
+  #[test]
+  fn hello_from_another_address_does_not_take_over() {
+    let mut a = bind(1);
+    let mut b = bind(2);
+    connect(&mut a, &mut b);
+    let (a_id, b_id) = (a.peer_id(), b.peer_id());
+
+    // anyone can claim the peer id of a
+    let attacker = UdpSocket::bind("127.0.0.1:0").unwrap();
+    send_hello(&attacker, a_id, socket_addr(&b.address().1).unwrap());
+    assert!(matches!(
+      next_event(&mut b),
+      NetworkEvent::ReplacementEstablished {
+        peer_id,
+        outbound: false,
+      } if peer_id == a_id
+    ));
+
+    // b still talks to a
+    b.send(a_id, ProtocolMessage::Ping { nonce: 5 }).unwrap();
+    assert!(matches!(
+      next_event(&mut a),
+      NetworkEvent::MessageReceived {
+        peer_id,
+        message: ProtocolMessage::Ping { nonce: 5 },
+      } if peer_id == b_id
+    ));
+
+    // the rejected replacement is closed
+    b.reject_replacement(a_id).unwrap();
+    attacker
+      .set_read_timeout(Some(Duration::from_secs(1)))
+      .unwrap();
+    let mut buf = [0u8; 64];
+    let closed = (0..10)
+      .any(|_| attacker.recv_from(&mut buf).is_ok_and(|_| buf[0] == CLOSE));
+    assert!(closed);
+  }
+
+  #[test]
+  fn accepted_replacement_takes_over() {
+    let mut a = bind(1);
+    let mut b = bind(2);
+    connect(&mut a, &mut b);
+    let a_id = a.peer_id();
+
+    // a comes back on another address
+    let mut restarted = bind(1);
+    restarted.add_peer(b.peer_id(), b.address().clone());
+    restarted.connect(b.peer_id()).unwrap();
+    assert!(matches!(
+      next_event(&mut b),
+      NetworkEvent::ReplacementEstablished { peer_id, .. } if peer_id == a_id
+    ));
+    assert!(matches!(
+      next_event(&mut restarted),
+      NetworkEvent::OutboundEstablished { .. }
+    ));
+
+    restarted
+      .send(b.peer_id(), ProtocolMessage::Ping { nonce: 1 })
+      .unwrap();
+    assert!(matches!(
+      next_event(&mut b),
+      NetworkEvent::ReplacementMessage {
+        message: ProtocolMessage::Ping { nonce: 1 },
+        ..
+      }
+    ));
+
+    // the old address is told the connection is gone
+    b.accept_replacement(a_id).unwrap();
+    assert!(matches!(
+      next_event(&mut a),
+      NetworkEvent::PeerDisconnected {
+        reason: DisconnectReason::RemoteRequest,
+        ..
+      }
+    ));
+    b.send(a_id, ProtocolMessage::Ping { nonce: 2 }).unwrap();
+    assert!(matches!(
+      next_event(&mut restarted),
+      NetworkEvent::MessageReceived {
+        message: ProtocolMessage::Ping { nonce: 2 },
+        ..
+      }
+    ));
+  }
+
+  #[test]
+  fn message_larger_than_a_datagram_is_rejected() {
+    let mut a = bind(1);
+    let mut b = bind(2);
+    connect(&mut a, &mut b);
+
+    let message = ProtocolMessage::Application {
+      protocol: 1,
+      payload: vec![0; MAX_DATAGRAM_SIZE],
+    };
+    assert!(matches!(
+      a.send(b.peer_id(), message),
+      Err(NetworkError::MessageTooLarge {
+        limit,
+        ..
+      }) if limit == MAX_DATAGRAM_SIZE - 1
+    ));
+
+    // the connection stays usable
+    a.send(b.peer_id(), ProtocolMessage::Ping { nonce: 1 })
+      .unwrap();
+    assert!(matches!(
+      next_event(&mut b),
+      NetworkEvent::MessageReceived {
+        message: ProtocolMessage::Ping { nonce: 1 },
+        ..
+      }
+    ));
+  }
+
+  #[test]
+  fn largest_behaviour_message_fits_a_datagram() {
+    let message = ProtocolMessage::Request {
+      id: 0,
+      request: Request::Application {
+        protocol: 1,
+        payload: vec![0; MAX_PAYLOAD_SIZE],
+      },
+    };
+    assert!(message.encoded_size() < MAX_DATAGRAM_SIZE);
+  }
+
+  #[test]
+  fn close_disconnects_the_remote() {
+    let mut a = bind(1);
+    let mut b = bind(2);
+    connect(&mut a, &mut b);
+
+    b.disconnect(a.peer_id(), DisconnectReason::LocalRequest)
+      .unwrap();
+    assert!(matches!(
+      next_event(&mut b),
+      NetworkEvent::PeerDisconnected {
+        reason: DisconnectReason::LocalRequest,
+        ..
+      }
+    ));
+    assert!(matches!(
+      next_event(&mut a),
+      NetworkEvent::PeerDisconnected {
+        reason: DisconnectReason::RemoteRequest,
+        ..
+      }
+    ));
+
+    // dropping a network closes its connections as well
+    connect(&mut a, &mut b);
+    drop(b);
+    assert!(matches!(
+      next_event(&mut a),
+      NetworkEvent::PeerDisconnected {
+        reason: DisconnectReason::RemoteRequest,
+        ..
+      }
+    ));
+  }
+
+  #[test]
+  fn silent_peer_times_out() {
+    let mut a = bind(1);
+
+    // a peer that answers the hello and then goes quiet
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let silent_id = peer_id(2);
+    let address = multiaddr(socket.local_addr().unwrap());
+    a.add_peer(silent_id, (silent_id, address));
+    a.connect(silent_id).unwrap();
+
+    let mut buf = [0u8; 64];
+    let (len, from) = socket.recv_from(&mut buf).unwrap();
+    assert_eq!(buf[0], HELLO);
+    assert_eq!(&buf[1..len], a.peer_id().to_bytes().as_slice());
+    let mut ack = vec![HELLO_ACK];
+    ack.extend_from_slice(&silent_id.to_bytes());
+    socket.send_to(&ack, from).unwrap();
+
+    assert!(matches!(
+      next_event(&mut a),
+      NetworkEvent::OutboundEstablished { .. }
+    ));
+    assert!(matches!(
+      next_event(&mut a),
+      NetworkEvent::PeerDisconnected {
+        peer_id,
+        reason: DisconnectReason::Timeout,
+      } if peer_id == silent_id
+    ));
+
+    // the connection was kept alive while it lasted, a retried hello might
+    // arrive first
+    socket
+      .set_read_timeout(Some(Duration::from_secs(1)))
+      .unwrap();
+    let kept_alive = (0..10).any(|_| {
+      socket
+        .recv_from(&mut buf)
+        .is_ok_and(|_| buf[0] == KEEPALIVE)
+    });
+    assert!(kept_alive);
+  }
+
+  #[test]
+  fn unanswered_dial_fails() {
+    let mut a = bind(1);
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let silent_id = peer_id(2);
+    let address = multiaddr(socket.local_addr().unwrap());
+    a.add_peer(silent_id, (silent_id, address));
+    a.connect(silent_id).unwrap();
+
+    assert!(matches!(
+      next_event(&mut a),
+      NetworkEvent::OutboundFailure { peer_id } if peer_id == silent_id
+    ));
+  }
+
+  /// Takes the events queued by the receiving thread without handling them.
+  fn drain_queue(network: &mut UdpNetwork) -> Vec<UdpEvent> {
+    let mut queued = Vec::new();
+    while let Ok(Some(event)) = network.events_rx.try_next() {
+      queued.push(event);
+    }
+    queued
+  }
+
+  #[test]
+  fn unpolled_network_queues_a_bounded_number_of_events() {
+    let mut a = bind(1);
+    let addr = socket_addr(&a.address().1).unwrap();
+
+    // ticks of an idle network do not pile up
+    thread::sleep(Duration::from_millis(300));
+    let queued = drain_queue(&mut a);
+    assert!(matches!(queued[..], [UdpEvent::Tick]));
+    a.tick_queued.store(false, Ordering::Relaxed);
+
+    // a peer flooding the network while nobody polls it
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for _ in 0..EVENT_QUEUE_SIZE * 4 {
+      socket.send_to(&[KEEPALIVE], addr).unwrap();
+    }
+    thread::sleep(Duration::from_millis(300));
+
+    // the channel holds one more event for its single sender
+    let queued = drain_queue(&mut a);
+    assert!(!queued.is_empty() && queued.len() <= EVENT_QUEUE_SIZE + 1);
+    let ticks = queued
+      .iter()
+      .filter(|event| matches!(event, UdpEvent::Tick))
+      .count();
+    assert!(ticks <= 1);
+  }
+
+  #[test]
+  fn hellos_beyond_the_pending_handshakes_are_ignored() {
+    let config = UdpConfig {
+      idle_timeout: Duration::from_secs(10),
+      ..config()
+    };
+    let address = "/ip4/127.0.0.1/udp/0".parse().unwrap();
+    let mut a =
+      UdpNetwork::bind_with_config((peer_id(1), address), config).unwrap();
+    let addr = socket_addr(&a.address().1).unwrap();
+
+    // peers that never send more than their hello hold on to their slots
+    let stalled: Vec<_> = (0..MAX_HANDSHAKING as u64)
+      .map(|seed| {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        send_hello(&socket, peer_id(10 + seed), addr);
+        assert!(matches!(
+          next_event(&mut a),
+          NetworkEvent::InboundEstablished { .. }
+        ));
+        socket
+      })
+      .collect();
+
+    let late = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let late_id = peer_id(2);
+    send_hello(&late, late_id, addr);
+    assert!(poll_for(&mut a, Duration::from_millis(200)).is_none());
+    late
+      .set_read_timeout(Some(Duration::from_millis(100)))
+      .unwrap();
+    assert!(late.recv_from(&mut [0u8; 64]).is_err());
+
+    // a confirmed connection frees its slot
+    stalled[0].send_to(&[KEEPALIVE], addr).unwrap();
+    send_hello(&late, late_id, addr);
+    assert!(matches!(
+      next_event(&mut a),
+      NetworkEvent::InboundEstablished { peer_id } if peer_id == late_id
+    ));
+  }
+
+  #[test]
+  fn new_identity_on_the_address_of_a_peer_disconnects_it() {
+    let mut a = bind(1);
+    let mut b = bind(2);
+    connect(&mut a, &mut b);
+    let (a_id, b_id) = (a.peer_id(), b.peer_id());
+
+    // a crashes without a close, another identity binds the port it used
+    let address = a.address().1.clone();
+    a.connections.clear();
+    drop(a);
+    let mut c = (0..50)
+      .find_map(|_| {
+        let bound =
+          UdpNetwork::bind_with_config((peer_id(3), address.clone()), config());
+        // the receiving thread of a lets go of the port on its next tick
+        thread::sleep(Duration::from_millis(20));
+        bound.ok()
+      })
+      .unwrap();
+    c.add_peer(b_id, b.address().clone());
+    c.connect(b_id).unwrap();
+
+    assert!(matches!(
+      next_event(&mut b),
+      NetworkEvent::PeerDisconnected {
+        peer_id,
+        reason: DisconnectReason::TransportError,
+      } if peer_id == a_id
+    ));
+    assert!(matches!(
+      next_event(&mut b),
+      NetworkEvent::InboundEstablished { peer_id } if peer_id == c.peer_id()
+    ));
+    assert!(matches!(
+      next_event(&mut c),
+      NetworkEvent::OutboundEstablished { peer_id } if peer_id == b_id
+    ));
+    assert!(matches!(
+      b.send(a_id, ProtocolMessage::Ping { nonce: 1 }),
+      Err(NetworkError::NotConnected)
+    ));
+  }
+}
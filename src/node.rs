@@ -1,55 +1,130 @@
+mod bootstrap;
+mod ping;
+pub mod requests;
+
 use {
   crate::{
-    network::{Network, NetworkEvent, ProtocolMessage},
+    b58::Base58Encode,
+    behaviour::{Behaviour, BehaviourAction, MAX_PAYLOAD_SIZE},
+    clock::Clock,
+    network::{
+      peer_record::PeerRecord,
+      DisconnectReason,
+      Network,
+      NetworkError,
+      NetworkEvent,
+      ProtocolId,
+      ProtocolMessage,
+      Request,
+      Response,
+    },
     node_config::{NodeConfig, NodeConfigBuilder},
-    node_events::NodeEvent,
+    node_events::{NodeEvent, NodeState},
     peer_list_manager::{PeerListManager, PeerListManagerEvent},
     storage::Storage,
-    types::PeerId,
+    types::{PeerId, PeerReputation},
   },
+  bootstrap::{Bootstrap, BootstrapEvent},
   futures::future::FutureExt,
+  ping::{PingEvent, Pinger},
+  rand::Rng,
+  requests::{Requests, TimedOut},
   std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
     future::Future,
     pin::Pin,
-    task::{Context, Poll},
+    rc::Rc,
+    task::{Context, Poll, Waker},
+    time::Duration,
   },
+  thiserror::Error,
 };
 
+/// Reputation change applied to a peer we failed to dial.
+const DIAL_FAILURE_PENALTY: PeerReputation = -1;
+
+/// Reputation change applied to a peer relaying a record with a forged
+/// signature.
+const FORGED_RECORD_PENALTY: PeerReputation = -10;
+
 #[derive(Default)]
-pub enum NodeState {
-  #[default]
-  Booting,
-  Connecting,
-  Joining,
-  Running,
-  Leaving,
-  Stopped,
+struct ShutdownState {
+  requested: bool,
+  waker: Option<Waker>,
+}
+
+/// Handle to request a graceful shutdown of a node from outside of its future.
+/// The node says goodbye to its peers, disconnects from them and stops.
+#[derive(Clone, Default)]
+pub struct ShutdownHandle(Rc<RefCell<ShutdownState>>);
+
+impl ShutdownHandle {
+  pub fn shutdown(&self) {
+    let mut state = self.0.borrow_mut();
+    state.requested = true;
+    if let Some(waker) = state.waker.take() {
+      waker.wake();
+    }
+  }
+
+  pub fn is_requested(&self) -> bool {
+    self.0.borrow().requested
+  }
+
+  /// Returns whether a shutdown has been requested and registers the task to
+  /// be woken by a later request.
+  fn poll_requested(&self, cx: &mut Context<'_>) -> bool {
+    let mut state = self.0.borrow_mut();
+    state.waker = Some(cx.waker().clone());
+    state.requested
+  }
 }
 
-pub struct Node<N, S, P>
+pub struct Node<N, S, P, C, R, B>
 where
   N: Network,
   S: Storage,
   P: PeerListManager,
+  C: Clock,
+  B: Behaviour,
 {
   config: NodeConfig,
   network: N,
   storage: S,
   peer_list_manager: P,
+  behaviour: B,
+  bootstrap: Bootstrap<C, R>,
+  ping: Pinger<C>,
+  requests: Requests<C, Request>,
+  // our own record and the newest verified record of every peer we know of
+  record: PeerRecord,
+  peer_records: HashMap<PeerId, PeerRecord>,
 
   state: NodeState,
+  // set once a peer list has been received while joining
+  peer_list_synced: bool,
+  shutdown: ShutdownHandle,
+  // events raised while handling another event, returned on the next polls
+  pending_events: VecDeque<NodeEvent<B::Event>>,
 }
 
 pub trait SimulatableNode: Future<Output = NodeEvent> {
   fn connections(&self) -> Vec<PeerId>;
   fn identity(&self) -> &PeerId;
+  fn state(&self) -> NodeState;
+  fn shutdown_handle(&self) -> ShutdownHandle;
 }
 
-impl<N, S, P> SimulatableNode for Node<N, S, P>
+impl<N, S, P, C, R, B> SimulatableNode for Node<N, S, P, C, R, B>
 where
   N: Network + Unpin,
   S: Storage + Unpin,
   P: PeerListManager + Unpin,
+  C: Clock,
+  R: Rng + Unpin,
+  B: Behaviour<Event = Infallible> + Unpin,
 {
   fn connections(&self) -> Vec<PeerId> {
     self.peer_list_manager.connections()
@@ -58,15 +133,26 @@ where
   fn identity(&self) -> &PeerId {
     self.config.identity()
   }
+
+  fn state(&self) -> NodeState {
+    self.state
+  }
+
+  fn shutdown_handle(&self) -> ShutdownHandle {
+    self.shutdown.clone()
+  }
 }
 
-impl<N, S, P> Node<N, S, P>
+impl<N, S, P, C, R, B> Node<N, S, P, C, R, B>
 where
   N: Network,
   S: Storage,
   P: PeerListManager,
+  C: Clock,
+  R: Rng,
+  B: Behaviour,
 {
-  pub fn builder() -> NodeBuilder<N, S, P> {
+  pub fn builder() -> NodeBuilder<N, S, P, C, R, B> {
     NodeBuilder::new()
   }
 
@@ -85,103 +171,443 @@ where
   pub fn identity(&self) -> &PeerId {
     self.config.identity()
   }
+
+  pub fn state(&self) -> NodeState {
+    self.state
+  }
+
+  pub fn behaviour(&self) -> &B {
+    &self.behaviour
+  }
+
+  pub fn behaviour_mut(&mut self) -> &mut B {
+    &mut self.behaviour
+  }
+
+  /// The smoothed round trip time to a connected peer, known once it
+  /// answered a ping.
+  pub fn rtt(&self, peer_id: &PeerId) -> Option<Duration> {
+    self.ping.rtt(peer_id)
+  }
+
+  /// Requests the node to leave the network. The node disconnects from all of
+  /// its peers the next time it is polled and stops once they are gone.
+  pub fn leave(&mut self) {
+    self.shutdown.shutdown();
+  }
+
+  pub fn shutdown_handle(&self) -> ShutdownHandle {
+    self.shutdown.clone()
+  }
 }
 
-impl<N, S, P> Node<N, S, P>
+impl<N, S, P, C, R, B> Node<N, S, P, C, R, B>
 where
   N: Network + Unpin,
   S: Storage,
   P: PeerListManager + Unpin,
+  C: Clock,
+  R: Rng + Unpin,
+  B: Behaviour + Unpin,
 {
-  /// When the node is in the booting state, it will attempt to connect to the
-  /// bootnode and activate the peer list manager to enable connections with
-  /// the peers of the network
-  fn poll_booting(&mut self, _cx: &mut Context<'_>) -> Poll<NodeEvent> {
-    // let first make sure we connect to the bootnode
-    // and discover enough other peers to try to join our consensus.
-    for (peer_id, _) in self.config.bootnodes() {
-      self
-        .network
-        .connect(*peer_id)
-        .expect("Failed to connect to bootnode");
-    }
+  /// Writes the peers we are connected to into storage, so the node can find
+  /// its way back into the network after a restart.
+  fn persist_peers(&mut self) {
+    let peers: Vec<String> = self
+      .peer_list_manager
+      .connections()
+      .iter()
+      .map(|peer_id| peer_id.bs58_encode())
+      .collect();
+    self.storage.write(peers.join("\n"));
+  }
+
+  /// Moves the node into `new_state` and reports the change to the runtime.
+  fn transition(&mut self, new_state: NodeState) -> Poll<NodeEvent<B::Event>> {
+    tracing::debug!(
+      "{} state change: {:?} -> {:?}",
+      self.config.identity(),
+      self.state,
+      new_state
+    );
+    self.state = new_state;
+    Poll::Ready(NodeEvent::StateChanged { new_state })
+  }
 
-    // move to the next state, waiting for dialing to succeed
-    // and to connect to a certain amount of peers
-    self.state = NodeState::Connecting;
+  /// When the node is in the booting state, it will attempt to connect to
+  /// one of the bootnodes, retrying with a backoff when all of them fail. The
+  /// peer list manager is active as well, so a restarted node can reach the
+  /// peers it persisted.
+  #[tracing::instrument(skip(self, cx), fields(peer_id=%self.config.identity()))]
+  fn poll_booting(
+    &mut self,
+    cx: &mut Context<'_>,
+  ) -> Poll<NodeEvent<B::Event>> {
+    // a node without bootnodes is the first node of the network, it waits for
+    // others to connect to it
+    if self.config.bootnodes().is_empty()
+      || !self.peer_list_manager.connections().is_empty()
+    {
+      self.bootstrap.finish();
+      // move to the next state, waiting to connect to a certain amount of
+      // peers
+      return self.transition(NodeState::Connecting);
+    }
 
-    Poll::Ready(NodeEvent::Noop)
+    match self.bootstrap.poll_unpin(cx) {
+      Poll::Ready(BootstrapEvent::Dial(peer_id)) => {
+        tracing::debug!("Dialing bootnode {}", peer_id);
+        self.connect(peer_id);
+        Poll::Ready(NodeEvent::Noop)
+      }
+      Poll::Ready(BootstrapEvent::Failed) => {
+        tracing::warn!("Failed to connect to any of the bootnodes");
+        // report the failure before the node is stopped and removed
+        if let Poll::Ready(event) = self.transition(NodeState::Stopped) {
+          self.pending_events.push_back(event);
+        }
+        Poll::Ready(NodeEvent::BootstrapFailed)
+      }
+      Poll::Pending => self.poll_overlay(cx),
+    }
   }
 
   /// The node tries to connect to the bootnodes and tries to discover the
   /// network
   #[tracing::instrument(skip(self, cx), fields(peer_id=%self.config.identity()))]
-  fn poll_connecting(&mut self, cx: &mut Context<'_>) -> Poll<NodeEvent> {
+  fn poll_connecting(
+    &mut self,
+    cx: &mut Context<'_>,
+  ) -> Poll<NodeEvent<B::Event>> {
     // check if we have enough peers to join the network
-    // if self.peer_list_manager.has_enough_peers() {
-    //   // move to the next state
-    //   self.state = NodeState::Joining;
-    // }
+    if self.peer_list_manager.connections().len() >= self.config.min_peers {
+      // wait for a peer list exchange with our new peers before running
+      self.peer_list_synced = false;
+      return self.transition(NodeState::Joining);
+    }
+
+    self.poll_overlay(cx)
+  }
+
+  /// The node has enough peers and waits until it has received a peer list,
+  /// after which it is considered part of the network.
+  #[tracing::instrument(skip(self, cx), fields(peer_id=%self.config.identity()))]
+  fn poll_joining(
+    &mut self,
+    cx: &mut Context<'_>,
+  ) -> Poll<NodeEvent<B::Event>> {
+    if self.peer_list_synced {
+      return self.transition(NodeState::Running);
+    }
+
+    self.poll_overlay(cx)
+  }
+
+  #[tracing::instrument(skip(self, cx), fields(peer_id=%self.config.identity()))]
+  fn poll_running(
+    &mut self,
+    cx: &mut Context<'_>,
+  ) -> Poll<NodeEvent<B::Event>> {
+    self.poll_overlay(cx)
+  }
+
+  /// Says goodbye to and disconnects from all peers when entering the leaving
+  /// state.
+  fn start_leaving(&mut self) -> Poll<NodeEvent<B::Event>> {
+    for peer_id in self.peer_list_manager.connections() {
+      if let Err(err) = self.network.send(peer_id, ProtocolMessage::Goodbye) {
+        tracing::warn!("Failed to say goodbye to {}: {}", peer_id, err);
+      }
+      if let Err(err) = self
+        .network
+        .disconnect(peer_id, DisconnectReason::LocalRequest)
+      {
+        // the network no longer knows the peer, there is nothing to wait for
+        tracing::warn!("Failed to disconnect from {}: {}", peer_id, err);
+        self.peer_list_manager.register_peer_disconnected(peer_id);
+      }
+    }
+
+    self.transition(NodeState::Leaving)
+  }
+
+  /// The node waits for all of its connections to be closed. New connections
+  /// are closed right away and messages are ignored.
+  #[tracing::instrument(skip(self, cx), fields(peer_id=%self.config.identity()))]
+  fn poll_leaving(
+    &mut self,
+    cx: &mut Context<'_>,
+  ) -> Poll<NodeEvent<B::Event>> {
+    if self.peer_list_manager.connections().is_empty() {
+      return self.transition(NodeState::Stopped);
+    }
+
+    if let Poll::Ready(network_event) = self.network.poll_unpin(cx) {
+      match network_event {
+        NetworkEvent::PeerDisconnected { peer_id, reason } => {
+          tracing::debug!("PeerDisconnected: {:?} {:?}", peer_id, reason);
+          self.peer_list_manager.register_peer_disconnected(peer_id);
+          self.ping.remove_peer(&peer_id);
+          self.requests.remove_peer(&peer_id);
+          self.behaviour.on_peer_disconnected(peer_id);
+          return Poll::Ready(NodeEvent::PeerDisconnected { peer_id, reason });
+        }
+        NetworkEvent::InboundEstablished { peer_id }
+        | NetworkEvent::OutboundEstablished { peer_id } => {
+          // we are on our way out, close the connection again
+          self.peer_list_manager.register_peer_connected(peer_id);
+          self.behaviour.on_peer_connected(peer_id);
+          if let Err(err) = self
+            .network
+            .disconnect(peer_id, DisconnectReason::LocalRequest)
+          {
+            tracing::warn!("Failed to disconnect from {}: {}", peer_id, err);
+            self.peer_list_manager.register_peer_disconnected(peer_id);
+          }
+        }
+        NetworkEvent::ReplacementEstablished { peer_id, .. } => {
+          self.reject_replacement(peer_id)
+        }
+        NetworkEvent::OutboundFailure { .. }
+        | NetworkEvent::MessageReceived { .. }
+        | NetworkEvent::ReplacementMessage { .. }
+        | NetworkEvent::ReplacementClosed { .. } => {}
+      }
 
+      return Poll::Ready(NodeEvent::Noop);
+    }
+
+    Poll::Pending
+  }
+
+  /// Drives the peer list manager and the network, this is the regular
+  /// operation of a node that is connected to the overlay.
+  fn poll_overlay(
+    &mut self,
+    cx: &mut Context<'_>,
+  ) -> Poll<NodeEvent<B::Event>> {
     // check if the peerlist manager has anything to do
     if let Poll::Ready(peer_list_manager_event) =
       self.peer_list_manager.poll_unpin(cx)
     {
       match peer_list_manager_event {
         PeerListManagerEvent::SyncPeerList(peer_id) => {
-          let peers = self
-            .peer_list_manager
-            .get_random_peers(self.config.peer_list_manager.exchange_peers);
-          self
-            .network
-            .send(peer_id, ProtocolMessage::PeerList { peers })
-            .expect("Failed to send peerlist");
+          self.request(peer_id, Request::GetPeers);
         }
         PeerListManagerEvent::PeerAdded(_, _) => {}
         PeerListManagerEvent::PeerRemoved(_) => {}
         PeerListManagerEvent::PeerReputationUpdated(_, _) => {}
         PeerListManagerEvent::Diconnect(peer_id) => {
-          self.network.disconnect(peer_id);
+          if let Err(err) =
+            self.network.disconnect(peer_id, DisconnectReason::Churn)
+          {
+            tracing::warn!("Failed to disconnect from {}: {}", peer_id, err);
+          }
         }
         PeerListManagerEvent::Dial(peer_id) => {
-          self.network.connect(peer_id).expect("Failed to dial peer");
+          self.connect(peer_id);
+        }
+      }
+
+      // let the runtime poll us again, the peer list manager and the network
+      // might have more work queued up
+      return Poll::Ready(NodeEvent::Noop);
+    }
+
+    // keep the connections alive and drop peers that stopped answering
+    if let Poll::Ready(ping_event) = self.ping.poll_unpin(cx) {
+      match ping_event {
+        PingEvent::Ping(peer_id, nonce) => {
+          self.send(peer_id, ProtocolMessage::Ping { nonce });
+        }
+        PingEvent::Unresponsive(peer_id) => {
+          tracing::debug!("{} stopped answering pings", peer_id);
+          if let Err(err) =
+            self.network.disconnect(peer_id, DisconnectReason::Timeout)
+          {
+            tracing::warn!("Failed to disconnect from {}: {}", peer_id, err);
+          }
+        }
+      }
+      return Poll::Ready(NodeEvent::Noop);
+    }
+
+    if let Poll::Ready(TimedOut {
+      peer_id,
+      id,
+      request,
+    }) = self.requests.poll_unpin(cx)
+    {
+      tracing::debug!("{:?} request {} to {} timed out", request, id, peer_id);
+      if let Request::Application { protocol, payload } = &request {
+        self
+          .behaviour
+          .on_request_timeout(peer_id, *protocol, payload.clone());
+      }
+      return Poll::Ready(NodeEvent::RequestTimedOut {
+        peer_id,
+        id,
+        request,
+      });
+    }
+
+    // let the behaviour talk to its peers
+    if let Poll::Ready(action) = self.behaviour.poll(cx) {
+      match action {
+        BehaviourAction::Send {
+          peer_id,
+          protocol,
+          payload,
+        } => {
+          let size = payload.len();
+          let message = ProtocolMessage::Application { protocol, payload };
+          self.send_for_behaviour(peer_id, protocol, size, message);
+        }
+        BehaviourAction::Request {
+          peer_id,
+          protocol,
+          payload,
+        } => {
+          let size = payload.len();
+          let request = Request::Application { protocol, payload };
+          let id = self.requests.start(peer_id, request.clone());
+          let message = ProtocolMessage::Request { id, request };
+          if !self.send_for_behaviour(peer_id, protocol, size, message) {
+            // nothing is coming back, the behaviour has been told already
+            self.requests.complete(peer_id, id, |_| true);
+          }
+        }
+        BehaviourAction::Respond {
+          peer_id,
+          protocol,
+          id,
+          payload,
+        } => {
+          let size = payload.len();
+          let response = Response::Application { protocol, payload };
+          let message = ProtocolMessage::Response { id, response };
+          self.send_for_behaviour(peer_id, protocol, size, message);
+        }
+        BehaviourAction::Event(event) => {
+          return Poll::Ready(NodeEvent::Behaviour(event));
         }
       }
+      return Poll::Ready(NodeEvent::Noop);
     }
 
     // handle the network event
     if let Poll::Ready(network_event) = self.network.poll_unpin(cx) {
       match network_event {
+        // a dial that resolved to the connection we have already, every
+        // connection the node took on is pinged
+        NetworkEvent::InboundEstablished { peer_id }
+        | NetworkEvent::OutboundEstablished { peer_id }
+          if self.ping.has_peer(&peer_id) =>
+        {
+          return Poll::Ready(NodeEvent::Noop);
+        }
         NetworkEvent::InboundEstablished { peer_id } => {
           tracing::debug!("InboundEstablished: {:?}", peer_id);
           self.peer_list_manager.register_peer_connected(peer_id);
+          self.ping.add_peer(peer_id);
+          self.behaviour.on_peer_connected(peer_id);
+          self.persist_peers();
 
-          // get a random list of peers to return
-          let peers = self
-            .peer_list_manager
-            .get_random_peers(self.config.peer_list_manager.exchange_peers);
-
-          self
-            .network
-            .send(peer_id, ProtocolMessage::PeerList { peers })
-            .expect("Failed to send peerlist");
+          // learn about the newcomer and the peers it knows
+          self.request(peer_id, Request::GetPeers);
 
           return Poll::Ready(NodeEvent::InboundEstablished { peer_id });
         }
-        NetworkEvent::PeerDisconnected { peer_id } => {
-          tracing::debug!("PeerDisconnected: {:?}", peer_id);
+        NetworkEvent::PeerDisconnected { peer_id, reason } => {
+          tracing::debug!("PeerDisconnected: {:?} {:?}", peer_id, reason);
           // remove from peer_list_manager
           self.peer_list_manager.register_peer_disconnected(peer_id);
-          return Poll::Ready(NodeEvent::PeerDisconnected { peer_id });
+          self.ping.remove_peer(&peer_id);
+          self.requests.remove_peer(&peer_id);
+          self.behaviour.on_peer_disconnected(peer_id);
+          self.persist_peers();
+          return Poll::Ready(NodeEvent::PeerDisconnected { peer_id, reason });
         }
         NetworkEvent::MessageReceived { peer_id, message } => {
           tracing::debug!("MessageReceived from {:?}: {:?}", peer_id, message);
           match message {
-            ProtocolMessage::PeerList { peers } => {
-              for peer_id in peers {
-                self.peer_list_manager.register_peer(peer_id);
+            ProtocolMessage::Request { id, request } => match request {
+              Request::GetPeers => {
+                let response = Response::PeerList {
+                  peers: self.peer_list(),
+                };
+                self.send(peer_id, ProtocolMessage::Response { id, response });
+              }
+              // the behaviour answers through `BehaviourAction::Respond`
+              Request::Application { protocol, payload } => {
+                if self.behaviour.supports(protocol) {
+                  self.behaviour.on_request(peer_id, protocol, id, payload);
+                } else {
+                  tracing::debug!(
+                    "Dropping request of unsupported protocol {} from {}",
+                    protocol,
+                    peer_id
+                  );
+                }
+              }
+            },
+            ProtocolMessage::Response { id, response } => {
+              let Some(request) =
+                self
+                  .requests
+                  .complete(peer_id, id, |request| response.answers(request))
+              else {
+                tracing::debug!("Unexpected response {} from {}", id, peer_id);
+                return Poll::Ready(NodeEvent::Noop);
+              };
+              match (request, response) {
+                (_, Response::PeerList { peers }) => {
+                  self.register_peer_records(peer_id, peers);
+                  self.peer_list_synced = true;
+                }
+                (
+                  Request::Application {
+                    payload: request, ..
+                  },
+                  Response::Application { protocol, payload },
+                ) => {
+                  self
+                    .behaviour
+                    .on_response(peer_id, protocol, request, payload);
+                }
+                // `answers` only lets matching pairs through
+                (Request::GetPeers, Response::Application { .. }) => {}
+              }
+            }
+            ProtocolMessage::Goodbye => {
+              // the peer is leaving the network, forget about it so we do not
+              // dial it again
+              self.peer_list_manager.remove_peer(&peer_id);
+              self.peer_records.remove(&peer_id);
+              self.persist_peers();
+            }
+            ProtocolMessage::Ping { nonce } => {
+              self.send(peer_id, ProtocolMessage::Pong { nonce });
+            }
+            ProtocolMessage::Pong { nonce } => {
+              if let Some(rtt) = self.ping.pong(&peer_id, nonce) {
+                self.peer_list_manager.update_peer_rtt(&peer_id, rtt);
               }
             }
+            ProtocolMessage::Application { protocol, payload } => {
+              if self.behaviour.supports(protocol) {
+                self.behaviour.on_message(peer_id, protocol, payload);
+              } else {
+                tracing::debug!(
+                  "Dropping message of unsupported protocol {} from {}",
+                  protocol,
+                  peer_id
+                );
+              }
+            }
+            // taken care of by the `Authenticated` network
+            ProtocolMessage::Handshake { .. }
+            | ProtocolMessage::HandshakeResponse { .. } => {}
           }
           return Poll::Ready(NodeEvent::Noop);
         }
@@ -189,45 +615,221 @@ where
           tracing::debug!("OutboundEstablished: {}", peer_id);
           // add to the peer list manager
           self.peer_list_manager.register_peer_connected(peer_id);
+          self.ping.add_peer(peer_id);
+          self.behaviour.on_peer_connected(peer_id);
+          self.persist_peers();
+          // ask our new peer for the peers it knows, which also lets a
+          // joining node find its way into the network
+          self.request(peer_id, Request::GetPeers);
+          return Poll::Ready(NodeEvent::Noop);
         }
         NetworkEvent::OutboundFailure { peer_id } => {
-          tracing::error!("OutboundFailed: {}", peer_id);
+          tracing::debug!("OutboundFailed: {}", peer_id);
+          self.dial_failed(peer_id);
+          return Poll::Ready(NodeEvent::Noop);
+        }
+        NetworkEvent::ReplacementEstablished { peer_id, .. } => {
+          self.reject_replacement(peer_id);
+          return Poll::Ready(NodeEvent::Noop);
+        }
+        NetworkEvent::ReplacementMessage { .. }
+        | NetworkEvent::ReplacementClosed { .. } => {
+          return Poll::Ready(NodeEvent::Noop);
         }
       }
     }
 
     Poll::Pending
   }
+
+  /// Our own record followed by the records of a random selection of the
+  /// peers we know of. Peers we have no record of are left out, the receiver
+  /// would not know how to reach them.
+  fn peer_list(&mut self) -> Vec<PeerRecord> {
+    let peers = self
+      .peer_list_manager
+      .get_random_peers(self.config.peer_list_manager.exchange_peers);
+
+    let mut records = vec![self.record.clone()];
+    records.extend(
+      peers
+        .iter()
+        .filter_map(|peer_id| self.peer_records.get(peer_id))
+        .cloned(),
+    );
+    records
+  }
+
+  /// Verifies the records of a peer list received from `sender`, the newer
+  /// ones tell the network where to find the peers. Peers we neither had a
+  /// record of nor are connected to are reported as discovered.
+  fn register_peer_records(
+    &mut self,
+    sender: PeerId,
+    records: Vec<PeerRecord>,
+  ) {
+    for record in records {
+      let peer_id = *record.peer_id();
+      if peer_id == *self.config.identity() {
+        continue;
+      }
+
+      if !record.verify() {
+        tracing::warn!("{} relayed a forged record of {}", sender, peer_id);
+        self
+          .peer_list_manager
+          .update_peer_reputation(&sender, FORGED_RECORD_PENALTY);
+        continue;
+      }
+
+      let known = self.peer_records.get(&peer_id);
+      if known.is_none() && !self.ping.has_peer(&peer_id) {
+        self
+          .pending_events
+          .push_back(NodeEvent::Discovered { peer_id });
+      }
+      if known.is_none_or(|known| known.seq() < record.seq()) {
+        if let Some(address) = record.node_address() {
+          self.network.add_peer(peer_id, address);
+        }
+        self.peer_records.insert(peer_id, record);
+      }
+      self.peer_list_manager.register_peer(peer_id);
+    }
+  }
+
+  /// Dials a peer. Failures are reported back to the peer list manager
+  /// instead of bringing down the node.
+  fn connect(&mut self, peer_id: PeerId) {
+    match self.network.connect(peer_id) {
+      Ok(()) => {}
+      // the connection exists or is being set up, its established event
+      // registers the peer
+      Err(NetworkError::AlreadyConnected(_)) => {
+        tracing::debug!("Already connected to {}", peer_id);
+      }
+      Err(err) => {
+        tracing::warn!("Failed to dial {}: {}", peer_id, err);
+        self.dial_failed(peer_id);
+      }
+    }
+  }
+
+  /// Sends a request, the response or a timeout comes back through
+  /// `poll_overlay`.
+  fn request(&mut self, peer_id: PeerId, request: Request) {
+    let id = self.requests.start(peer_id, request.clone());
+    self.send(peer_id, ProtocolMessage::Request { id, request });
+  }
+
+  /// Sends a message to a peer. The peer might have disconnected between
+  /// deciding to send and sending, in which case the peer list manager is
+  /// told the peer is gone. A peer that can not keep up, or a message that
+  /// is too large, only loses the message.
+  fn send(&mut self, peer_id: PeerId, message: ProtocolMessage) {
+    // the failure has been taken care of, nobody else needs to know
+    let _ = self.try_send(peer_id, message);
+  }
+
+  /// Like `send`, returning the error for the caller to report.
+  fn try_send(
+    &mut self,
+    peer_id: PeerId,
+    message: ProtocolMessage,
+  ) -> Result<(), NetworkError> {
+    let Err(err) = self.network.send(peer_id, message) else {
+      return Ok(());
+    };
+
+    tracing::warn!("Failed to send to {}: {}", peer_id, err);
+    if !matches!(
+      err,
+      NetworkError::Backpressure(_) | NetworkError::MessageTooLarge { .. }
+    ) {
+      self.peer_list_manager.register_peer_disconnected(peer_id);
+      self.ping.remove_peer(&peer_id);
+      self.requests.remove_peer(&peer_id);
+      self
+        .pending_events
+        .push_back(NodeEvent::SendFailed { peer_id });
+    }
+    Err(err)
+  }
+
+  /// Sends a message of the behaviour carrying a payload of `size` bytes.
+  /// Payloads over `MAX_PAYLOAD_SIZE` are not sent, the behaviour is told
+  /// about every message that could not be sent. Returns whether the message
+  /// has been sent.
+  fn send_for_behaviour(
+    &mut self,
+    peer_id: PeerId,
+    protocol: ProtocolId,
+    size: usize,
+    message: ProtocolMessage,
+  ) -> bool {
+    let result = if size > MAX_PAYLOAD_SIZE {
+      tracing::warn!("Payload of {} bytes for {} is too large", size, peer_id);
+      Err(NetworkError::MessageTooLarge {
+        size,
+        limit: MAX_PAYLOAD_SIZE,
+      })
+    } else {
+      self.try_send(peer_id, message)
+    };
+
+    match result {
+      Ok(()) => true,
+      Err(err) => {
+        self.behaviour.on_send_failed(peer_id, protocol, err);
+        false
+      }
+    }
+  }
+
+  /// Keeps the existing connection of a peer. Replacements only reach the
+  /// node over a network that does not authenticate its peers, which can not
+  /// tell a restarted peer from anyone claiming its peer id.
+  fn reject_replacement(&mut self, peer_id: PeerId) {
+    if let Err(err) = self.network.reject_replacement(peer_id) {
+      tracing::debug!("Failed to reject replacement of {}: {}", peer_id, err);
+    }
+  }
+
+  fn dial_failed(&mut self, peer_id: PeerId) {
+    self.bootstrap.dial_failed(peer_id);
+    self.peer_list_manager.register_peer_disconnected(peer_id);
+    self
+      .peer_list_manager
+      .update_peer_reputation(&peer_id, DIAL_FAILURE_PENALTY);
+    self
+      .pending_events
+      .push_back(NodeEvent::DialFailed { peer_id });
+  }
 }
 
-impl<N, S, P> Future for Node<N, S, P>
+impl<N, S, P, C, R, B> Future for Node<N, S, P, C, R, B>
 where
   N: Network + Unpin,
   S: Storage + Unpin,
   P: PeerListManager + Unpin,
+  C: Clock,
+  R: Rng + Unpin,
+  B: Behaviour + Unpin,
+  B::Event: Unpin,
 {
-  type Output = NodeEvent;
+  type Output = NodeEvent<B::Event>;
 
   fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
     let this = self.get_mut();
 
-    match this.state {
-      NodeState::Booting => {
-        return this.poll_booting(cx);
-      }
-      NodeState::Connecting => {
-        return this.poll_connecting(cx);
-      }
-      NodeState::Joining => {}
-      NodeState::Running => {
-        // The node is running
-      }
-      NodeState::Leaving => {
-        // The node is leaving
-      }
-      NodeState::Stopped => {
-        // The node is stopped
-      }
+    if let Some(event) = this.pending_events.pop_front() {
+      return Poll::Ready(event);
+    }
+
+    if this.shutdown.poll_requested(cx)
+      && !matches!(this.state, NodeState::Leaving | NodeState::Stopped)
+    {
+      return this.start_leaving();
     }
 
     // handle the storage events
@@ -235,42 +837,67 @@ where
       // Storage has completed its work
     }
 
-    // This example does not complete, to illustrate ongoing processing.
-    // Adjust according to your simulation's end conditions.
-    Poll::Pending
+    match this.state {
+      NodeState::Booting => this.poll_booting(cx),
+      NodeState::Connecting => this.poll_connecting(cx),
+      NodeState::Joining => this.poll_joining(cx),
+      NodeState::Running => this.poll_running(cx),
+      NodeState::Leaving => this.poll_leaving(cx),
+      // The node is stopped, there is nothing left to do
+      NodeState::Stopped => Poll::Pending,
+    }
   }
 }
 
+/// Why `NodeBuilder::build` could not assemble a node.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum NodeBuilderError {
+  #[error("{0} is required")]
+  Missing(&'static str),
+}
+
 // Builder pattern for Node
-pub struct NodeBuilder<N, S, P> {
+pub struct NodeBuilder<N, S, P, C, R, B> {
   config: Option<NodeConfig>,
   network: Option<N>,
   storage: Option<S>,
   peer_list_manager: Option<P>,
+  behaviour: Option<B>,
+  clock: Option<C>,
+  rng: Option<R>,
 }
 
-impl<N, S, P> Default for NodeBuilder<N, S, P>
+impl<N, S, P, C, R, B> Default for NodeBuilder<N, S, P, C, R, B>
 where
   N: Network,
   S: Storage,
   P: PeerListManager,
+  C: Clock,
+  R: Rng,
+  B: Behaviour,
 {
   fn default() -> Self {
     Self::new()
   }
 }
 
-impl<N, S, P> NodeBuilder<N, S, P>
+impl<N, S, P, C, R, B> NodeBuilder<N, S, P, C, R, B>
 where
   N: Network,
   S: Storage,
   P: PeerListManager,
+  C: Clock,
+  R: Rng,
+  B: Behaviour,
 {
   pub fn new() -> Self {
     Self {
       network: None,
       storage: None,
       peer_list_manager: None,
+      behaviour: None,
+      clock: None,
+      rng: None,
       config: None,
     }
   }
@@ -298,21 +925,566 @@ where
     self
   }
 
-  pub fn build(self) -> Node<N, S, P> {
-    let config = self.config.expect("Node configuration is required");
-    let mut peer_list_manager = self
-      .peer_list_manager
-      .expect("Peer list manager is required");
+  /// The application protocols the node runs, `()` for none.
+  pub fn behaviour(mut self, behaviour: B) -> Self {
+    self.behaviour = Some(behaviour);
+    self
+  }
+
+  pub fn clock(mut self, clock: C) -> Self {
+    self.clock = Some(clock);
+    self
+  }
+
+  pub fn rng(mut self, rng: R) -> Self {
+    self.rng = Some(rng);
+    self
+  }
+
+  /// Assembles the node, fails when one of its components has not been
+  /// given.
+  pub fn build(self) -> Result<Node<N, S, P, C, R, B>, NodeBuilderError> {
+    let missing = NodeBuilderError::Missing;
+    let config = self.config.ok_or(missing("node configuration"))?;
+    let mut peer_list_manager =
+      self.peer_list_manager.ok_or(missing("peer list manager"))?;
+    let mut storage = self.storage.ok_or(missing("storage"))?;
+    let mut network = self.network.ok_or(missing("network"))?;
+    let behaviour = self.behaviour.ok_or(missing("behaviour"))?;
+    let clock = self.clock.ok_or(missing("clock"))?;
+    let rng = self.rng.ok_or(missing("rng"))?;
 
     // exclude our ientity from the peer list manager
     peer_list_manager.exclude_peer(*config.identity());
 
-    Node {
+    // the network needs to know where to find the bootnodes
+    for (peer_id, address) in config.bootnodes() {
+      network.add_peer(*peer_id, (*peer_id, address.clone()));
+    }
+    // a restarted node picks up the peers it persisted before
+    for peer_id in decode_peers(&storage.read()) {
+      peer_list_manager.register_peer(peer_id);
+    }
+
+    let bootnodes = config
+      .bootnodes()
+      .iter()
+      .map(|(peer_id, _)| *peer_id)
+      .collect();
+    let ping = Pinger::new(config.ping.clone(), clock.clone());
+    let requests = Requests::new(config.request_timeout, clock.clone());
+    // sign the address the transport is bound to, the configured one might
+    // leave the port to the operating system
+    let (_, address) = network.address();
+    let record_seq = config
+      .record_seq
+      .unwrap_or_else(|| clock.now().as_millis() as u64);
+    let record =
+      PeerRecord::new(config.keypair(), vec![address.clone()], record_seq);
+    let bootstrap =
+      Bootstrap::new(config.bootstrap.clone(), bootnodes, clock, rng);
+
+    Ok(Node {
+      record,
+      peer_records: Default::default(),
       state: Default::default(),
+      peer_list_synced: false,
+      shutdown: Default::default(),
+      pending_events: Default::default(),
       config,
-      network: self.network.expect("Network component is required"),
-      storage: self.storage.expect("Storage component is required"),
+      network,
+      storage,
       peer_list_manager,
+      behaviour,
+      bootstrap,
+      ping,
+      requests,
+    })
+  }
+}
+
+/// Decodes the peers written by `Node::persist_peers`, skipping anything that
+/// is not a valid peer id.
+fn decode_peers(data: &str) -> Vec<PeerId> {
+  data.lines().filter_map(|line| line.parse().ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::{
+      clock::sim::SimClock,
+      network::{
+        memory::{MemoryHub, MemoryNetwork},
+        RequestId,
+      },
+      peer_list_manager::simple::SimplePeerListManager,
+      storage::sim::SimStorage,
+      types::{Keypair, NodeAddress},
+    },
+    futures::{future::Either, task::noop_waker_ref},
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  type TestNode<B> = Node<
+    MemoryNetwork,
+    SimStorage<StdRng>,
+    SimplePeerListManager<StdRng, SimClock>,
+    SimClock,
+    StdRng,
+    B,
+  >;
+
+  type Events<B> = Vec<NodeEvent<<B as Behaviour>::Event>>;
+
+  const LOOKUP: ProtocolId = 7;
+
+  fn address(seed: u64) -> NodeAddress {
+    let keypair = Keypair::generate(&mut StdRng::seed_from_u64(seed));
+    (
+      *keypair.public(),
+      format!("/memory/{seed}").parse().unwrap(),
+    )
+  }
+
+  fn node<B: Behaviour>(
+    hub: &MemoryHub,
+    clock: &SimClock,
+    seed: u64,
+    bootnode: Option<u64>,
+    behaviour: B,
+  ) -> TestNode<B> {
+    let mut config = NodeConfigBuilder::new()
+      .with_identity(Keypair::generate(&mut StdRng::seed_from_u64(seed)))
+      .with_address(address(seed).1)
+      .with_min_peers(1);
+    if let Some(bootnode) = bootnode {
+      config = config.with_bootnode(address(bootnode));
+    }
+    Node::builder()
+      .network(MemoryNetwork::new(hub, address(seed)))
+      .storage(SimStorage::build(StdRng::seed_from_u64(seed)))
+      .peer_list_manager(SimplePeerListManager::build(
+        StdRng::seed_from_u64(seed),
+        clock.clone(),
+      ))
+      .behaviour(behaviour)
+      .clock(clock.clone())
+      .rng(StdRng::seed_from_u64(seed))
+      .with_node_config(config.build())
+      .build()
+      .unwrap()
+  }
+
+  fn poll_events<B>(
+    node: &mut TestNode<B>,
+    events: &mut Vec<NodeEvent<B::Event>>,
+  ) -> bool
+  where
+    B: Behaviour + Unpin,
+    B::Event: Unpin,
+  {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let mut progressed = false;
+    while let Poll::Ready(event) = node.poll_unpin(&mut cx) {
+      progressed = true;
+      if !matches!(event, NodeEvent::Noop) {
+        events.push(event);
+      }
+    }
+    progressed
+  }
+
+  /// Runs both nodes for `duration` of simulated time and returns the events
+  /// they reported.
+  fn run<A, B>(
+    clock: &SimClock,
+    a: &mut TestNode<A>,
+    b: &mut TestNode<B>,
+    duration: Duration,
+  ) -> (Events<A>, Events<B>)
+  where
+    A: Behaviour + Unpin,
+    A::Event: Unpin,
+    B: Behaviour + Unpin,
+    B::Event: Unpin,
+  {
+    let deadline = clock.now() + duration;
+    let (mut a_events, mut b_events) = (Vec::new(), Vec::new());
+    loop {
+      let a_progressed = poll_events(a, &mut a_events);
+      let b_progressed = poll_events(b, &mut b_events);
+      if a_progressed || b_progressed {
+        continue;
+      }
+      match clock.next_deadline() {
+        Some(next) if next <= deadline => clock.advance_to(next),
+        _ => return (a_events, b_events),
+      }
+    }
+  }
+
+  #[derive(Debug, Clone, PartialEq, Eq)]
+  enum Lookup {
+    Answered { request: Vec<u8>, response: Vec<u8> },
+    TimedOut { request: Vec<u8> },
+  }
+
+  /// Asks every peer it connects to for the reverse of a word, and answers
+  /// such requests when `answers` is set.
+  struct Reverse {
+    answers: bool,
+    actions: VecDeque<BehaviourAction<Lookup>>,
+  }
+
+  impl Reverse {
+    fn new(answers: bool) -> Self {
+      Reverse {
+        answers,
+        actions: Default::default(),
+      }
+    }
+  }
+
+  impl Behaviour for Reverse {
+    type Event = Lookup;
+
+    fn supports(&self, protocol: ProtocolId) -> bool {
+      protocol == LOOKUP
+    }
+
+    fn on_peer_connected(&mut self, peer_id: PeerId) {
+      self.actions.push_back(BehaviourAction::Request {
+        peer_id,
+        protocol: LOOKUP,
+        payload: b"stressed".to_vec(),
+      });
+    }
+
+    fn on_peer_disconnected(&mut self, _: PeerId) {}
+
+    fn on_message(&mut self, _: PeerId, _: ProtocolId, _: Vec<u8>) {}
+
+    fn on_request(
+      &mut self,
+      peer_id: PeerId,
+      protocol: ProtocolId,
+      id: RequestId,
+      mut payload: Vec<u8>,
+    ) {
+      if self.answers {
+        payload.reverse();
+        self.actions.push_back(BehaviourAction::Respond {
+          peer_id,
+          protocol,
+          id,
+          payload,
+        });
+      }
+    }
+
+    fn on_response(
+      &mut self,
+      _: PeerId,
+      _: ProtocolId,
+      request: Vec<u8>,
+      response: Vec<u8>,
+    ) {
+      self
+        .actions
+        .push_back(BehaviourAction::Event(Lookup::Answered {
+          request,
+          response,
+        }));
+    }
+
+    fn on_request_timeout(
+      &mut self,
+      _: PeerId,
+      _: ProtocolId,
+      request: Vec<u8>,
+    ) {
+      self
+        .actions
+        .push_back(BehaviourAction::Event(Lookup::TimedOut { request }));
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<BehaviourAction<Lookup>> {
+      self.actions.pop_front().map_or(Poll::Pending, Poll::Ready)
+    }
+  }
+
+  fn behaviour_events<E>(events: Vec<NodeEvent<E>>) -> Vec<E> {
+    events
+      .into_iter()
+      .filter_map(|event| match event {
+        NodeEvent::Behaviour(event) => Some(event),
+        _ => None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn behaviours_exchange_requests_and_responses() {
+    let (hub, clock) = (MemoryHub::new(), SimClock::new());
+    let mut bootnode = node(&hub, &clock, 1, None, Reverse::new(true));
+    let mut joining = node(&hub, &clock, 2, Some(1), Reverse::new(true));
+
+    let (bootnode_events, joining_events) =
+      run(&clock, &mut bootnode, &mut joining, Duration::from_secs(3));
+    let answered = Lookup::Answered {
+      request: b"stressed".to_vec(),
+      response: b"desserts".to_vec(),
+    };
+    assert_eq!(behaviour_events(bootnode_events), vec![answered.clone()]);
+    assert_eq!(behaviour_events(joining_events), vec![answered]);
+  }
+
+  #[test]
+  fn unanswered_behaviour_request_times_out() {
+    let (hub, clock) = (MemoryHub::new(), SimClock::new());
+    // the bootnode does not run the protocol and drops the request
+    let mut bootnode = node(&hub, &clock, 1, None, ());
+    let mut joining = node(&hub, &clock, 2, Some(1), Reverse::new(true));
+
+    let (_, joining_events) =
+      run(&clock, &mut bootnode, &mut joining, Duration::from_secs(10));
+    let timed_out = joining_events.iter().any(|event| {
+      matches!(event, NodeEvent::RequestTimedOut {
+        request: Request::Application {
+          protocol: LOOKUP,
+          ..
+        },
+        ..
+      })
+    });
+    assert!(timed_out);
+    assert_eq!(behaviour_events(joining_events), vec![Lookup::TimedOut {
+      request: b"stressed".to_vec()
+    }]);
+  }
+
+  #[derive(Debug, PartialEq, Eq)]
+  enum Greeting {
+    Received(Vec<u8>),
+    Failed { size: usize, limit: usize },
+  }
+
+  /// Greets every peer it connects to and reports the greetings it gets.
+  struct Greeter {
+    protocol: ProtocolId,
+    greeting: Vec<u8>,
+    actions: VecDeque<BehaviourAction<Greeting>>,
+  }
+
+  impl Greeter {
+    fn new(protocol: ProtocolId, greeting: &[u8]) -> Self {
+      Greeter {
+        protocol,
+        greeting: greeting.to_vec(),
+        actions: Default::default(),
+      }
+    }
+  }
+
+  impl Behaviour for Greeter {
+    type Event = Greeting;
+
+    fn supports(&self, protocol: ProtocolId) -> bool {
+      protocol == self.protocol
+    }
+
+    fn on_peer_connected(&mut self, peer_id: PeerId) {
+      self.actions.push_back(BehaviourAction::Send {
+        peer_id,
+        protocol: self.protocol,
+        payload: self.greeting.clone(),
+      });
+    }
+
+    fn on_peer_disconnected(&mut self, _: PeerId) {}
+
+    fn on_message(
+      &mut self,
+      _: PeerId,
+      protocol: ProtocolId,
+      payload: Vec<u8>,
+    ) {
+      assert_eq!(protocol, self.protocol);
+      self
+        .actions
+        .push_back(BehaviourAction::Event(Greeting::Received(payload)));
+    }
+
+    fn on_send_failed(
+      &mut self,
+      _: PeerId,
+      _: ProtocolId,
+      error: NetworkError,
+    ) {
+      let NetworkError::MessageTooLarge { size, limit } = error else {
+        panic!("unexpected error {error}");
+      };
+      self
+        .actions
+        .push_back(BehaviourAction::Event(Greeting::Failed { size, limit }));
     }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<BehaviourAction<Greeting>> {
+      self.actions.pop_front().map_or(Poll::Pending, Poll::Ready)
+    }
+  }
+
+  #[test]
+  fn messages_are_routed_to_the_supporting_behaviour() {
+    let (hub, clock) = (MemoryHub::new(), SimClock::new());
+    let mut bootnode = node(
+      &hub,
+      &clock,
+      1,
+      None,
+      (Greeter::new(1, b"hello"), (Greeter::new(2, b"hi"), ())),
+    );
+    // does not run protocol 1, its greeting of protocol 3 is not understood
+    let mut joining = node(
+      &hub,
+      &clock,
+      2,
+      Some(1),
+      (Greeter::new(3, b"hey"), Greeter::new(2, b"howdy")),
+    );
+
+    let (bootnode_events, joining_events) =
+      run(&clock, &mut bootnode, &mut joining, Duration::from_secs(3));
+    assert!(matches!(
+      &behaviour_events(bootnode_events)[..],
+      [Either::Right(Either::Left(Greeting::Received(greeting)))]
+        if greeting == b"howdy"
+    ));
+    assert!(matches!(
+      &behaviour_events(joining_events)[..],
+      [Either::Right(Greeting::Received(greeting))] if greeting == b"hi"
+    ));
+  }
+
+  #[test]
+  fn oversized_payload_is_not_sent() {
+    let (hub, clock) = (MemoryHub::new(), SimClock::new());
+    let oversized = vec![0; MAX_PAYLOAD_SIZE + 1];
+    let mut bootnode = node(&hub, &clock, 1, None, Greeter::new(1, &oversized));
+    let mut joining = node(&hub, &clock, 2, Some(1), Greeter::new(1, b"hi"));
+
+    let (bootnode_events, joining_events) =
+      run(&clock, &mut bootnode, &mut joining, Duration::from_secs(3));
+    assert_eq!(behaviour_events(bootnode_events), vec![
+      Greeting::Failed {
+        size: MAX_PAYLOAD_SIZE + 1,
+        limit: MAX_PAYLOAD_SIZE,
+      },
+      Greeting::Received(b"hi".to_vec()),
+    ]);
+    assert_eq!(behaviour_events(joining_events), vec![]);
+
+    // the connection is still up
+    assert_eq!(bootnode.connections(), vec![*joining.identity()]);
+  }
+
+  #[test]
+  fn dial_racing_an_inbound_connection_does_not_hide_it() {
+    let (hub, clock) = (MemoryHub::new(), SimClock::new());
+    let mut bootnode = node(&hub, &clock, 1, None, ());
+    let mut joining = node(&hub, &clock, 2, Some(1), ());
+    let joining_id = *joining.identity();
+
+    // the joining node connects before the bootnode heard of it
+    poll_events(&mut joining, &mut Vec::new());
+    bootnode.connect(joining_id);
+    assert!(bootnode.connections().is_empty());
+
+    let mut events = Vec::new();
+    poll_events(&mut bootnode, &mut events);
+    assert!(events.contains(&NodeEvent::InboundEstablished {
+      peer_id: joining_id
+    }));
+    assert!(bootnode.ping.has_peer(&joining_id));
+  }
+
+  #[test]
+  fn peers_learned_from_a_peer_list_are_discovered() {
+    let (hub, clock) = (MemoryHub::new(), SimClock::new());
+    let mut bootnode = node(&hub, &clock, 1, None, ());
+    let mut first = node(&hub, &clock, 2, Some(1), ());
+    let mut second = node(&hub, &clock, 3, Some(1), ());
+    let first_id = *first.identity();
+
+    run(&clock, &mut bootnode, &mut first, Duration::from_secs(3));
+    let (_, events) =
+      run(&clock, &mut bootnode, &mut second, Duration::from_secs(3));
+    let discovered: Vec<_> = events
+      .iter()
+      .filter_map(|event| match event {
+        NodeEvent::Discovered { peer_id } => Some(*peer_id),
+        _ => None,
+      })
+      .collect();
+    // the bootnode is connected already, the first node is only learned of
+    // through the bootnode
+    assert_eq!(discovered, vec![first_id]);
+  }
+
+  fn states<E>(events: &[NodeEvent<E>]) -> Vec<NodeState> {
+    events
+      .iter()
+      .filter_map(|event| match event {
+        NodeEvent::StateChanged { new_state } => Some(*new_state),
+        _ => None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn node_goes_through_its_lifecycle() {
+    let (hub, clock) = (MemoryHub::new(), SimClock::new());
+    let mut bootnode = node(&hub, &clock, 1, None, ());
+    let mut joining = node(&hub, &clock, 2, Some(1), ());
+    assert_eq!(joining.state, NodeState::Booting);
+
+    let (_, events) =
+      run(&clock, &mut bootnode, &mut joining, Duration::from_secs(3));
+    assert_eq!(states(&events), vec![
+      NodeState::Connecting,
+      NodeState::Joining,
+      NodeState::Running,
+    ]);
+
+    joining.shutdown_handle().shutdown();
+    let (bootnode_events, events) =
+      run(&clock, &mut bootnode, &mut joining, Duration::from_secs(3));
+    assert_eq!(states(&events), vec![
+      NodeState::Leaving,
+      NodeState::Stopped
+    ]);
+    assert!(joining.connections().is_empty());
+    assert!(bootnode_events.iter().any(|event| matches!(
+      event,
+      NodeEvent::PeerDisconnected { peer_id, .. } if peer_id == joining.identity()
+    )));
+  }
+
+  #[test]
+  fn node_stops_when_shut_down_while_booting() {
+    let (hub, clock) = (MemoryHub::new(), SimClock::new());
+    let mut bootnode = node(&hub, &clock, 1, None, ());
+    let mut booting = node(&hub, &clock, 2, Some(1), ());
+
+    booting.shutdown_handle().shutdown();
+    let (_, events) =
+      run(&clock, &mut bootnode, &mut booting, Duration::from_secs(3));
+    assert_eq!(states(&events), vec![
+      NodeState::Leaving,
+      NodeState::Stopped
+    ]);
+    assert!(bootnode.connections().is_empty());
   }
 }
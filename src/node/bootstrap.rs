@@ -0,0 +1,239 @@
+use {
+  crate::{clock::Clock, node_config::BootstrapConfig, types::PeerId},
+  futures::{Future, FutureExt},
+  rand::{seq::SliceRandom, Rng},
+  std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+  },
+};
+
+pub enum BootstrapEvent {
+  /// Dial the given bootnode and report the outcome back.
+  Dial(PeerId),
+  /// All rounds have been exhausted without connecting to a bootnode.
+  Failed,
+}
+
+enum BootstrapState<D> {
+  Idle,
+  Dialing(PeerId),
+  BackingOff(D),
+  Done,
+}
+
+/// Dials the bootnodes one at a time in a random order. When every bootnode
+/// of a round has failed, the next round starts after an exponential backoff
+/// until the configured number of rounds is exhausted.
+pub struct Bootstrap<C: Clock, R> {
+  config: BootstrapConfig,
+  bootnodes: Vec<PeerId>,
+  remaining: Vec<PeerId>,
+  round: u32,
+  state: BootstrapState<C::Delay>,
+  clock: C,
+  rng: R,
+}
+
+impl<C: Clock, R: Rng> Bootstrap<C, R> {
+  pub fn new(
+    config: BootstrapConfig,
+    bootnodes: Vec<PeerId>,
+    clock: C,
+    rng: R,
+  ) -> Self {
+    Bootstrap {
+      config,
+      bootnodes,
+      remaining: Vec::new(),
+      round: 0,
+      state: BootstrapState::Idle,
+      clock,
+      rng,
+    }
+  }
+
+  /// Called when dialing `peer_id` failed, the next bootnode will be tried.
+  pub fn dial_failed(&mut self, peer_id: PeerId) {
+    if matches!(self.state, BootstrapState::Dialing(dialing) if dialing == peer_id)
+    {
+      self.state = BootstrapState::Idle;
+    }
+  }
+
+  /// Called once the node is connected, no further bootnodes are dialed.
+  pub fn finish(&mut self) {
+    self.state = BootstrapState::Done;
+  }
+
+  fn start_round(&mut self) {
+    self.round += 1;
+    self.remaining = self.bootnodes.clone();
+    self.remaining.shuffle(&mut self.rng);
+    tracing::debug!("Bootstrap round {}", self.round);
+  }
+
+  /// The delay before the given round, doubling with every round.
+  fn backoff(&self, round: u32) -> Duration {
+    let factor = 2u32.saturating_pow(round.saturating_sub(2));
+    self
+      .config
+      .initial_backoff
+      .saturating_mul(factor)
+      .min(self.config.max_backoff)
+  }
+}
+
+impl<C: Clock, R: Rng + Unpin> Future for Bootstrap<C, R> {
+  type Output = BootstrapEvent;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+
+    loop {
+      match &mut this.state {
+        // waiting for the outcome of the dial, or nothing left to do
+        BootstrapState::Dialing(_) | BootstrapState::Done => {
+          return Poll::Pending
+        }
+        BootstrapState::BackingOff(delay) => {
+          if delay.poll_unpin(cx).is_pending() {
+            return Poll::Pending;
+          }
+          this.state = BootstrapState::Idle;
+          this.start_round();
+        }
+        BootstrapState::Idle => {
+          if let Some(peer_id) = this.remaining.pop() {
+            this.state = BootstrapState::Dialing(peer_id);
+            return Poll::Ready(BootstrapEvent::Dial(peer_id));
+          }
+
+          if this.round >= this.config.max_rounds {
+            this.state = BootstrapState::Done;
+            return Poll::Ready(BootstrapEvent::Failed);
+          }
+
+          if this.round == 0 {
+            this.start_round();
+          } else {
+            let backoff = this.backoff(this.round + 1);
+            tracing::debug!("All bootnodes failed, retrying in {:?}", backoff);
+            this.state = BootstrapState::BackingOff(this.clock.delay(backoff));
+          }
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::clock::sim::SimClock,
+    futures::task::noop_waker_ref,
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  fn peer_id(seed: u64) -> PeerId {
+    PeerId::unique(&mut StdRng::seed_from_u64(seed))
+  }
+
+  fn bootstrap(
+    bootnodes: &[PeerId],
+  ) -> (SimClock, Bootstrap<SimClock, StdRng>) {
+    let clock = SimClock::new();
+    let config = BootstrapConfig {
+      max_rounds: 5,
+      initial_backoff: Duration::from_secs(1),
+      max_backoff: Duration::from_secs(3),
+    };
+    let bootstrap = Bootstrap::new(
+      config,
+      bootnodes.to_vec(),
+      clock.clone(),
+      StdRng::seed_from_u64(0),
+    );
+    (clock, bootstrap)
+  }
+
+  /// Fails every dial until bootstrapping gives up, returns when each
+  /// bootnode was dialed at.
+  fn fail_every_dial(
+    clock: &SimClock,
+    bootstrap: &mut Bootstrap<SimClock, StdRng>,
+  ) -> Vec<(Duration, PeerId)> {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let mut dials = Vec::new();
+    loop {
+      match bootstrap.poll_unpin(&mut cx) {
+        Poll::Ready(BootstrapEvent::Dial(peer_id)) => {
+          dials.push((clock.now(), peer_id));
+          bootstrap.dial_failed(peer_id);
+        }
+        Poll::Ready(BootstrapEvent::Failed) => return dials,
+        Poll::Pending => {
+          clock.advance().expect("bootstrap stalled");
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn every_round_dials_each_bootnode_once() {
+    let bootnodes: Vec<_> = (0..3).map(peer_id).collect();
+    let (clock, mut bootstrap) = bootstrap(&bootnodes);
+
+    let dials = fail_every_dial(&clock, &mut bootstrap);
+    assert_eq!(dials.len(), 5 * bootnodes.len());
+    for round in dials.chunks(bootnodes.len()) {
+      let mut dialed: Vec<_> =
+        round.iter().map(|(_, peer_id)| *peer_id).collect();
+      dialed.sort();
+      let mut expected = bootnodes.clone();
+      expected.sort();
+      assert_eq!(dialed, expected);
+    }
+  }
+
+  #[test]
+  fn rounds_back_off_exponentially_up_to_the_limit() {
+    let (clock, mut bootstrap) = bootstrap(&[peer_id(0)]);
+
+    let started: Vec<_> = fail_every_dial(&clock, &mut bootstrap)
+      .into_iter()
+      .map(|(at, _)| at.as_secs())
+      .collect();
+    // backoffs of 1s, 2s, then capped at 3s
+    assert_eq!(started, vec![0, 1, 3, 6, 9]);
+  }
+
+  #[test]
+  fn finished_bootstrap_dials_no_more() {
+    let bootnodes: Vec<_> = (0..2).map(peer_id).collect();
+    let (_, mut bootstrap) = bootstrap(&bootnodes);
+    let mut cx = Context::from_waker(noop_waker_ref());
+
+    let Poll::Ready(BootstrapEvent::Dial(dialed)) =
+      bootstrap.poll_unpin(&mut cx)
+    else {
+      panic!("no bootnode dialed");
+    };
+    // the outcome of another dial does not move on to the next bootnode
+    let other = *bootnodes.iter().find(|&&b| b != dialed).unwrap();
+    bootstrap.dial_failed(other);
+    assert!(bootstrap.poll_unpin(&mut cx).is_pending());
+
+    bootstrap.finish();
+    bootstrap.dial_failed(dialed);
+    assert!(bootstrap.poll_unpin(&mut cx).is_pending());
+  }
+
+  #[test]
+  fn bootstrap_without_bootnodes_fails() {
+    let (clock, mut bootstrap) = bootstrap(&[]);
+    assert!(fail_every_dial(&clock, &mut bootstrap).is_empty());
+  }
+}
@@ -0,0 +1,262 @@
+use {
+  crate::{clock::Clock, node_config::PingConfig, types::PeerId},
+  futures::{Future, FutureExt},
+  std::{
+    collections::{BTreeMap, VecDeque},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+  },
+};
+
+pub enum PingEvent {
+  /// Send a ping with the given nonce to the peer.
+  Ping(PeerId, u64),
+  /// The peer left its ping unanswered for too long.
+  Unresponsive(PeerId),
+}
+
+#[derive(Default)]
+struct PingState {
+  // nonce and send time of the ping waiting for its pong
+  outstanding: Option<(u64, Duration)>,
+  missed: u32,
+  rtt: Option<Duration>,
+}
+
+/// Pings every connected peer on an interval and estimates the round trip
+/// time from the pongs. The estimate is smoothed the way TCP does it, every
+/// sample moves it an eighth of the way.
+pub struct Pinger<C: Clock> {
+  config: PingConfig,
+  peers: BTreeMap<PeerId, PingState>,
+  next_nonce: u64,
+  interval: C::Delay,
+  clock: C,
+  events: VecDeque<PingEvent>,
+}
+
+impl<C: Clock> Pinger<C> {
+  pub fn new(config: PingConfig, clock: C) -> Self {
+    Pinger {
+      interval: clock.delay(config.interval),
+      config,
+      peers: Default::default(),
+      next_nonce: 0,
+      clock,
+      events: Default::default(),
+    }
+  }
+
+  pub fn add_peer(&mut self, peer_id: PeerId) {
+    self.peers.entry(peer_id).or_default();
+  }
+
+  pub fn remove_peer(&mut self, peer_id: &PeerId) {
+    self.peers.remove(peer_id);
+  }
+
+  pub fn has_peer(&self, peer_id: &PeerId) -> bool {
+    self.peers.contains_key(peer_id)
+  }
+
+  /// Registers a pong and returns the updated round trip time estimate.
+  /// Pongs that do not answer the outstanding ping are ignored.
+  pub fn pong(&mut self, peer_id: &PeerId, nonce: u64) -> Option<Duration> {
+    let now = self.clock.now();
+    let state = self.peers.get_mut(peer_id)?;
+    let (expected, sent_at) = state.outstanding?;
+    if expected != nonce {
+      return None;
+    }
+
+    let sample = now.saturating_sub(sent_at);
+    let rtt = match state.rtt {
+      Some(rtt) => (rtt * 7 + sample) / 8,
+      None => sample,
+    };
+    state.outstanding = None;
+    state.missed = 0;
+    state.rtt = Some(rtt);
+    Some(rtt)
+  }
+
+  pub fn rtt(&self, peer_id: &PeerId) -> Option<Duration> {
+    self.peers.get(peer_id)?.rtt
+  }
+
+  /// Pings every peer that is not waiting for a pong. A ping that is still
+  /// outstanding counts as missed and is left in place, so a slow pong still
+  /// answers it.
+  fn tick(&mut self) {
+    let now = self.clock.now();
+    let mut unresponsive = Vec::new();
+    for (peer_id, state) in self.peers.iter_mut() {
+      if state.outstanding.is_some() {
+        state.missed += 1;
+        if state.missed >= self.config.max_missed {
+          unresponsive.push(*peer_id);
+        }
+        continue;
+      }
+
+      let nonce = self.next_nonce;
+      self.next_nonce = self.next_nonce.wrapping_add(1);
+      state.outstanding = Some((nonce, now));
+      self.events.push_back(PingEvent::Ping(*peer_id, nonce));
+    }
+
+    for peer_id in unresponsive {
+      self.peers.remove(&peer_id);
+      self.events.push_back(PingEvent::Unresponsive(peer_id));
+    }
+  }
+}
+
+impl<C: Clock> Future for Pinger<C> {
+  type Output = PingEvent;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+
+    loop {
+      if let Some(event) = this.events.pop_front() {
+        return Poll::Ready(event);
+      }
+
+      if this.interval.poll_unpin(cx).is_pending() {
+        return Poll::Pending;
+      }
+      this.interval = this.clock.delay(this.config.interval);
+      this.tick();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::{clock::sim::SimClock, types::Keypair},
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  const INTERVAL: Duration = Duration::from_secs(5);
+
+  fn pinger(clock: &SimClock) -> Pinger<SimClock> {
+    let config = PingConfig {
+      interval: INTERVAL,
+      max_missed: 3,
+    };
+    Pinger::new(config, clock.clone())
+  }
+
+  fn peer_id(seed: u64) -> PeerId {
+    *Keypair::generate(&mut StdRng::seed_from_u64(seed)).public()
+  }
+
+  // moves the clock to the next interval and collects the events it causes
+  fn tick(clock: &SimClock, pinger: &mut Pinger<SimClock>) -> Vec<PingEvent> {
+    assert!(pinger.now_or_never().is_none());
+    clock.advance().unwrap();
+    std::iter::from_fn(|| pinger.now_or_never()).collect()
+  }
+
+  fn sent_nonce(events: &[PingEvent]) -> u64 {
+    match events {
+      [PingEvent::Ping(_, nonce)] => *nonce,
+      _ => panic!("expected a single ping"),
+    }
+  }
+
+  #[test]
+  fn pong_updates_the_round_trip_time() {
+    let clock = SimClock::new();
+    let mut pinger = pinger(&clock);
+    let peer = peer_id(1);
+    pinger.add_peer(peer);
+
+    let nonce = sent_nonce(&tick(&clock, &mut pinger));
+    clock.advance_to(INTERVAL + Duration::from_millis(80));
+    assert_eq!(pinger.pong(&peer, nonce), Some(Duration::from_millis(80)));
+    assert_eq!(pinger.rtt(&peer), Some(Duration::from_millis(80)));
+
+    // the sample moves the estimate an eighth of the way
+    let nonce = sent_nonce(&tick(&clock, &mut pinger));
+    clock.advance_to(INTERVAL * 2 + Duration::from_millis(160));
+    assert_eq!(pinger.pong(&peer, nonce), Some(Duration::from_millis(90)));
+
+    // an answered ping is not counted twice
+    assert_eq!(pinger.pong(&peer, nonce), None);
+  }
+
+  #[test]
+  fn unknown_nonce_is_ignored() {
+    let clock = SimClock::new();
+    let mut pinger = pinger(&clock);
+    let peer = peer_id(1);
+    pinger.add_peer(peer);
+
+    let nonce = sent_nonce(&tick(&clock, &mut pinger));
+    assert_eq!(pinger.pong(&peer, nonce + 1), None);
+    assert_eq!(pinger.pong(&peer_id(2), nonce), None);
+    assert_eq!(pinger.rtt(&peer), None);
+  }
+
+  #[test]
+  fn late_pong_still_counts() {
+    let clock = SimClock::new();
+    let mut pinger = pinger(&clock);
+    let peer = peer_id(1);
+    pinger.add_peer(peer);
+
+    let nonce = sent_nonce(&tick(&clock, &mut pinger));
+
+    // no new ping while the first one is outstanding
+    assert!(tick(&clock, &mut pinger).is_empty());
+    clock.advance_to(INTERVAL * 2 + Duration::from_secs(1));
+    assert_eq!(
+      pinger.pong(&peer, nonce),
+      Some(INTERVAL + Duration::from_secs(1))
+    );
+
+    // answered, so the peer is pinged again
+    assert!(matches!(
+      tick(&clock, &mut pinger)[..],
+      [PingEvent::Ping(p, _)] if p == peer
+    ));
+  }
+
+  #[test]
+  fn silent_peer_becomes_unresponsive() {
+    let clock = SimClock::new();
+    let mut pinger = pinger(&clock);
+    let peer = peer_id(1);
+    pinger.add_peer(peer);
+
+    sent_nonce(&tick(&clock, &mut pinger));
+    assert!(tick(&clock, &mut pinger).is_empty());
+    assert!(tick(&clock, &mut pinger).is_empty());
+    assert!(matches!(
+      tick(&clock, &mut pinger)[..],
+      [PingEvent::Unresponsive(p)] if p == peer
+    ));
+
+    // the peer is forgotten
+    assert!(tick(&clock, &mut pinger).is_empty());
+    assert_eq!(pinger.rtt(&peer), None);
+  }
+
+  #[test]
+  fn removed_peer_is_not_pinged() {
+    let clock = SimClock::new();
+    let mut pinger = pinger(&clock);
+    let peer = peer_id(1);
+    pinger.add_peer(peer);
+
+    let nonce = sent_nonce(&tick(&clock, &mut pinger));
+    pinger.remove_peer(&peer);
+    assert_eq!(pinger.pong(&peer, nonce), None);
+    assert!(tick(&clock, &mut pinger).is_empty());
+  }
+}
@@ -0,0 +1,221 @@
+use {
+  crate::{clock::Clock, network::RequestId, types::PeerId},
+  futures::{Future, FutureExt},
+  std::{
+    collections::BTreeMap,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+  },
+};
+
+/// A request that has not been answered in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedOut<Q> {
+  pub peer_id: PeerId,
+  pub id: RequestId,
+  pub request: Q,
+}
+
+struct Outstanding<Q, D> {
+  request: Q,
+  timeout: D,
+}
+
+/// Keeps track of the requests waiting for a response. Every request gets an
+/// id its response is matched with, requests that are not answered within
+/// the timeout are reported once and forgotten.
+///
+/// The node tracks its own requests with it, a behaviour running a request
+/// response protocol of its own can do the same with its requests `Q`.
+pub struct Requests<C: Clock, Q> {
+  timeout: Duration,
+  next_id: RequestId,
+  outstanding: BTreeMap<(PeerId, RequestId), Outstanding<Q, C::Delay>>,
+  clock: C,
+}
+
+impl<C: Clock, Q> Requests<C, Q> {
+  pub fn new(timeout: Duration, clock: C) -> Self {
+    Requests {
+      timeout,
+      next_id: 0,
+      outstanding: Default::default(),
+      clock,
+    }
+  }
+
+  /// Registers a request to `peer_id` and returns the id to send it with.
+  pub fn start(&mut self, peer_id: PeerId, request: Q) -> RequestId {
+    let id = self.next_id;
+    self.next_id = self.next_id.wrapping_add(1);
+    self.outstanding.insert((peer_id, id), Outstanding {
+      request,
+      timeout: self.clock.delay(self.timeout),
+    });
+    id
+  }
+
+  /// Matches a response with its request, `answers` tells whether the
+  /// response is of the kind the request expects. Responses nobody waits for,
+  /// or of the wrong kind, return `None` and leave the request outstanding.
+  pub fn complete(
+    &mut self,
+    peer_id: PeerId,
+    id: RequestId,
+    answers: impl FnOnce(&Q) -> bool,
+  ) -> Option<Q> {
+    let outstanding = self.outstanding.get(&(peer_id, id))?;
+    if !answers(&outstanding.request) {
+      return None;
+    }
+    self
+      .outstanding
+      .remove(&(peer_id, id))
+      .map(|outstanding| outstanding.request)
+  }
+
+  /// Forgets the requests to a peer that is gone, they will not be answered.
+  pub fn remove_peer(&mut self, peer_id: &PeerId) {
+    self.outstanding.retain(|(peer, _), _| peer != peer_id);
+  }
+}
+
+impl<C: Clock, Q: Unpin> Future for Requests<C, Q> {
+  type Output = TimedOut<Q>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+
+    let expired = this.outstanding.iter_mut().find_map(|(key, outstanding)| {
+      outstanding
+        .timeout
+        .poll_unpin(cx)
+        .is_ready()
+        .then_some(*key)
+    });
+    let Some((peer_id, id)) = expired else {
+      return Poll::Pending;
+    };
+
+    let outstanding = this.outstanding.remove(&(peer_id, id)).unwrap();
+    Poll::Ready(TimedOut {
+      peer_id,
+      id,
+      request: outstanding.request,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::{clock::sim::SimClock, types::Keypair},
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  const TIMEOUT: Duration = Duration::from_secs(10);
+
+  #[derive(Debug, PartialEq, Eq)]
+  enum Query {
+    Time,
+    Name,
+  }
+
+  fn requests(clock: &SimClock) -> Requests<SimClock, Query> {
+    Requests::new(TIMEOUT, clock.clone())
+  }
+
+  fn peer_id(seed: u64) -> PeerId {
+    *Keypair::generate(&mut StdRng::seed_from_u64(seed)).public()
+  }
+
+  #[test]
+  fn response_is_matched_by_peer_and_id() {
+    let clock = SimClock::new();
+    let mut requests = requests(&clock);
+    let time = requests.start(peer_id(1), Query::Time);
+    let name = requests.start(peer_id(1), Query::Name);
+    assert_ne!(time, name);
+
+    // the id is only known to the peer it was sent to
+    assert_eq!(requests.complete(peer_id(2), name, |_| true), None);
+    assert_eq!(
+      requests.complete(peer_id(1), name, |_| true),
+      Some(Query::Name)
+    );
+    // a request is answered once
+    assert_eq!(requests.complete(peer_id(1), name, |_| true), None);
+    assert_eq!(
+      requests.complete(peer_id(1), time, |_| true),
+      Some(Query::Time)
+    );
+  }
+
+  #[test]
+  fn response_of_the_wrong_kind_is_ignored() {
+    let clock = SimClock::new();
+    let mut requests = requests(&clock);
+    let id = requests.start(peer_id(1), Query::Time);
+
+    let is_time = |query: &Query| *query == Query::Time;
+    let is_name = |query: &Query| *query == Query::Name;
+    assert_eq!(requests.complete(peer_id(1), id, is_name), None);
+    // the request is still waiting for the right response
+    assert_eq!(
+      requests.complete(peer_id(1), id, is_time),
+      Some(Query::Time)
+    );
+  }
+
+  #[test]
+  fn unanswered_request_times_out_once() {
+    let clock = SimClock::new();
+    let mut requests = requests(&clock);
+    let id = requests.start(peer_id(1), Query::Time);
+    assert!((&mut requests).now_or_never().is_none());
+
+    assert_eq!(clock.advance(), Some(TIMEOUT));
+    assert_eq!(
+      (&mut requests).now_or_never(),
+      Some(TimedOut {
+        peer_id: peer_id(1),
+        id,
+        request: Query::Time,
+      })
+    );
+    assert!((&mut requests).now_or_never().is_none());
+    assert_eq!(requests.complete(peer_id(1), id, |_| true), None);
+  }
+
+  #[test]
+  fn answered_request_does_not_time_out() {
+    let clock = SimClock::new();
+    let mut requests = requests(&clock);
+    let id = requests.start(peer_id(1), Query::Time);
+    requests.complete(peer_id(1), id, |_| true).unwrap();
+
+    assert_eq!(clock.advance(), None);
+    assert!((&mut requests).now_or_never().is_none());
+  }
+
+  #[test]
+  fn removed_peer_forgets_its_requests() {
+    let clock = SimClock::new();
+    let mut requests = requests(&clock);
+    let gone = requests.start(peer_id(1), Query::Time);
+    let kept = requests.start(peer_id(2), Query::Time);
+    requests.remove_peer(&peer_id(1));
+
+    assert_eq!(requests.complete(peer_id(1), gone, |_| true), None);
+    clock.advance();
+    assert_eq!(
+      (&mut requests)
+        .now_or_never()
+        .map(|timed_out| timed_out.peer_id),
+      Some(peer_id(2))
+    );
+    assert_eq!(requests.complete(peer_id(2), kept, |_| true), None);
+  }
+}
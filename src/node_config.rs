@@ -5,14 +5,65 @@ use {
   },
   multiaddr::Multiaddr,
   rand::Rng,
-  std::collections::HashSet,
+  std::{collections::BTreeSet, time::Duration},
 };
 
+/// Controls how a booting node retries its bootnodes.
+#[derive(Clone, Debug)]
+pub struct BootstrapConfig {
+  /// The number of times every bootnode is dialed before bootstrapping fails.
+  pub max_rounds: u32,
+  /// The delay before the second round, doubled for every following round.
+  pub initial_backoff: Duration,
+  /// The upper bound for the delay between two rounds.
+  pub max_backoff: Duration,
+}
+
+impl Default for BootstrapConfig {
+  fn default() -> Self {
+    Self {
+      max_rounds: 5,
+      initial_backoff: Duration::from_secs(1),
+      max_backoff: Duration::from_secs(30),
+    }
+  }
+}
+
+/// Controls the keepalive pings sent to connected peers.
+#[derive(Clone, Debug)]
+pub struct PingConfig {
+  /// How often every peer is pinged.
+  pub interval: Duration,
+  /// The number of intervals a ping may stay unanswered before the peer is
+  /// disconnected.
+  pub max_missed: u32,
+}
+
+impl Default for PingConfig {
+  fn default() -> Self {
+    Self {
+      interval: Duration::from_secs(5),
+      max_missed: 3,
+    }
+  }
+}
+
 pub struct NodeConfig {
-  pub bootnodes: HashSet<NodeAddress>,
+  pub bootnodes: BTreeSet<NodeAddress>,
   pub identity: NodeIdentity,
   pub address: Multiaddr,
+  /// The number of connected peers required before the node joins the
+  /// network.
+  pub min_peers: usize,
+  pub bootstrap: BootstrapConfig,
+  pub ping: PingConfig,
+  /// How long a peer has to answer a request.
+  pub request_timeout: Duration,
   pub peer_list_manager: PeerListManagerConfig,
+  /// Sequence number of the signed record announcing our address, it has to
+  /// grow whenever the node restarts. Taken from the clock of the node when
+  /// not set.
+  pub record_seq: Option<u64>,
 }
 
 impl NodeConfig {
@@ -20,25 +71,35 @@ impl NodeConfig {
     NodeConfigBuilder::new()
   }
 
-  pub fn bootnodes(&self) -> &HashSet<NodeAddress> {
+  pub fn bootnodes(&self) -> &BTreeSet<NodeAddress> {
     &self.bootnodes
   }
 
   pub fn identity(&self) -> &PeerId {
+    self.identity.public()
+  }
+
+  /// The keypair proving that the node owns its identity.
+  pub fn keypair(&self) -> &NodeIdentity {
     &self.identity
   }
 
   pub fn node_address(&self) -> NodeAddress {
-    (self.identity, self.address.clone())
+    (*self.identity.public(), self.address.clone())
   }
 }
 
 /// Builder pattern for NodeConfig
 pub struct NodeConfigBuilder {
-  bootnodes: HashSet<NodeAddress>,
+  bootnodes: BTreeSet<NodeAddress>,
   identity: Option<NodeIdentity>,
   address: Option<Multiaddr>,
+  min_peers: usize,
+  bootstrap: BootstrapConfig,
+  ping: PingConfig,
+  request_timeout: Duration,
   peer_list_manager: PeerListManagerConfig,
+  record_seq: Option<u64>,
 }
 
 impl Default for NodeConfigBuilder {
@@ -50,10 +111,15 @@ impl Default for NodeConfigBuilder {
 impl NodeConfigBuilder {
   pub fn new() -> Self {
     Self {
-      bootnodes: HashSet::new(),
+      bootnodes: BTreeSet::new(),
       identity: None,
       address: None,
+      min_peers: 2,
+      bootstrap: BootstrapConfig::default(),
+      ping: PingConfig::default(),
+      request_timeout: Duration::from_secs(5),
       peer_list_manager: PeerListManagerConfig::default(),
+      record_seq: None,
     }
   }
 
@@ -72,8 +138,28 @@ impl NodeConfigBuilder {
     self
   }
 
+  pub fn with_min_peers(mut self, min_peers: usize) -> Self {
+    self.min_peers = min_peers;
+    self
+  }
+
+  pub fn with_bootstrap_config(mut self, bootstrap: BootstrapConfig) -> Self {
+    self.bootstrap = bootstrap;
+    self
+  }
+
+  pub fn with_ping_config(mut self, ping: PingConfig) -> Self {
+    self.ping = ping;
+    self
+  }
+
+  pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+    self.request_timeout = request_timeout;
+    self
+  }
+
   pub fn with_unique_identity<R: Rng>(mut self, rng: &mut R) -> Self {
-    self.identity = Some(NodeIdentity::unique(rng));
+    self.identity = Some(NodeIdentity::generate(rng));
     self
   }
 
@@ -85,12 +171,24 @@ impl NodeConfigBuilder {
     self
   }
 
+  /// Sets the sequence number of the node's peer record, defaults to the
+  /// milliseconds on the clock of the node when it is built.
+  pub fn with_record_seq(mut self, record_seq: u64) -> Self {
+    self.record_seq = Some(record_seq);
+    self
+  }
+
   pub fn build(self) -> NodeConfig {
     NodeConfig {
       bootnodes: self.bootnodes,
       identity: self.identity.expect("Node identity is required"),
       address: self.address.expect("Node address is required"),
-      peer_list_manager: PeerListManagerConfig::default(),
+      min_peers: self.min_peers,
+      bootstrap: self.bootstrap,
+      ping: self.ping,
+      request_timeout: self.request_timeout,
+      peer_list_manager: self.peer_list_manager,
+      record_seq: self.record_seq,
     }
   }
 }
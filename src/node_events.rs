@@ -1,12 +1,39 @@
-use crate::{types::PeerId};
+use {
+  crate::{
+    network::{DisconnectReason, Request, RequestId},
+    types::PeerId,
+  },
+  std::convert::Infallible,
+};
 
-pub enum NodeEvent {
+/// Events of a node, `E` are the events of its behaviour.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeEvent<E = Infallible> {
   /// The node has successfully dialed and connected to a peer.
   InboundEstablished { peer_id: PeerId },
   /// The node has disconnected from a peer.
-  PeerDisconnected { peer_id: PeerId },
+  PeerDisconnected {
+    peer_id: PeerId,
+    reason: DisconnectReason,
+  },
   /// The node has discovered a new peer through the discovery mechanism.
   Discovered { peer_id: PeerId },
+  /// Dialing a peer failed, the peer has been reported to the peer list
+  /// manager.
+  DialFailed { peer_id: PeerId },
+  /// Sending a message to a peer failed because the peer is no longer
+  /// connected.
+  SendFailed { peer_id: PeerId },
+  /// A request to a peer has not been answered in time.
+  RequestTimedOut {
+    peer_id: PeerId,
+    id: RequestId,
+    request: Request,
+  },
+  /// None of the bootnodes could be reached after retrying, the node stops.
+  BootstrapFailed,
+  /// An event reported by the behaviour of the node.
+  Behaviour(E),
   /// The node has entered a new state in the lifecycle.
   StateChanged { new_state: NodeState },
   /// Noop event to return from the future and let the runtime
@@ -14,11 +41,20 @@ pub enum NodeEvent {
   Noop,
 }
 
+/// The lifecycle of a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum NodeState {
-  /// The node is starting up.
-  Starting,
-  /// The node is running and processing events.
+  /// The node is starting up and dialing its bootnodes.
+  #[default]
+  Booting,
+  /// The node is connecting to peers until it has enough of them to join.
+  Connecting,
+  /// The node has enough peers and waits for its first peer list exchange.
+  Joining,
+  /// The node is part of the overlay and processing events.
   Running,
-  /// The node is shutting down.
-  Stopping,
+  /// The node is disconnecting from its peers.
+  Leaving,
+  /// The node has disconnected from all of its peers.
+  Stopped,
 }
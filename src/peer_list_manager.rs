@@ -55,7 +55,6 @@ impl Default for PeerListManagerConfig {
 ///
 /// Implementations should base their logic around the PeerListManagerConfig,
 /// which can be used to design the behavior of the component.
-
 pub trait PeerListManager: Future<Output = PeerListManagerEvent> {
   /// Called when a peer has been discovered. The PSM will determine if we are
   /// already connected or if we should connect.
@@ -79,5 +78,9 @@ pub trait PeerListManager: Future<Output = PeerListManagerEvent> {
     reputation_delta: PeerReputation,
   );
 
+  /// Called with the smoothed round trip time every time a connected peer
+  /// answered a ping, lets the manager prefer low-latency peers.
+  fn update_peer_rtt(&mut self, peer_id: &PeerId, rtt: Duration);
+
   fn connections(&self) -> Vec<PeerId>;
 }
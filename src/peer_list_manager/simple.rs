@@ -1,20 +1,22 @@
 use {
   super::{PeerListManagerConfig, PeerListManagerEvent},
-  crate::peer_list_manager::{PeerId, PeerListManager, PeerReputation},
+  crate::{
+    clock::Clock,
+    peer_list_manager::{PeerId, PeerListManager, PeerReputation},
+  },
   futures::{Future, FutureExt},
-  futures_timer::Delay,
   rand::{seq::SliceRandom, RngCore},
   std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashSet},
     pin::Pin,
     task::{Context, Poll},
-    time::Instant,
+    time::Duration,
   },
 };
 
 #[derive(Default, PartialEq, Eq)]
 struct DialInfo {
-  last_dial: Option<Instant>,
+  last_dial: Option<Duration>,
   attempts: u64,
 }
 
@@ -30,28 +32,33 @@ enum PeerState {
 struct PeerInfo {
   reputation: PeerReputation,
   state: PeerState,
+  rtt: Option<Duration>,
 }
 
-pub struct SimplePeerListManager<R> {
+pub struct SimplePeerListManager<R, C: Clock> {
   config: PeerListManagerConfig,
-  peers: HashMap<PeerId, PeerInfo>,
+  // ordered, so iterating the peers does not depend on the hasher state and
+  // simulations stay reproducible
+  peers: BTreeMap<PeerId, PeerInfo>,
   exclude_peers: HashSet<PeerId>,
-  interval: Delay,
-  dial_interval: Delay,
-  churn_interval: Delay,
+  interval: C::Delay,
+  dial_interval: C::Delay,
+  churn_interval: C::Delay,
+  clock: C,
   rng: R,
 }
 
-impl<R> SimplePeerListManager<R> {
-  pub fn build(rng: R) -> Self {
+impl<R, C: Clock> SimplePeerListManager<R, C> {
+  pub fn build(rng: R, clock: C) -> Self {
     let config: PeerListManagerConfig = Default::default();
     SimplePeerListManager {
-      interval: Delay::new(config.exchange_peers_interval),
-      dial_interval: Delay::new(config.dial_interval),
-      churn_interval: Delay::new(config.churn_interval),
+      interval: clock.delay(config.exchange_peers_interval),
+      dial_interval: clock.delay(config.dial_interval),
+      churn_interval: clock.delay(config.churn_interval),
       exclude_peers: Default::default(),
       config,
-      peers: HashMap::new(),
+      peers: BTreeMap::new(),
+      clock,
       rng,
     }
   }
@@ -67,7 +74,27 @@ impl<R> SimplePeerListManager<R> {
   }
 }
 
-impl<R: RngCore + Unpin> Future for SimplePeerListManager<R> {
+impl<R: RngCore, C: Clock> SimplePeerListManager<R, C> {
+  /// A random pick among the slower half of the connected peers with a known
+  /// round trip time. Always picking the slowest one would let a single
+  /// distant peer be dropped over and over.
+  fn slow_connected_peer(&mut self) -> Option<PeerId> {
+    let mut by_rtt: Vec<(Duration, PeerId)> = self
+      .peers
+      .iter()
+      .filter(|(_, peer_info)| peer_info.state == PeerState::Connected)
+      .filter_map(|(peer_id, peer_info)| Some((peer_info.rtt?, *peer_id)))
+      .collect();
+    by_rtt.sort();
+
+    let slower_half = &by_rtt[by_rtt.len() / 2..];
+    slower_half
+      .choose(&mut self.rng)
+      .map(|(_, peer_id)| *peer_id)
+  }
+}
+
+impl<R: RngCore + Unpin, C: Clock> Future for SimplePeerListManager<R, C> {
   type Output = PeerListManagerEvent;
 
   fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Self::Output> {
@@ -76,7 +103,7 @@ impl<R: RngCore + Unpin> Future for SimplePeerListManager<R> {
     // peer list from and return the sync event
     if let Poll::Ready(()) = this.interval.poll_unpin(_cx) {
       // reset the interval
-      this.interval.reset(this.config.exchange_peers_interval);
+      this.interval = this.clock.delay(this.config.exchange_peers_interval);
       if let Some(peer_id) = this.get_random_connected_peer() {
         return Poll::Ready(PeerListManagerEvent::SyncPeerList(peer_id));
       }
@@ -97,33 +124,40 @@ impl<R: RngCore + Unpin> Future for SimplePeerListManager<R> {
       .count();
 
     if let Poll::Ready(()) = this.dial_interval.poll_unpin(_cx) {
-      this.dial_interval.reset(this.config.dial_interval);
+      this.dial_interval = this.clock.delay(this.config.dial_interval);
 
       // if in range
-      if in_flight < this.config.dial_max_in_flight {
-        if connected < this.config.max_peers {
-          // reset the interval
-          // check if we have some peers to dial
-          if let Some((peer_id, peer_info)) = this
-            .peers
-            .iter_mut()
-            .find(|(_, peer_info)| peer_info.state == PeerState::Disconnected)
-          {
-            peer_info.state = PeerState::Dialing(DialInfo::default());
-            return Poll::Ready(PeerListManagerEvent::Dial(*peer_id));
-          }
+      if in_flight < this.config.dial_max_in_flight
+        && connected < this.config.max_peers
+      {
+        // check if we have some peers to dial
+        let now = this.clock.now();
+        if let Some((peer_id, peer_info)) = this
+          .peers
+          .iter_mut()
+          .find(|(_, peer_info)| peer_info.state == PeerState::Disconnected)
+        {
+          peer_info.state = PeerState::Dialing(DialInfo {
+            last_dial: Some(now),
+            attempts: 1,
+          });
+          return Poll::Ready(PeerListManagerEvent::Dial(*peer_id));
         }
       }
     }
 
     if let Poll::Ready(()) = this.churn_interval.poll_unpin(_cx) {
-      this.churn_interval.reset(this.config.churn_interval);
+      this.churn_interval = this.clock.delay(this.config.churn_interval);
 
-      // Disconnect from a random peer if the maximum number is reached. This
+      // Disconnect from one of the slower peers, or a random one while no
+      // round trip times are known, if the maximum number is reached. This
       // churn in connections fosters a more robust network topology over
       // time.
       if connected >= this.config.max_peers - this.config.churn_threshold {
-        if let Some(peer_id) = this.get_random_connected_peer() {
+        if let Some(peer_id) = this
+          .slow_connected_peer()
+          .or_else(|| this.get_random_connected_peer())
+        {
           return Poll::Ready(PeerListManagerEvent::Diconnect(peer_id));
         }
       }
@@ -133,7 +167,9 @@ impl<R: RngCore + Unpin> Future for SimplePeerListManager<R> {
   }
 }
 
-impl<R: RngCore + Unpin> PeerListManager for SimplePeerListManager<R> {
+impl<R: RngCore + Unpin, C: Clock> PeerListManager
+  for SimplePeerListManager<R, C>
+{
   fn exclude_peer(&mut self, peer_id: PeerId) {
     self.exclude_peers.insert(peer_id);
   }
@@ -160,6 +196,12 @@ impl<R: RngCore + Unpin> PeerListManager for SimplePeerListManager<R> {
     }
   }
 
+  fn update_peer_rtt(&mut self, peer_id: &PeerId, rtt: Duration) {
+    if let Some(peer_info) = self.peers.get_mut(peer_id) {
+      peer_info.rtt = Some(rtt);
+    }
+  }
+
   /// Returna a single random peer
   fn get_random_connected_peer(&mut self) -> Option<PeerId> {
     // TODO: optimize this
@@ -201,15 +243,21 @@ impl<R: RngCore + Unpin> PeerListManager for SimplePeerListManager<R> {
     // list. If it is not registered, add it to the connected list.
     self.register_peer(peer_id);
 
-    // Now make sure the stat of the peer is connected
-    self.peers.get_mut(&peer_id).unwrap().state = PeerState::Connected;
+    // Now make sure the stat of the peer is connected, excluded peers are
+    // never tracked
+    if let Some(peer) = self.peers.get_mut(&peer_id) {
+      peer.state = PeerState::Connected;
+    }
   }
 
   fn register_peer_disconnected(&mut self, peer_id: PeerId) {
     // Check if this peer is in the list, and remove it from the connected list
     // if present.
+    // The round trip time was measured on the closed connection, a new one
+    // may take another route.
     if let Some(peer) = self.peers.get_mut(&peer_id) {
       peer.state = PeerState::Disconnected;
+      peer.rtt = None;
     }
   }
 
@@ -217,3 +265,63 @@ impl<R: RngCore + Unpin> PeerListManager for SimplePeerListManager<R> {
     self.connected_peers().collect()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::{clock::sim::SimClock, types::Keypair},
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  fn manager(seed: u64) -> SimplePeerListManager<StdRng, SimClock> {
+    SimplePeerListManager::build(StdRng::seed_from_u64(seed), SimClock::new())
+  }
+
+  fn peer_id(seed: u64) -> PeerId {
+    *Keypair::generate(&mut StdRng::seed_from_u64(seed)).public()
+  }
+
+  #[test]
+  fn churn_picks_among_the_slower_half() {
+    let mut manager = manager(1);
+    let peers: Vec<PeerId> = (0..6).map(peer_id).collect();
+    for (rtt, peer_id) in (1..).zip(&peers) {
+      manager.register_peer_connected(*peer_id);
+      manager.update_peer_rtt(peer_id, Duration::from_millis(rtt * 10));
+    }
+
+    let picked: HashSet<PeerId> = (0..100)
+      .filter_map(|_| manager.slow_connected_peer())
+      .collect();
+    assert_eq!(picked, peers[3..].iter().copied().collect());
+  }
+
+  #[test]
+  fn churn_ignores_peers_without_round_trip_time() {
+    let mut manager = manager(1);
+    manager.register_peer_connected(peer_id(1));
+    assert_eq!(manager.slow_connected_peer(), None);
+
+    manager.update_peer_rtt(&peer_id(1), Duration::from_millis(10));
+    assert_eq!(manager.slow_connected_peer(), Some(peer_id(1)));
+  }
+
+  #[test]
+  fn excluded_peer_is_never_connected() {
+    let mut manager = manager(1);
+    manager.exclude_peer(peer_id(1));
+    manager.register_peer_connected(peer_id(1));
+    assert!(manager.connections().is_empty());
+  }
+
+  #[test]
+  fn disconnect_forgets_the_round_trip_time() {
+    let mut manager = manager(1);
+    manager.register_peer_connected(peer_id(1));
+    manager.update_peer_rtt(&peer_id(1), Duration::from_millis(10));
+    manager.register_peer_disconnected(peer_id(1));
+    manager.register_peer_connected(peer_id(1));
+    assert_eq!(manager.slow_connected_peer(), None);
+  }
+}
@@ -1,12 +1,19 @@
 use {
-  crate::{b58::Base58Encode, types::NodeAddress},
+  crate::{
+    b58::{Base58Decode, Base58DecodeError, Base58Encode},
+    types::NodeAddress,
+  },
   core::fmt,
+  ed25519_dalek::{Signer, SigningKey, VerifyingKey},
   multiaddr::Multiaddr,
   rand::Rng,
-  std::fmt::{Display, Formatter},
+  std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+  },
 };
 
-#[derive(PartialEq, Eq, Hash, Copy, Clone)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
 pub struct Pubkey {
   key: [u8; 32],
 }
@@ -18,6 +25,10 @@ impl Pubkey {
     Pubkey { key: rng.gen() }
   }
 
+  pub fn from_bytes(key: [u8; 32]) -> Self {
+    Pubkey { key }
+  }
+
   pub fn to_bytes(&self) -> [u8; 32] {
     self.key
   }
@@ -25,6 +36,16 @@ impl Pubkey {
   pub fn into_node_address(&self, addr: Multiaddr) -> NodeAddress {
     (*self, addr)
   }
+
+  /// Whether `signature` is a valid ed25519 signature of `message` by this
+  /// key. Keys that are not valid curve points never verify.
+  pub fn verify(&self, message: &[u8], signature: &Signature) -> bool {
+    let Ok(key) = VerifyingKey::from_bytes(&self.key) else {
+      return false;
+    };
+    let signature = ed25519_dalek::Signature::from_bytes(&signature.0);
+    key.verify_strict(message, &signature).is_ok()
+  }
 }
 
 impl std::fmt::Debug for Pubkey {
@@ -38,3 +59,121 @@ impl Display for Pubkey {
     write!(f, "{}", self.bs58_encode())
   }
 }
+
+/// Parses the base58 form printed by `Display`.
+impl FromStr for Pubkey {
+  type Err = Base58DecodeError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Pubkey::bs58_decode(s)
+  }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Pubkey {
+  fn serialize<S: serde::Serializer>(
+    &self,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Pubkey {
+  fn deserialize<D: serde::Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    let encoded = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+    encoded.parse().map_err(serde::de::Error::custom)
+  }
+}
+
+/// An ed25519 signature.
+#[derive(PartialEq, Eq, Copy, Clone)]
+pub struct Signature([u8; 64]);
+
+impl Signature {
+  pub fn from_bytes(bytes: [u8; 64]) -> Self {
+    Signature(bytes)
+  }
+
+  pub fn to_bytes(&self) -> [u8; 64] {
+    self.0
+  }
+}
+
+impl std::fmt::Debug for Signature {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(f, "{}", self.0.bs58_encode())
+  }
+}
+
+/// An ed25519 keypair, its public key is the peer id of the node holding it.
+#[derive(Clone)]
+pub struct Keypair {
+  secret: SigningKey,
+  public: Pubkey,
+}
+
+impl Keypair {
+  /// Generates a new keypair, deterministic for a seeded rng.
+  pub fn generate<R: Rng>(rng: &mut R) -> Self {
+    Self::from_secret(rng.gen())
+  }
+
+  pub fn from_secret(secret: [u8; 32]) -> Self {
+    let secret = SigningKey::from_bytes(&secret);
+    let public = Pubkey::from_bytes(secret.verifying_key().to_bytes());
+    Keypair { secret, public }
+  }
+
+  pub fn public(&self) -> &Pubkey {
+    &self.public
+  }
+
+  /// The secret key, keep it out of logs.
+  pub fn to_secret(&self) -> [u8; 32] {
+    self.secret.to_bytes()
+  }
+
+  pub fn sign(&self, message: &[u8]) -> Signature {
+    Signature(self.secret.sign(message).to_bytes())
+  }
+}
+
+// never print the secret key
+impl std::fmt::Debug for Keypair {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(f, "Keypair({})", self.public)
+  }
+}
+
+/// Parses the base58 encoded secret key.
+impl FromStr for Keypair {
+  type Err = Base58DecodeError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Ok(Keypair::from_secret(Base58Decode::bs58_decode(s)?))
+  }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Keypair {
+  fn serialize<S: serde::Serializer>(
+    &self,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&self.to_secret().bs58_encode())
+  }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Keypair {
+  fn deserialize<D: serde::Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    let encoded = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+    encoded.parse().map_err(serde::de::Error::custom)
+  }
+}
@@ -11,4 +11,3 @@ impl<T: SeedableRng + RngCore> GeneratesRngSeed<T> for T {
     Self::seed_from_u64(self.next_u64())
   }
 }
-
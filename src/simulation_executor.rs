@@ -1,64 +1,887 @@
 use {
-  crate::{node::SimulatableNode, node_events::NodeEvent},
+  crate::{
+    clock::{sim::SimClock, Clock},
+    node::SimulatableNode,
+    node_events::{NodeEvent, NodeState},
+    types::PeerId,
+  },
   futures::{
-    stream::{FuturesOrdered, FuturesUnordered},
+    future::LocalBoxFuture,
+    stream::FuturesUnordered,
+    task::{waker, ArcWake},
     Future,
     FutureExt,
     StreamExt,
   },
-  futures_timer::Delay,
-  std::{pin::Pin, task::Context, time::Duration},
+  rand::{seq::SliceRandom, Rng},
+  std::{
+    collections::{BTreeSet, HashMap},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+  },
 };
 
-type SimulatableNodeFuture = Pin<Box<dyn SimulatableNode>>;
+pub type SimulatableNodeFuture = Pin<Box<dyn SimulatableNode>>;
+
+type NodeFactory = Box<dyn FnMut() -> SimulatableNodeFuture>;
+
+/// The futures driven by the executor. The ordering determines the order in
+/// which woken tasks are polled within a tick.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Task {
+  Network,
+  Scheduled,
+  Node(usize),
+}
+
+/// Actions that are executed once the simulated clock reaches their time.
+enum Action {
+  Join(SimulatableNodeFuture),
+  Stop(PeerId),
+  Kill(PeerId),
+  Restart(PeerId),
+}
+
+/// Set of tasks that have been woken and need to be polled.
+#[derive(Default)]
+struct ReadyTasks(Mutex<BTreeSet<Task>>);
+
+impl ReadyTasks {
+  fn schedule(&self, task: Task) {
+    self.0.lock().unwrap().insert(task);
+  }
+
+  fn take(&self) -> BTreeSet<Task> {
+    std::mem::take(&mut *self.0.lock().unwrap())
+  }
+
+  fn is_empty(&self) -> bool {
+    self.0.lock().unwrap().is_empty()
+  }
+}
+
+struct TaskWaker {
+  task: Task,
+  ready: Arc<ReadyTasks>,
+}
+
+impl ArcWake for TaskWaker {
+  fn wake_by_ref(arc_self: &Arc<Self>) {
+    arc_self.ready.schedule(arc_self.task);
+  }
+}
+
+/// The order in which the woken nodes are polled within a tick. The network
+/// and the scheduled actions are always polled before the nodes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum PollOrder {
+  /// Nodes are polled in the order they joined the simulation.
+  Sequential,
+  /// Nodes are polled in a random order that is drawn from the executor rng
+  /// on every tick. This exercises races between nodes that a fixed order
+  /// would hide.
+  #[default]
+  Shuffled,
+}
+
+/// What happened during a single call to `SimulationExecutor::run_tick`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TickOutcome {
+  /// The given number of woken tasks have been polled.
+  Polled(usize),
+  /// Nothing was runnable, the clock jumped to the next deadline.
+  Advanced(Duration),
+  /// Nothing is runnable and there are no pending timers, the simulation
+  /// will not make any further progress.
+  Idle,
+}
+
+/// A `NodeEvent` emitted by one of the nodes, tagged with the node that
+/// emitted it and the simulated time at which it happened.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SimulationEvent {
+  pub at: Duration,
+  pub peer_id: PeerId,
+  pub event: NodeEvent,
+}
+
+type EventSubscriber = Box<dyn FnMut(&SimulationEvent)>;
+
+/// Limits how long `SimulationExecutor::run_until` keeps running. An empty
+/// budget runs until the predicate holds or the simulation becomes idle.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct RunBudget {
+  max_ticks: Option<usize>,
+  max_duration: Option<Duration>,
+}
 
-pub struct SimulationExecutor<N> {
+impl RunBudget {
+  /// Run for at most `max_ticks` calls to `run_tick`.
+  pub fn ticks(max_ticks: usize) -> Self {
+    Self::default().with_ticks(max_ticks)
+  }
+
+  /// Run for at most `max_duration` of simulated time.
+  pub fn duration(max_duration: Duration) -> Self {
+    Self::default().with_duration(max_duration)
+  }
+
+  pub fn with_ticks(mut self, max_ticks: usize) -> Self {
+    self.max_ticks = Some(max_ticks);
+    self
+  }
+
+  pub fn with_duration(mut self, max_duration: Duration) -> Self {
+    self.max_duration = Some(max_duration);
+    self
+  }
+}
+
+/// Why a bounded run returned.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RunOutcome {
+  /// The predicate held.
+  Satisfied,
+  /// The tick or time budget ran out before the predicate held.
+  BudgetExhausted,
+  /// Nothing is runnable and no timers are pending.
+  Idle,
+}
+
+/// Summary of a bounded run.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RunReport {
+  pub outcome: RunOutcome,
+  /// The number of ticks executed during the run.
+  pub ticks: usize,
+  /// The simulated time at which the run returned.
+  pub now: Duration,
+}
+
+impl RunReport {
+  pub fn is_satisfied(&self) -> bool {
+    self.outcome == RunOutcome::Satisfied
+  }
+}
+
+/// Read only view on a simulation handed to run predicates.
+pub struct SimulationView<'a> {
+  now: Duration,
+  nodes: &'a [Option<SimulatableNodeFuture>],
+}
+
+impl<'a> SimulationView<'a> {
+  /// The current simulated time.
+  pub fn now(&self) -> Duration {
+    self.now
+  }
+
+  /// The nodes that have joined and not yet left the simulation.
+  pub fn nodes(&self) -> impl Iterator<Item = &'a dyn SimulatableNode> + 'a {
+    self
+      .nodes
+      .iter()
+      .flatten()
+      .map(|node| node.as_ref().get_ref())
+  }
+}
+
+pub struct SimulationExecutor<N, R> {
+  clock: SimClock,
+  rng: R,
+  poll_order: PollOrder,
+  ready: Arc<ReadyTasks>,
   network: Pin<Box<N>>,
-  delayed_join:
-    FuturesUnordered<Pin<Box<dyn Future<Output = SimulatableNodeFuture>>>>,
-  pub nodes: Vec<SimulatableNodeFuture>,
+  network_waker: Waker,
+  scheduled: FuturesUnordered<LocalBoxFuture<'static, Action>>,
+  scheduled_waker: Waker,
+  // Nodes keep their slot after leaving the simulation, the index doubles as
+  // the task id of the node.
+  nodes: Vec<Option<SimulatableNodeFuture>>,
+  node_wakers: Vec<Waker>,
+  restart_factories: HashMap<PeerId, NodeFactory>,
+  record_events: bool,
+  events: Vec<SimulationEvent>,
+  subscribers: Vec<EventSubscriber>,
 }
 
-impl<N: Future<Output = ()>> SimulationExecutor<N> {
-  pub fn new(network: Pin<Box<N>>) -> Self {
+impl<N: Future<Output = ()>, R: Rng> SimulationExecutor<N, R> {
+  /// Creates an executor driving `network`. The `clock` should be the same
+  /// clock that was handed to the network and the nodes, the executor is the
+  /// only one moving it forward. The `rng` determines the order in which the
+  /// nodes are polled.
+  pub fn new(network: Pin<Box<N>>, clock: SimClock, rng: R) -> Self {
+    let ready: Arc<ReadyTasks> = Default::default();
+    let network_waker = Self::task_waker(&ready, Task::Network);
+    let scheduled_waker = Self::task_waker(&ready, Task::Scheduled);
+
+    // give the network a chance to register its waker
+    ready.schedule(Task::Network);
+
     SimulationExecutor {
+      clock,
+      rng,
+      poll_order: Default::default(),
+      ready,
       network,
-      delayed_join: Default::default(),
+      network_waker,
+      scheduled: Default::default(),
+      scheduled_waker,
       nodes: Vec::new(),
+      node_wakers: Vec::new(),
+      restart_factories: HashMap::new(),
+      record_events: true,
+      events: Vec::new(),
+      subscribers: Vec::new(),
     }
   }
 
+  fn task_waker(ready: &Arc<ReadyTasks>, task: Task) -> Waker {
+    waker(Arc::new(TaskWaker {
+      task,
+      ready: Arc::clone(ready),
+    }))
+  }
+
+  pub fn with_poll_order(mut self, poll_order: PollOrder) -> Self {
+    self.poll_order = poll_order;
+    self
+  }
+
+  /// Enables or disables the event log. Subscribers are notified either way,
+  /// long running simulations that only need the subscribers can turn the log
+  /// off to bound memory usage.
+  pub fn with_event_log(mut self, record_events: bool) -> Self {
+    self.record_events = record_events;
+    self
+  }
+
+  /// Registers a callback that is invoked for every event emitted by a node.
+  pub fn subscribe<F>(&mut self, subscriber: F)
+  where
+    F: FnMut(&SimulationEvent) + 'static,
+  {
+    self.subscribers.push(Box::new(subscriber));
+  }
+
+  /// The events recorded since the start of the simulation or since the last
+  /// call to `drain_events`.
+  pub fn events(&self) -> &[SimulationEvent] {
+    &self.events
+  }
+
+  /// Takes the recorded events out of the log.
+  pub fn drain_events(&mut self) -> Vec<SimulationEvent> {
+    std::mem::take(&mut self.events)
+  }
+
+  pub fn network(&self) -> &N {
+    &self.network
+  }
+
+  pub fn clock(&self) -> &SimClock {
+    &self.clock
+  }
+
+  /// The current simulated time.
+  pub fn now(&self) -> Duration {
+    self.clock.now()
+  }
+
+  /// Returns true when no task has been woken, meaning nothing will happen
+  /// until the clock reaches the next deadline.
+  pub fn is_idle(&self) -> bool {
+    self.ready.is_empty()
+  }
+
+  /// The deadline the clock will jump to once the executor is idle.
+  pub fn next_deadline(&self) -> Option<Duration> {
+    self.clock.next_deadline()
+  }
+
+  /// The nodes that have joined and not yet left the simulation.
+  pub fn nodes(&self) -> impl Iterator<Item = &dyn SimulatableNode> {
+    self
+      .nodes
+      .iter()
+      .flatten()
+      .map(|node| node.as_ref().get_ref())
+  }
+
   pub fn add_node(&mut self, delay: Duration, node: SimulatableNodeFuture) {
+    self.schedule(delay, Action::Join(node));
+  }
+
+  /// Gracefully stops the node, it says goodbye to its peers and leaves the
+  /// simulation once it is disconnected from all of them. Returns false when
+  /// the node is not part of the simulation.
+  pub fn stop_node(&mut self, peer_id: &PeerId) -> bool {
+    match self.find_node(peer_id) {
+      Some(idx) => {
+        self.node(idx).shutdown_handle().shutdown();
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Removes the node from the simulation right away, without notifying its
+  /// peers, as if it crashed. Returns false when the node is not part of the
+  /// simulation.
+  pub fn kill_node(&mut self, peer_id: &PeerId) -> bool {
+    match self.find_node(peer_id) {
+      Some(idx) => {
+        tracing::debug!("Killing node {}", peer_id);
+        self.nodes[idx] = None;
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Gracefully stops the node once the simulated clock reaches `at`.
+  pub fn stop_node_at(&mut self, at: Duration, peer_id: PeerId) {
+    self.schedule(at.saturating_sub(self.now()), Action::Stop(peer_id));
+  }
+
+  /// Kills the node once the simulated clock reaches `at`.
+  pub fn kill_node_at(&mut self, at: Duration, peer_id: PeerId) {
+    self.schedule(at.saturating_sub(self.now()), Action::Kill(peer_id));
+  }
+
+  /// Registers how to rebuild the node with `peer_id` when it is restarted.
+  /// The factory should build the node with the same identity and the same
+  /// persistent storage.
+  pub fn set_restart_factory<F>(&mut self, peer_id: PeerId, factory: F)
+  where
+    F: FnMut() -> SimulatableNodeFuture + 'static,
+  {
+    self.restart_factories.insert(peer_id, Box::new(factory));
+  }
+
+  /// Rebuilds the node with `peer_id` from its restart factory and adds it to
+  /// the simulation. A node that is still running is killed first. Returns
+  /// false when no restart factory is registered for the node.
+  pub fn restart_node(&mut self, peer_id: &PeerId) -> bool {
+    if !self.restart_factories.contains_key(peer_id) {
+      return false;
+    }
+
+    // the old incarnation has to be gone before the new one registers itself
+    // with the network
+    self.kill_node(peer_id);
+
+    tracing::debug!("Restarting node {}", peer_id);
+    let node = (self.restart_factories.get_mut(peer_id).unwrap())();
+    self.run_action(Action::Join(node));
+    true
+  }
+
+  /// Restarts the node once the simulated clock reaches `at`.
+  pub fn restart_node_at(&mut self, at: Duration, peer_id: PeerId) {
+    self.schedule(at.saturating_sub(self.now()), Action::Restart(peer_id));
+  }
+
+  fn schedule(&mut self, delay: Duration, action: Action) {
     self
-      .delayed_join
-      .push(Delay::new(delay).map(move |_| node).boxed_local());
+      .scheduled
+      .push(self.clock.delay(delay).map(move |_| action).boxed_local());
+    self.ready.schedule(Task::Scheduled);
   }
 
-  pub fn run_tick(&mut self) {
-    let waker = futures::task::noop_waker();
-    let mut cx = Context::from_waker(&waker);
+  fn run_action(&mut self, action: Action) {
+    match action {
+      Action::Join(node) => {
+        let task = Task::Node(self.nodes.len());
+        self.nodes.push(Some(node));
+        self.node_wakers.push(Self::task_waker(&self.ready, task));
+        self.ready.schedule(task);
+      }
+      Action::Stop(peer_id) => {
+        self.stop_node(&peer_id);
+      }
+      Action::Kill(peer_id) => {
+        self.kill_node(&peer_id);
+      }
+      Action::Restart(peer_id) => {
+        self.restart_node(&peer_id);
+      }
+    }
+  }
+
+  fn find_node(&self, peer_id: &PeerId) -> Option<usize> {
+    self.nodes.iter().position(|node| {
+      node.as_ref().is_some_and(|node| node.identity() == peer_id)
+    })
+  }
 
-    // Attempt to progess the network
-    if self.network.as_mut().poll(&mut cx).is_ready() {
-      // The network has completed its operation
-      // return;
+  fn node(&self, idx: usize) -> &dyn SimulatableNode {
+    self.nodes[idx].as_ref().unwrap().as_ref().get_ref()
+  }
+
+  /// Polls every task that has been woken since the previous tick. When no
+  /// task is runnable the simulated clock jumps to the next pending deadline
+  /// instead, waking the timers that expire.
+  pub fn run_tick(&mut self) -> TickOutcome {
+    let mut tasks: Vec<Task> = self.ready.take().into_iter().collect();
+    if tasks.is_empty() {
+      return match self.clock.advance() {
+        Some(now) => TickOutcome::Advanced(now),
+        None => TickOutcome::Idle,
+      };
     }
 
-    // Attempt to progress any delayed nodes
-    if let std::task::Poll::Ready(Some(node)) =
-      self.delayed_join.poll_next_unpin(&mut cx)
+    // The tasks are sorted with the network and the scheduled actions first,
+    // only the nodes following them are permuted.
+    if self.poll_order == PollOrder::Shuffled {
+      let first_node = tasks
+        .iter()
+        .position(|task| matches!(task, Task::Node(_)))
+        .unwrap_or(tasks.len());
+      tasks[first_node..].shuffle(&mut self.rng);
+    }
+
+    for task in &tasks {
+      match *task {
+        Task::Network => {
+          let mut cx = Context::from_waker(&self.network_waker);
+          // The network never completes, it only forwards its work to the
+          // clients.
+          let _ = self.network.as_mut().poll(&mut cx);
+        }
+        Task::Scheduled => {
+          let waker = self.scheduled_waker.clone();
+          let mut cx = Context::from_waker(&waker);
+          while let Poll::Ready(Some(action)) =
+            self.scheduled.poll_next_unpin(&mut cx)
+          {
+            self.run_action(action);
+          }
+        }
+        Task::Node(idx) => {
+          // the node might have left the simulation after it was woken
+          let Some(node) = self.nodes[idx].as_mut() else {
+            continue;
+          };
+
+          let mut cx = Context::from_waker(&self.node_wakers[idx]);
+          if let Poll::Ready(event) = node.as_mut().poll(&mut cx) {
+            // a node returning an event might have more work queued up
+            self.ready.schedule(Task::Node(idx));
+            self.record(idx, event);
+          }
+        }
+      }
+    }
+
+    TickOutcome::Polled(tasks.len())
+  }
+
+  fn record(&mut self, idx: usize, event: NodeEvent) {
+    if event == NodeEvent::Noop {
+      return;
+    }
+
+    let event = SimulationEvent {
+      at: self.now(),
+      peer_id: *self.node(idx).identity(),
+      event,
+    };
+
+    // a stopped node has nothing left to do, it leaves the simulation
+    if event.event
+      == (NodeEvent::StateChanged {
+        new_state: NodeState::Stopped,
+      })
     {
-      self.nodes.push(node);
+      tracing::debug!("Node {} stopped", event.peer_id);
+      self.nodes[idx] = None;
+    }
+
+    for subscriber in &mut self.subscribers {
+      subscriber(&event);
+    }
+
+    if self.record_events {
+      self.events.push(event);
+    }
+  }
+
+  pub fn view(&self) -> SimulationView<'_> {
+    SimulationView {
+      now: self.now(),
+      nodes: &self.nodes,
+    }
+  }
+
+  /// Runs the simulation for `duration` of simulated time. The clock ends
+  /// exactly `duration` after the current time unless the simulation becomes
+  /// idle before that.
+  pub fn run_for(&mut self, duration: Duration) -> RunReport {
+    self.run_until(RunBudget::duration(duration), |_| false)
+  }
+
+  /// Runs the simulation until `predicate` holds or the `budget` runs out.
+  /// The predicate is checked before every tick.
+  pub fn run_until<F>(
+    &mut self,
+    budget: RunBudget,
+    mut predicate: F,
+  ) -> RunReport
+  where
+    F: FnMut(&SimulationView<'_>) -> bool,
+  {
+    let deadline = budget.max_duration.map(|duration| self.now() + duration);
+    let mut ticks = 0;
+
+    let outcome = loop {
+      if predicate(&self.view()) {
+        break RunOutcome::Satisfied;
+      }
+
+      if budget.max_ticks.is_some_and(|max_ticks| ticks >= max_ticks) {
+        break RunOutcome::BudgetExhausted;
+      }
+
+      // Never jump past the deadline, stop at it instead.
+      if let Some(deadline) = deadline {
+        let next_deadline_passed = self
+          .next_deadline()
+          .is_some_and(|next_deadline| next_deadline > deadline);
+        if self.is_idle() && next_deadline_passed {
+          self.clock.advance_to(deadline);
+          break RunOutcome::BudgetExhausted;
+        }
+      }
+
+      ticks += 1;
+      if self.run_tick() == TickOutcome::Idle {
+        break RunOutcome::Idle;
+      }
+    };
+
+    RunReport {
+      outcome,
+      ticks,
+      now: self.now(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::{
+      clock::sim::SimDelay,
+      node::ShutdownHandle,
+      node_events::{NodeEvent, NodeState},
+      types::PeerId,
+    },
+    futures::future::{pending, Pending},
+    rand::{rngs::StdRng, SeedableRng},
+    std::{cell::RefCell, rc::Rc},
+  };
+
+  type Polls = Rc<RefCell<Vec<usize>>>;
+
+  /// A node that wakes up every `period` and records each time it is polled.
+  /// It stops on the first poll after a shutdown was requested.
+  struct Ticker {
+    id: usize,
+    peer_id: PeerId,
+    clock: SimClock,
+    period: Duration,
+    delay: SimDelay,
+    polls: Polls,
+    shutdown: ShutdownHandle,
+  }
+
+  impl Future for Ticker {
+    type Output = NodeEvent;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<NodeEvent> {
+      let this = self.get_mut();
+      this.polls.borrow_mut().push(this.id);
+      if this.shutdown.is_requested() {
+        return Poll::Ready(NodeEvent::StateChanged {
+          new_state: NodeState::Stopped,
+        });
+      }
+      match this.delay.poll_unpin(cx) {
+        Poll::Ready(()) => {
+          this.delay = this.clock.delay(this.period);
+          Poll::Ready(NodeEvent::Noop)
+        }
+        Poll::Pending => Poll::Pending,
+      }
+    }
+  }
+
+  impl SimulatableNode for Ticker {
+    fn connections(&self) -> Vec<PeerId> {
+      Vec::new()
+    }
+
+    fn identity(&self) -> &PeerId {
+      &self.peer_id
     }
 
-    // Randomize the polling of the nodes and exit on the first exit event
+    fn state(&self) -> NodeState {
+      NodeState::Running
+    }
+
+    fn shutdown_handle(&self) -> ShutdownHandle {
+      self.shutdown.clone()
+    }
+  }
+
+  fn ticker(
+    clock: &SimClock,
+    id: usize,
+    period: Duration,
+    polls: &Polls,
+  ) -> SimulatableNodeFuture {
+    Box::pin(Ticker {
+      id,
+      peer_id: PeerId::unique(&mut StdRng::seed_from_u64(id as u64)),
+      clock: clock.clone(),
+      period,
+      delay: clock.delay(period),
+      polls: Rc::clone(polls),
+      shutdown: Default::default(),
+    })
+  }
+
+  fn executor(seed: u64) -> SimulationExecutor<Pending<()>, StdRng> {
+    SimulationExecutor::new(
+      Box::pin(pending()),
+      SimClock::new(),
+      StdRng::seed_from_u64(seed),
+    )
+  }
+
+  /// Runs ticks until nothing is runnable, returns what happened then.
+  fn poll_runnable<N: Future<Output = ()>>(
+    simulation: &mut SimulationExecutor<N, StdRng>,
+  ) -> TickOutcome {
+    loop {
+      match simulation.run_tick() {
+        TickOutcome::Polled(_) => continue,
+        outcome => return outcome,
+      }
+    }
+  }
+
+  #[test]
+  fn idle_executor_jumps_to_the_next_deadline() {
+    let mut simulation = executor(0);
+    assert_eq!(simulation.run_tick(), TickOutcome::Polled(1));
+    assert!(simulation.is_idle());
+    assert_eq!(simulation.run_tick(), TickOutcome::Idle);
+
+    let polls = Polls::default();
+    let node = ticker(simulation.clock(), 0, Duration::from_secs(3), &polls);
+    simulation.add_node(Duration::from_secs(2), node);
+    assert_eq!(simulation.next_deadline(), Some(Duration::from_secs(2)));
+    // polling the delayed join does not move the clock, the node is only
+    // polled once it joined
+    assert_eq!(
+      poll_runnable(&mut simulation),
+      TickOutcome::Advanced(Duration::from_secs(2))
+    );
+    assert!(polls.borrow().is_empty());
+    assert_eq!(
+      poll_runnable(&mut simulation),
+      TickOutcome::Advanced(Duration::from_secs(3))
+    );
+    assert_eq!(*polls.borrow(), vec![0]);
+  }
+
+  #[test]
+  fn only_woken_nodes_are_polled() {
+    let mut simulation = executor(0);
+    let polls = Polls::default();
+    for (id, period) in [(0, 1), (1, 3)] {
+      let period = Duration::from_secs(period);
+      let node = ticker(simulation.clock(), id, period, &polls);
+      simulation.add_node(Duration::ZERO, node);
+    }
+
+    simulation.run_for(Duration::from_secs(6));
+    let count = |id| polls.borrow().iter().filter(|&&i| i == id).count();
+    // the first poll, then two polls for every expired timer: one returning
+    // the event and the one after it finding nothing left to do
+    assert_eq!(count(0), 1 + 2 * 6);
+    assert_eq!(count(1), 1 + 2 * 2);
+  }
+
+  /// The order in which five nodes sharing their wakeups are polled.
+  fn poll_log(seed: u64, poll_order: PollOrder) -> Vec<usize> {
+    let mut simulation = executor(seed).with_poll_order(poll_order);
+    let polls = Polls::default();
+    for id in 0..5 {
+      let node = ticker(simulation.clock(), id, Duration::from_secs(1), &polls);
+      simulation.add_node(Duration::ZERO, node);
+    }
+    simulation.run_for(Duration::from_secs(5));
+    polls.take()
+  }
 
-    // Attempt to progress each node's state by one tick.
-    for node in &mut self.nodes {
-      let _ = node.as_mut().poll(&mut cx);
+  #[test]
+  fn sequential_order_polls_nodes_as_they_joined() {
+    let log = poll_log(0, PollOrder::Sequential);
+    assert!(!log.is_empty());
+    for tick in log.chunks(5) {
+      assert_eq!(tick, [0, 1, 2, 3, 4]);
     }
   }
 
-  // Here you would handle any logic to check if the simulation should continue
-  // or if specific nodes have completed their operations.
+  #[test]
+  fn shuffled_order_is_reproducible_for_a_seed() {
+    let log = poll_log(1, PollOrder::Shuffled);
+    assert_eq!(log, poll_log(1, PollOrder::Shuffled));
+    assert_ne!(log, poll_log(2, PollOrder::Shuffled));
+    assert_ne!(log, poll_log(1, PollOrder::Sequential));
+
+    // every node is still polled as often
+    let mut sorted = log.clone();
+    sorted.sort();
+    let mut sequential = poll_log(1, PollOrder::Sequential);
+    sequential.sort();
+    assert_eq!(sorted, sequential);
+  }
+
+  #[test]
+  fn run_for_stops_at_the_end_of_the_duration() {
+    let mut simulation = executor(0);
+    let polls = Polls::default();
+    let node = ticker(simulation.clock(), 0, Duration::from_secs(3), &polls);
+    simulation.add_node(Duration::ZERO, node);
+
+    // the next timer lies beyond the duration, the clock does not skip to it
+    let report = simulation.run_for(Duration::from_secs(4));
+    assert_eq!(report.outcome, RunOutcome::BudgetExhausted);
+    assert_eq!(report.now, Duration::from_secs(4));
+    assert_eq!(simulation.next_deadline(), Some(Duration::from_secs(6)));
+  }
+
+  #[test]
+  fn run_until_stops_once_the_predicate_holds() {
+    let mut simulation = executor(0);
+    let polls = Polls::default();
+    let node = ticker(simulation.clock(), 0, Duration::from_secs(1), &polls);
+    simulation.add_node(Duration::ZERO, node);
+
+    let budget = RunBudget::duration(Duration::from_secs(10));
+    let report = simulation.run_until(budget, |view| {
+      view.now() >= Duration::from_secs(3) && view.nodes().count() == 1
+    });
+    assert!(report.is_satisfied());
+    assert_eq!(report.now, Duration::from_secs(3));
+
+    // a predicate holding from the start runs no tick at all
+    let report = simulation.run_until(budget, |_| true);
+    assert!(report.is_satisfied());
+    assert_eq!(report.ticks, 0);
+  }
+
+  #[test]
+  fn run_until_respects_the_tick_budget() {
+    let mut simulation = executor(0);
+    let polls = Polls::default();
+    let node = ticker(simulation.clock(), 0, Duration::from_secs(1), &polls);
+    simulation.add_node(Duration::ZERO, node);
+
+    let budget = RunBudget::ticks(3).with_duration(Duration::from_secs(60));
+    let report = simulation.run_until(budget, |_| false);
+    assert_eq!(report.outcome, RunOutcome::BudgetExhausted);
+    assert_eq!(report.ticks, 3);
+  }
+
+  #[test]
+  fn run_until_returns_when_nothing_is_left_to_do() {
+    let mut simulation = executor(0);
+    let report = simulation.run_until(RunBudget::default(), |_| false);
+    assert_eq!(report.outcome, RunOutcome::Idle);
+    assert_eq!(report.now, Duration::ZERO);
+  }
+
+  #[test]
+  fn stopped_node_leaves_the_simulation() {
+    let mut simulation = executor(0);
+    let polls = Polls::default();
+    let node = ticker(simulation.clock(), 0, Duration::from_secs(1), &polls);
+    let peer_id = *node.identity();
+    simulation.add_node(Duration::ZERO, node);
+    simulation.run_for(Duration::from_secs(1));
+
+    simulation.stop_node_at(Duration::from_secs(3), peer_id);
+    simulation.run_for(Duration::from_secs(5));
+    assert_eq!(simulation.nodes().count(), 0);
+    assert!(!simulation.stop_node(&peer_id));
+    assert_eq!(simulation.events(), [SimulationEvent {
+      at: Duration::from_secs(3),
+      peer_id,
+      event: NodeEvent::StateChanged {
+        new_state: NodeState::Stopped,
+      },
+    }]);
+  }
+
+  #[test]
+  fn killed_node_is_never_polled_again() {
+    let mut simulation = executor(0);
+    let polls = Polls::default();
+    let node = ticker(simulation.clock(), 0, Duration::from_secs(1), &polls);
+    let peer_id = *node.identity();
+    simulation.add_node(Duration::ZERO, node);
+    simulation.run_for(Duration::from_secs(1));
+
+    simulation.kill_node_at(Duration::from_secs(2), peer_id);
+    simulation.run_for(Duration::from_secs(1));
+    let polled = polls.borrow().len();
+    simulation.run_for(Duration::from_secs(5));
+    assert_eq!(polls.borrow().len(), polled);
+    assert_eq!(simulation.nodes().count(), 0);
+    // killing records no event, the node just vanishes
+    assert!(simulation.events().is_empty());
+    assert!(!simulation.kill_node(&peer_id));
+  }
+
+  #[test]
+  fn restart_rebuilds_the_node_from_its_factory() {
+    let mut simulation = executor(0);
+    let polls = Polls::default();
+    let node = ticker(simulation.clock(), 0, Duration::from_secs(1), &polls);
+    let peer_id = *node.identity();
+    assert!(!simulation.restart_node(&peer_id));
+
+    simulation.add_node(Duration::ZERO, node);
+    let clock = simulation.clock().clone();
+    let restarted = Polls::default();
+    let factory_polls = Rc::clone(&restarted);
+    simulation.set_restart_factory(peer_id, move || {
+      ticker(&clock, 0, Duration::from_secs(1), &factory_polls)
+    });
+    simulation.run_for(Duration::from_secs(1));
+
+    // the running incarnation is replaced by a fresh one with the same
+    // identity
+    simulation.restart_node_at(Duration::from_secs(2), peer_id);
+    simulation.run_for(Duration::from_secs(1));
+    let polled = polls.borrow().len();
+    simulation.run_for(Duration::from_secs(2));
+    assert_eq!(polls.borrow().len(), polled);
+    assert!(!restarted.borrow().is_empty());
+    assert_eq!(
+      simulation
+        .nodes()
+        .map(|node| *node.identity())
+        .collect::<Vec<_>>(),
+      vec![peer_id]
+    );
+  }
 }
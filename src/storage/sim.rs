@@ -2,36 +2,60 @@ use {
   crate::storage::Storage,
   futures::Future,
   std::{
+    cell::RefCell,
     pin::Pin,
+    rc::Rc,
     task::{Context, Poll},
   },
 };
 
+/// The persistent medium behind a `SimStorage`. The disk outlives the node
+/// using it, so a node that is restarted after a crash finds the data it
+/// wrote before.
+#[derive(Clone, Default)]
+pub struct SimDisk(Rc<RefCell<String>>);
+
+impl SimDisk {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
 pub struct SimStorage<R> {
+  #[allow(dead_code)]
   rng: R,
+  disk: SimDisk,
 }
 
 impl<R> Future for SimStorage<R> {
   type Output = ();
 
   fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
-    eprintln!("Simulating storage...");
+    tracing::trace!("Simulating storage...");
     Poll::Ready(())
   }
 }
 
 impl<R> Storage for SimStorage<R> {
   fn read(&mut self) -> String {
-    String::new()
+    self.disk.0.borrow().clone()
   }
 
-  fn write(&mut self, date: String) {
-    println!("Writing message: {}", date);
+  fn write(&mut self, data: String) {
+    tracing::trace!("Writing message: {}", data);
+    *self.disk.0.borrow_mut() = data;
   }
 }
 
 impl<R> SimStorage<R> {
+  /// Builds a storage on a fresh disk.
   pub fn build(rng: R) -> Self {
-    SimStorage { rng }
+    Self::with_disk(rng, SimDisk::new())
+  }
+
+  /// Builds a storage on an existing disk, picking up whatever was written to
+  /// it before.
+  pub fn with_disk(rng: R, disk: SimDisk) -> Self {
+    SimStorage { rng, disk }
   }
 }
@@ -1,7 +1,7 @@
-use multiaddr::Multiaddr;
+use {crate::b58::Base58DecodeError, multiaddr::Multiaddr, thiserror::Error};
 
-// Re-export `Pubkey` from the `primitives` module for external use.
-pub use crate::primitives::Pubkey;
+// Re-export the key types from the `primitives` module for external use.
+pub use crate::primitives::{Keypair, Pubkey, Signature};
 
 // `PeerId` is a type alias for a public key that uniquely identifies a peer in
 // the network.
@@ -15,7 +15,157 @@ pub type PeerReputation = i32;
 // node in the network.
 pub type NodeAddress = (PeerId, Multiaddr);
 
-// `NodeIdentity` is a type alias for a public key used to identify a node
-// uniquely.
-pub type NodeIdentity = Pubkey;
+// `NodeIdentity` is the keypair of a node, its public key is the `PeerId` of
+// the node.
+pub type NodeIdentity = Keypair;
 
+#[derive(Debug, Error)]
+pub enum ParseNodeAddressError {
+  #[error("expected <peer id>@<multiaddr>")]
+  MissingSeparator,
+  #[error("invalid peer id: {0}")]
+  PeerId(#[from] Base58DecodeError),
+  #[error("invalid address: {0}")]
+  Address(#[from] multiaddr::Error),
+}
+
+// Parses a `NodeAddress` written as `<base58 peer id>@<multiaddr>`, the form
+// used for bootnodes on the command line and in config files.
+pub fn parse_node_address(
+  s: &str,
+) -> Result<NodeAddress, ParseNodeAddressError> {
+  let (peer_id, address) = s
+    .split_once('@')
+    .ok_or(ParseNodeAddressError::MissingSeparator)?;
+  Ok((peer_id.parse()?, address.parse()?))
+}
+
+// Formats a `NodeAddress` the way `parse_node_address` reads it.
+pub fn format_node_address((peer_id, address): &NodeAddress) -> String {
+  format!("{}@{}", peer_id, address)
+}
+
+// Serializes a `NodeAddress` in the `<peer id>@<multiaddr>` form, use it with
+// `#[serde(with = "c2n::types::node_address_serde")]`.
+#[cfg(feature = "serde")]
+pub mod node_address_serde {
+  use {
+    super::{format_node_address, parse_node_address, NodeAddress},
+    serde::{de, Deserialize, Deserializer, Serializer},
+    std::borrow::Cow,
+  };
+
+  pub fn serialize<S: Serializer>(
+    address: &NodeAddress,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_node_address(address))
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<NodeAddress, D::Error> {
+    let encoded = <Cow<str>>::deserialize(deserializer)?;
+    parse_node_address(&encoded).map_err(de::Error::custom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    rand::{rngs::StdRng, SeedableRng},
+  };
+
+  fn node_address() -> NodeAddress {
+    let keypair = Keypair::generate(&mut StdRng::seed_from_u64(1));
+    (
+      *keypair.public(),
+      "/ip4/127.0.0.1/tcp/4000".parse().unwrap(),
+    )
+  }
+
+  #[test]
+  fn node_address_round_trips() {
+    let address = node_address();
+    let formatted = format_node_address(&address);
+    assert_eq!(formatted, format!("{}@/ip4/127.0.0.1/tcp/4000", address.0));
+    assert_eq!(parse_node_address(&formatted).unwrap(), address);
+  }
+
+  #[test]
+  fn malformed_node_address_is_rejected() {
+    let (peer_id, _) = node_address();
+    assert!(matches!(
+      parse_node_address(&format!("{peer_id}/ip4/127.0.0.1/tcp/4000")),
+      Err(ParseNodeAddressError::MissingSeparator)
+    ));
+    assert!(matches!(
+      parse_node_address("0OIl@/ip4/127.0.0.1/tcp/4000"),
+      Err(ParseNodeAddressError::PeerId(
+        Base58DecodeError::InvalidEncoding(_)
+      ))
+    ));
+    assert!(matches!(
+      parse_node_address("abc@/ip4/127.0.0.1/tcp/4000"),
+      Err(ParseNodeAddressError::PeerId(
+        Base58DecodeError::InvalidLength { expected: 32, .. }
+      ))
+    ));
+    assert!(matches!(
+      parse_node_address(&format!("{peer_id}@/ip4/127.0.0.1/tcp/port")),
+      Err(ParseNodeAddressError::Address(_))
+    ));
+  }
+
+  #[test]
+  fn keys_parse_what_they_print() {
+    let keypair = Keypair::generate(&mut StdRng::seed_from_u64(1));
+    let peer_id = *keypair.public();
+    assert_eq!(peer_id.to_string().parse::<PeerId>(), Ok(peer_id));
+
+    let secret = Keypair::from_secret(keypair.to_secret());
+    assert_eq!(secret.public(), keypair.public());
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn keys_serialize_as_base58_strings() {
+    let keypair = Keypair::generate(&mut StdRng::seed_from_u64(1));
+    let peer_id = *keypair.public();
+
+    let json = serde_json::to_string(&peer_id).unwrap();
+    assert_eq!(json, format!("\"{peer_id}\""));
+    assert_eq!(serde_json::from_str::<PeerId>(&json).unwrap(), peer_id);
+
+    let json = serde_json::to_string(&keypair).unwrap();
+    let decoded: Keypair = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.public(), keypair.public());
+
+    assert!(serde_json::from_str::<PeerId>("\"0OIl\"").is_err());
+    assert!(serde_json::from_str::<PeerId>("\"abc\"").is_err());
+    assert!(serde_json::from_str::<PeerId>("7").is_err());
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn node_address_serializes_as_a_string() {
+    let address = node_address();
+    let mut json = Vec::new();
+    node_address_serde::serialize(
+      &address,
+      &mut serde_json::Serializer::new(&mut json),
+    )
+    .unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert_eq!(json, format!("\"{}\"", format_node_address(&address)));
+
+    let decode = |json: &str| {
+      node_address_serde::deserialize(&mut serde_json::Deserializer::from_str(
+        json,
+      ))
+    };
+    assert_eq!(decode(&json).unwrap(), address);
+    assert!(decode("\"/ip4/127.0.0.1/tcp/4000\"").is_err());
+  }
+}
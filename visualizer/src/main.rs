@@ -87,17 +87,14 @@ async fn main() {
     .build();
 
   let node_colors = [RED, GREEN, BLUE, YELLOW, MAGENTA, ORANGE, PURPLE];
+  let mut total_events = 0;
 
   loop {
     let screen_width = screen_width();
     let screen_height = screen_height();
 
-    let peer_ids: Vec<_> = simulation
-      .nodes
-      .iter()
-      .map(|n| n.identity())
-      .cloned()
-      .collect();
+    let peer_ids: Vec<_> =
+      simulation.nodes().map(|n| n.identity()).cloned().collect();
     let num_nodes = peer_ids.len();
 
     let node_positions =
@@ -113,18 +110,23 @@ async fn main() {
     }
 
     let connections: HashMap<_, _> = simulation
-      .nodes
-      .iter()
+      .nodes()
       .map(|node| (*node.identity(), node.connections()))
       .collect();
 
     draw_nodes(&nodes, &connections);
 
     let total_connections: usize = connections.values().map(|v| v.len()).sum();
-    let stats_text = format!("Total connections: {}", total_connections);
+    let stats_text = format!(
+      "Total connections: {} Events: {} Time: {:.1}s",
+      total_connections,
+      total_events,
+      simulation.now().as_secs_f32()
+    );
     draw_text(&stats_text, 10.0, screen_height - 20.0, 20.0, WHITE);
 
     simulation.run_tick();
+    total_events += simulation.drain_events().len();
 
     next_frame().await
   }